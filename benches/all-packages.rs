@@ -1,15 +1,59 @@
-use criterion::{criterion_group, criterion_main, Benchmark, Criterion, Throughput};
-use rnix::parse;
+use std::{fs, path::Path};
 
-fn all_packages(c: &mut Criterion) {
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use rnix::{parse, tokenizer::Tokenizer};
+
+/// Return the name and text of the input to benchmark. Without git-lfs,
+/// `all-packages.nix` is only a small pointer file, so the test data is used
+/// instead. The name goes in the benchmark IDs, so results on the two can't
+/// be mixed up.
+fn input() -> (&'static str, String) {
     let input = include_str!("all-packages.nix");
-    c.bench(
-        "all-packages",
-        Benchmark::new("all-packages", move |b| b.iter(|| parse(input)))
-            .throughput(Throughput::Bytes(input.len() as u64))
-            .sample_size(30),
-    );
+    if !input.starts_with("version https://git-lfs") {
+        return ("all-packages.nix", input.to_string());
+    }
+    fn collect(dir: &Path, out: &mut String) {
+        let mut entries: Vec<_> = fs::read_dir(dir).unwrap().map(|e| e.unwrap().path()).collect();
+        entries.sort();
+        for path in entries {
+            if path.is_dir() {
+                collect(&path, out);
+            } else if path.extension().is_some_and(|ext| ext == "nix") {
+                out.push_str(&fs::read_to_string(path).unwrap());
+                out.push('\n');
+            }
+        }
+    }
+    let mut out = String::new();
+    collect(&Path::new(env!("CARGO_MANIFEST_DIR")).join("test_data"), &mut out);
+    ("test_data", out)
+}
+
+fn all_packages(c: &mut Criterion) {
+    let (name, input) = input();
+    let input = input.as_str();
+    let mut group = c.benchmark_group("all-packages");
+    group.throughput(Throughput::Bytes(input.len() as u64)).sample_size(30);
+    group.bench_function(BenchmarkId::new("parse", name), move |b| b.iter(|| parse(input)));
+    group.finish();
+}
+
+fn tokenize(c: &mut Criterion) {
+    let (name, input) = input();
+    let input = input.as_str();
+    let mut group = c.benchmark_group("tokenize");
+    group.throughput(Throughput::Bytes(input.len() as u64)).sample_size(30);
+    group.bench_function(BenchmarkId::new("owned", name), |b| {
+        b.iter(|| Tokenizer::new(input).count())
+    });
+    group.bench_function(BenchmarkId::new("borrowed", name), |b| {
+        b.iter(|| Tokenizer::new(input).borrowed().count())
+    });
+    group.bench_function(BenchmarkId::new("ranged", name), |b| {
+        b.iter(|| Tokenizer::new(input).ranged().count())
+    });
+    group.finish();
 }
 
-criterion_group!(benches, all_packages);
+criterion_group!(benches, all_packages, tokenize);
 criterion_main!(benches);
@@ -0,0 +1,36 @@
+use std::{env, error::Error, fs};
+
+use rnix::doc::Docs;
+
+const USAGE: &str = "Usage: rnix-doc [--json] [--prefix <prefix>] <file>";
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let mut json = false;
+    let mut prefix = String::new();
+    let mut file = None;
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--json" => json = true,
+            "--prefix" => prefix = args.next().ok_or(USAGE)?,
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return Ok(());
+            }
+            _ if file.is_none() && !arg.starts_with('-') => file = Some(arg),
+            _ => return Err(USAGE.into()),
+        }
+    }
+    let file = file.ok_or(USAGE)?;
+
+    let content = fs::read_to_string(&file)?;
+    let ast = rnix::parse(&content).as_result()?;
+    let docs = Docs::extract(&ast.root());
+    if json {
+        println!("{}", docs.to_json());
+    } else {
+        print!("{}", docs.to_markdown(&prefix));
+    }
+    Ok(())
+}
@@ -0,0 +1,326 @@
+//! Find the comments that belong to a node.
+//!
+//! Every comment belongs to exactly one node, by these rules:
+//! - A comment after a token on the same line trails the outermost node that
+//!   ends with that token, like `a = 1; # about a`. A `,` in between is
+//!   skipped, like in `{ a, # about a`.
+//! - A comment after another comment on the same line belongs where that
+//!   comment does, like both in `/* a */ /* b */ x` lead `x`.
+//! - Otherwise, a comment on its own line leads the outermost node that starts
+//!   with the next token, if only comments and at most one line break are in
+//!   between, like `# about a` on the line above `a = 1;`.
+//! - Anything else dangles in the node it's inside, such as a comment in an
+//!   empty set or one followed by an empty line.
+
+use crate::{value::remove_common_indent, SyntaxKind::*, SyntaxNode, SyntaxToken};
+
+/// What kind of comment a token is
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum CommentKind {
+    /// `# ...`
+    Line,
+    /// `/* ... */`
+    Block,
+    /// `/** ... */`, documentation for what follows
+    Doc,
+}
+
+/// How a comment is attached to the node it belongs to
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Attachment {
+    Leading,
+    Trailing,
+    Dangling,
+}
+
+/// A `TOKEN_COMMENT`
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Comment(SyntaxToken);
+impl Comment {
+    /// Cast a token into a comment, if it is one
+    pub fn cast(token: SyntaxToken) -> Option<Self> {
+        if token.kind() == TOKEN_COMMENT {
+            Some(Self(token))
+        } else {
+            None
+        }
+    }
+    /// Return the underlying token
+    pub fn token(&self) -> &SyntaxToken {
+        &self.0
+    }
+    /// Return what kind of comment this is
+    pub fn kind(&self) -> CommentKind {
+        let text = self.0.text();
+        if text.starts_with('#') {
+            CommentKind::Line
+        } else if text.starts_with("/**") && text.as_str() != "/**/" {
+            CommentKind::Doc
+        } else {
+            CommentKind::Block
+        }
+    }
+    /// Returns true if this is a `/** */` doc comment
+    pub fn is_doc(&self) -> bool {
+        self.kind() == CommentKind::Doc
+    }
+    /// Return the text of the comment without `#`, `/*` and `*/`, and with
+    /// the indention common to all lines removed
+    pub fn text(&self) -> String {
+        let text = self.0.text().as_str();
+        let inner = match self.kind() {
+            CommentKind::Line => &text[1..],
+            CommentKind::Block => &text[2..],
+            CommentKind::Doc => &text[3..],
+        };
+        let inner = match self.kind() {
+            CommentKind::Line => inner,
+            _ => inner.strip_suffix("*/").unwrap_or(inner),
+        };
+        remove_common_indent(inner).trim().to_string()
+    }
+    /// Return the node this comment belongs to and how it's attached to it
+    pub fn owner(&self) -> (Attachment, SyntaxNode) {
+        let is_space = |token: &SyntaxToken| token.kind() == TOKEN_WHITESPACE;
+        let newlines = |token: &SyntaxToken| token.text().matches('\n').count();
+
+        let mut prev = self.0.prev_token();
+        while let Some(token) =
+            prev.as_ref().filter(|token| is_space(token) && newlines(token) == 0)
+        {
+            prev = token.prev_token();
+        }
+        match prev {
+            // After a token on the same line
+            Some(mut prev) if !prev.kind().is_trivia() => {
+                if prev.kind() == TOKEN_COMMA {
+                    while let Some(token) = prev.prev_token() {
+                        prev = token;
+                        if !prev.kind().is_trivia() {
+                            break;
+                        }
+                    }
+                }
+                let end = prev.text_range().end();
+                return match outermost(&prev, |node| node.text_range().end() == end) {
+                    Some(node) => (Attachment::Trailing, node),
+                    None => (Attachment::Dangling, self.0.parent()),
+                };
+            }
+            // After another comment on the same line
+            Some(prev) if !is_space(&prev) => {
+                return match Comment(prev).owner() {
+                    (Attachment::Dangling, _) => (Attachment::Dangling, self.0.parent()),
+                    owner => owner,
+                };
+            }
+            // First thing on its line
+            _ => (),
+        }
+
+        let mut next = self.0.next_token();
+        while let Some(token) = next.as_ref().filter(|token| {
+            token.kind() == TOKEN_COMMENT || is_space(token) && newlines(token) <= 1
+        }) {
+            next = token.next_token();
+        }
+        if let Some(next) = next.filter(|next| !next.kind().is_trivia()) {
+            let start = next.text_range().start();
+            if let Some(node) = outermost(&next, |node| node.text_range().start() == start) {
+                return (Attachment::Leading, node);
+            }
+        }
+        (Attachment::Dangling, self.0.parent())
+    }
+}
+
+/// Return the outermost node around `token` for which `f` holds, as long as
+/// it holds for every node in between
+fn outermost<F>(token: &SyntaxToken, f: F) -> Option<SyntaxNode>
+where
+    F: Fn(&SyntaxNode) -> bool,
+{
+    token.parent().ancestors().take_while(|node| f(node)).last()
+}
+
+/// The comments belonging to a node, see the module documentation
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Comments {
+    /// Comments on the lines directly above the node
+    pub leading: Vec<Comment>,
+    /// Comments after the node on the same line
+    pub trailing: Vec<Comment>,
+    /// Comments inside the node that belong to none of its children
+    pub dangling: Vec<Comment>,
+}
+impl Comments {
+    /// Return the doc comment, which is the last leading comment if that is
+    /// a `/** */` block
+    pub fn doc(&self) -> Option<&Comment> {
+        self.leading.last().filter(|comment| comment.is_doc())
+    }
+}
+
+/// Return the comments belonging to `node`
+pub(crate) fn comments(node: &SyntaxNode) -> Comments {
+    let owned_by = |token: &SyntaxToken, attachment: Attachment| {
+        Comment::cast(token.clone()).filter(|comment| comment.owner() == (attachment, node.clone()))
+    };
+    let mut comments = Comments::default();
+
+    let mut prev = node.first_token().and_then(|token| token.prev_token());
+    while let Some(token) = prev.filter(|token| token.kind().is_trivia()) {
+        comments.leading.extend(owned_by(&token, Attachment::Leading));
+        prev = token.prev_token();
+    }
+    comments.leading.reverse();
+
+    let mut next = node.last_token().and_then(|token| token.next_token());
+    while let Some(token) =
+        next.filter(|token| token.kind().is_trivia() || token.kind() == TOKEN_COMMA)
+    {
+        comments.trailing.extend(owned_by(&token, Attachment::Trailing));
+        if token.text().contains('\n') {
+            break;
+        }
+        next = token.next_token();
+    }
+
+    comments.dangling = node
+        .children_with_tokens()
+        .filter_map(|child| child.into_token())
+        .filter_map(|token| owned_by(&token, Attachment::Dangling))
+        .collect();
+    comments
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::*;
+
+    fn texts(comments: &[Comment]) -> Vec<String> {
+        comments.iter().map(Comment::text).collect()
+    }
+
+    #[test]
+    fn kinds() {
+        let ast = crate::parse("/** doc */ /* block */ # line\n/**/ 1");
+        let kinds: Vec<_> = ast
+            .node()
+            .descendants_with_tokens()
+            .filter_map(|element| element.into_token().and_then(Comment::cast))
+            .map(|comment| comment.kind())
+            .collect();
+        use CommentKind::*;
+        assert_eq!(kinds, [Doc, Block, Line, Block]);
+    }
+    #[test]
+    fn text() {
+        let ast = crate::parse("/**\n    Adds two numbers.\n\n      Indented\n  */\n# line \n1");
+        let comments: Vec<_> = ast
+            .node()
+            .children_with_tokens()
+            .filter_map(|element| element.into_token().and_then(Comment::cast))
+            .collect();
+        assert_eq!(texts(&comments), ["Adds two numbers.\n\n  Indented", "line"]);
+    }
+    #[test]
+    fn attachment() {
+        let code = "\
+# file
+
+{
+  # about a
+  # more about a
+  a = 1; # after a
+
+  # about b
+  b =
+    /** doc for the lambda */
+    x: x;
+  c = [ /* empty */ ];
+  d = { # opening
+  };
+  e = { x, # about x
+    y }: x;
+  /* one */ /* two */ f = 1; /* three */ /* four */
+  # end of set
+}
+";
+        let ast = crate::parse(code);
+        let set = ast.root().inner().and_then(Expr::downcast::<AttrSet>).unwrap();
+        let root = ast.root().comments();
+        assert_eq!(texts(&root.dangling), ["file"]);
+        let comments = set.comments();
+        assert!(comments.leading.is_empty() && comments.trailing.is_empty());
+        assert_eq!(texts(&comments.dangling), ["end of set"]);
+
+        let mut entries = set.entries();
+        let a = entries.next().unwrap().comments();
+        assert_eq!(texts(&a.leading), ["about a", "more about a"]);
+        assert_eq!(texts(&a.trailing), ["after a"]);
+        assert!(a.doc().is_none());
+
+        let b = entries.next().unwrap();
+        assert_eq!(texts(&b.comments().leading), ["about b"]);
+        let lambda = b.value().unwrap().comments();
+        assert_eq!(lambda.doc().map(Comment::text), Some("doc for the lambda".into()));
+
+        let c = entries.next().unwrap();
+        let list = c.value().unwrap().comments();
+        assert_eq!(texts(&list.dangling), ["empty"]);
+        assert!(c.comments() == Comments::default());
+
+        let d = entries.next().unwrap().value().unwrap().comments();
+        assert_eq!(texts(&d.dangling), ["opening"]);
+
+        let e = entries.next().and_then(|e| e.value()).and_then(Expr::downcast::<Lambda>);
+        let pattern = e.and_then(|e| e.arg()).and_then(Param::downcast::<Pattern>).unwrap();
+        let x = pattern.entries().next().unwrap().comments();
+        assert_eq!(texts(&x.trailing), ["about x"]);
+
+        let f = entries.next().unwrap().comments();
+        assert_eq!(texts(&f.leading), ["one", "two"]);
+        assert_eq!(texts(&f.trailing), ["three", "four"]);
+
+        // Each comment belongs to exactly one node
+        let mut owned = 0;
+        for node in ast.node().descendants() {
+            let comments = super::comments(&node);
+            owned += comments.leading.len() + comments.dangling.len();
+            owned += comments.trailing.len();
+        }
+        let all = ast.node().descendants_with_tokens().filter(|e| e.kind() == TOKEN_COMMENT);
+        assert_eq!(owned, all.count());
+    }
+    #[test]
+    fn corpus() {
+        use std::fs;
+
+        for dir in &["test_data/general", "test_data/parser"] {
+            for entry in fs::read_dir(dir).unwrap() {
+                let path = entry.unwrap().path();
+                if path.extension().map_or(true, |ext| ext != "nix") {
+                    continue;
+                }
+                let code = fs::read_to_string(&path).unwrap();
+                let ast = crate::parse(&code);
+                for comment in ast.node().descendants_with_tokens() {
+                    let comment = match comment.into_token().and_then(Comment::cast) {
+                        Some(comment) => comment,
+                        None => continue,
+                    };
+                    let (attachment, owner) = comment.owner();
+                    let comments = super::comments(&owner);
+                    let found = match attachment {
+                        Attachment::Leading => comments.leading.contains(&comment),
+                        Attachment::Trailing => comments.trailing.contains(&comment),
+                        Attachment::Dangling => comments.dangling.contains(&comment),
+                    };
+                    assert!(found, "{:?} in {:?}", comment, path);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,195 @@
+//! Tell input that is only unfinished apart from input with errors, for
+//! reading code line by line in a REPL
+
+use std::fmt;
+
+use crate::{
+    parser::{ParseError, AST},
+    tokenizer::LexError,
+    NodeOrToken,
+    SyntaxKind::{self, *},
+    SyntaxNode, SyntaxToken, TextRange,
+};
+
+/// Something that was opened and needs to be closed again
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Delimiter {
+    Curly,
+    Square,
+    Paren,
+    /// `${`, both in strings and in attribute names
+    Interpol,
+    String,
+    MultilineString,
+    /// `let` waiting for its `in`
+    Let,
+    Comment,
+}
+impl Delimiter {
+    /// Return the text that opens this delimiter
+    pub fn open(self) -> &'static str {
+        match self {
+            Delimiter::Curly => "{",
+            Delimiter::Square => "[",
+            Delimiter::Paren => "(",
+            Delimiter::Interpol => "${",
+            Delimiter::String => "\"",
+            Delimiter::MultilineString => "''",
+            Delimiter::Let => "let",
+            Delimiter::Comment => "/*",
+        }
+    }
+    /// Return the text that closes this delimiter
+    pub fn close(self) -> &'static str {
+        match self {
+            Delimiter::Curly | Delimiter::Interpol => "}",
+            Delimiter::Square => "]",
+            Delimiter::Paren => ")",
+            Delimiter::String => "\"",
+            Delimiter::MultilineString => "''",
+            Delimiter::Let => "in",
+            Delimiter::Comment => "*/",
+        }
+    }
+}
+impl fmt::Display for Delimiter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.open())
+    }
+}
+
+/// Whether some input is a finished expression
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Completeness {
+    /// The input parses without errors
+    Complete,
+    /// The input would be valid if more was added to the end, such as a
+    /// `}`. Holds the delimiters that are still open along with where they
+    /// were opened, outermost first. Empty input is also incomplete.
+    Incomplete(Vec<(Delimiter, TextRange)>),
+    /// The input has errors that adding more to the end won't fix
+    Invalid,
+}
+
+impl AST {
+    /// Check if the input is complete, incomplete or invalid. Input is only
+    /// incomplete if all errors are about the end of file, like an unclosed
+    /// string or `let` without `in`.
+    pub fn completeness(&self) -> Completeness {
+        let errors = self.errors();
+        if errors.is_empty() {
+            return Completeness::Complete;
+        }
+        let mut comment = None;
+        for error in &errors {
+            match error {
+                ParseError::UnexpectedEOF | ParseError::UnexpectedEOFWanted(_) => (),
+                ParseError::Lexical(LexError::UnterminatedString { .. }, _) => (),
+                ParseError::Lexical(LexError::UnterminatedComment { start }, _) => {
+                    comment = Some((Delimiter::Comment, *start))
+                }
+                _ => return Completeness::Invalid,
+            }
+        }
+
+        let root = self.node();
+        let mut last = root.last_token();
+        while let Some(token) = last.as_ref().filter(|token| token.kind().is_trivia()) {
+            last = token.prev_token();
+        }
+        let mut open: Vec<_> = last
+            .iter()
+            .flat_map(|token| token.parent().ancestors())
+            .filter_map(|node| unclosed(&node))
+            .collect();
+        open.reverse();
+        open.extend(comment);
+        Completeness::Incomplete(open)
+    }
+}
+
+/// Return the delimiter opened by a token, and the token that closes it
+fn delimiter(token: &SyntaxToken) -> Option<(Delimiter, SyntaxKind)> {
+    Some(match token.kind() {
+        TOKEN_CURLY_B_OPEN => (Delimiter::Curly, TOKEN_CURLY_B_CLOSE),
+        TOKEN_SQUARE_B_OPEN => (Delimiter::Square, TOKEN_SQUARE_B_CLOSE),
+        TOKEN_PAREN_OPEN => (Delimiter::Paren, TOKEN_PAREN_CLOSE),
+        TOKEN_INTERPOL_START => (Delimiter::Interpol, TOKEN_INTERPOL_END),
+        TOKEN_DYNAMIC_START => (Delimiter::Interpol, TOKEN_DYNAMIC_END),
+        TOKEN_STRING_START if token.text() == "''" => {
+            (Delimiter::MultilineString, TOKEN_STRING_END)
+        }
+        TOKEN_STRING_START => (Delimiter::String, TOKEN_STRING_END),
+        TOKEN_LET if token.parent().kind() == NODE_LET_IN => (Delimiter::Let, TOKEN_IN),
+        _ => return None,
+    })
+}
+/// Return the delimiter this node opens but doesn't close, if any
+fn unclosed(node: &SyntaxNode) -> Option<(Delimiter, TextRange)> {
+    let mut open = None;
+    for child in node.children_with_tokens() {
+        if let NodeOrToken::Token(child) = child {
+            match (&open, delimiter(&child)) {
+                (None, Some((delimiter, close))) => open = Some((delimiter, child, close)),
+                (Some((_, _, close)), _) if child.kind() == *close => return None,
+                _ => (),
+            }
+        }
+    }
+    open.map(|(delimiter, token, _)| (delimiter, token.text_range()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn range(start: u32, end: u32) -> TextRange {
+        TextRange::from_to(start.into(), end.into())
+    }
+    fn check(code: &str) -> Completeness {
+        crate::parse(code).completeness()
+    }
+
+    #[test]
+    fn complete() {
+        for &code in &["1", "{ a = 1; }", "let a = 1; in a", "''\n  a\n''", "x: x # comment"] {
+            assert_eq!(check(code), Completeness::Complete, "{}", code);
+        }
+    }
+    #[test]
+    fn incomplete() {
+        use Delimiter::*;
+
+        let cases = vec![
+            ("", vec![]),
+            ("1 +", vec![]),
+            (
+                "{ a = [ (1 ",
+                vec![(Curly, range(0, 1)), (Square, range(6, 7)), (Paren, range(8, 9))],
+            ),
+            ("let a = 1;", vec![(Let, range(0, 3))]),
+            ("let a = 1; in", vec![]),
+            ("''\n  foo", vec![(MultilineString, range(0, 2))]),
+            ("\"a ${b", vec![(String, range(0, 1)), (Interpol, range(3, 5))]),
+            ("{ a.${b", vec![(Curly, range(0, 1)), (Interpol, range(4, 6))]),
+            ("{ a, b", vec![(Curly, range(0, 1))]),
+            ("[ 1 ] /* x", vec![(Comment, range(6, 8))]),
+            ("let\n  a = [\n    \"x\"\n", vec![(Let, range(0, 3)), (Square, range(10, 11))]),
+        ];
+        for (code, open) in cases {
+            assert_eq!(check(code), Completeness::Incomplete(open), "{}", code);
+        }
+    }
+    #[test]
+    fn invalid() {
+        for &code in &["1 )", "{ a = 1 b = 2", "[ 1 ] ^", "{ a = 1; } }"] {
+            assert_eq!(check(code), Completeness::Invalid, "{}", code);
+        }
+    }
+    #[test]
+    fn delimiters() {
+        assert_eq!(Delimiter::MultilineString.to_string(), "''");
+        assert_eq!(Delimiter::Let.close(), "in");
+        assert_eq!(Delimiter::Interpol.close(), "}");
+    }
+}
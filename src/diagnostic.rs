@@ -0,0 +1,397 @@
+//! Structured diagnostics, describing parse errors in more detail
+
+use std::fmt;
+
+use crate::{
+    parser::{Limit, ParseError, Wanted, AST},
+    reparse::{error_range, TextEdit},
+    tokenizer::LexError,
+    NodeOrToken,
+    SyntaxKind::{self, *},
+    SyntaxNode, SyntaxToken, TextRange, TextUnit,
+};
+
+/// How serious a diagnostic is
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        })
+    }
+}
+
+/// A range of the source with a message about it
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Label {
+    pub range: TextRange,
+    pub message: String,
+}
+impl Label {
+    /// Create a new label
+    pub fn new(range: TextRange, message: impl Into<String>) -> Self {
+        Self { range, message: message.into() }
+    }
+}
+
+/// A change that fixes the problem, safe to apply without asking. Pass the
+/// edit to `AST::reparse` to apply it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Fix {
+    pub message: String,
+    pub edit: TextEdit,
+}
+
+/// A problem found while parsing, with everything needed to show it to a user
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Diagnostic {
+    /// A code for the kind of problem, which will stay the same between
+    /// versions
+    pub code: &'static str,
+    pub severity: Severity,
+    pub message: String,
+    /// Where the problem is
+    pub primary: Label,
+    /// Other places related to the problem
+    pub secondary: Vec<Label>,
+    pub notes: Vec<String>,
+    pub fix: Option<Fix>,
+}
+
+impl Diagnostic {
+    /// Describe a parse error without looking at the tree it was found in.
+    /// Errors at the end of file point at `eof`.
+    pub fn from_error(error: &ParseError, eof: TextUnit) -> Self {
+        let eof = TextRange::offset_len(eof, 0.into());
+        let (code, message, primary) = match error {
+            ParseError::Unexpected(range) => {
+                ("E001", "unexpected input".into(), Label::new(*range, "this could not be parsed"))
+            }
+            ParseError::UnexpectedExtra(range) => (
+                "E002",
+                "unexpected input after the expression".into(),
+                Label::new(*range, "wanted end of file"),
+            ),
+            ParseError::UnexpectedWanted(got, range, wanted) => {
+                ("E003", format!("unexpected {}", got), Label::new(*range, wanted_label(wanted)))
+            }
+            ParseError::UnexpectedDoubleBind(range) => (
+                "E004",
+                "pattern is bound more than once".into(),
+                Label::new(*range, "second binding"),
+            ),
+            ParseError::UnexpectedEOF => {
+                ("E005", "unexpected end of file".into(), Label::new(eof, "more input wanted"))
+            }
+            ParseError::UnexpectedEOFWanted(wanted) => {
+                ("E006", "unexpected end of file".into(), Label::new(eof, wanted_label(wanted)))
+            }
+            ParseError::DisabledFeature(feature, range) => {
+                ("E007", format!("{} is not enabled", feature), Label::new(*range, "not enabled"))
+            }
+            ParseError::Lexical(err, range) => {
+                let (code, label) = match err {
+                    LexError::UnterminatedString { .. } => ("E008", "never closed"),
+                    LexError::UnterminatedComment { .. } => ("E009", "never closed"),
+                    LexError::PathEndsWithSlash => ("E010", "trailing `/`"),
+                    LexError::HomeWithoutSlash => ("E011", "wanted `/` after `~`"),
+                    LexError::MissingExponent => ("E012", "wanted digits after the `e`"),
+                    LexError::UnclosedStorePath { .. } => ("E013", "never closed"),
+                    LexError::UnexpectedChar => ("E014", "not valid here"),
+                };
+                (code, err.to_string(), Label::new(err.start().unwrap_or(*range), label))
+            }
+            ParseError::LimitExceeded(limit, range) => {
+                let (code, message) = match limit {
+                    Limit::Depth => ("E015", "expression is nested too deeply"),
+                    Limit::Tokens => ("E016", "too many tokens"),
+                    Limit::InputSize => ("E017", "input is too large"),
+                };
+                (code, message.into(), Label::new(*range, "not parsed"))
+            }
+        };
+        let notes = match error {
+            ParseError::UnexpectedExtra(_) => {
+                vec!["a Nix file is a single expression".to_string()]
+            }
+            ParseError::UnexpectedDoubleBind(_) => {
+                vec!["use either `args@{ ... }` or `{ ... }@args`, not both".to_string()]
+            }
+            ParseError::DisabledFeature(..) => {
+                vec!["this syntax can be turned on in the `ParseOptions`".to_string()]
+            }
+            ParseError::LimitExceeded(limit, _) => {
+                vec![format!("the {} can be raised in the `ParseOptions`", limit)]
+            }
+            _ => Vec::new(),
+        };
+        Self {
+            code,
+            severity: Severity::Error,
+            message,
+            primary,
+            secondary: Vec::new(),
+            notes,
+            fix: None,
+        }
+    }
+    /// Describe a parse error, looking at the tree it was found in for
+    /// unclosed delimiters and fixes to try
+    fn from_error_in(error: &ParseError, root: &SyntaxNode) -> Self {
+        let eof = root.text_range().end();
+        let mut diagnostic = Self::from_error(error, eof);
+        let (offset, wanted): (_, &[SyntaxKind]) = match error {
+            ParseError::UnexpectedWanted(_, range, wanted) => (range.start(), wanted),
+            ParseError::UnexpectedEOFWanted(wanted) => (eof, wanted),
+            ParseError::UnexpectedEOF => (eof, &[]),
+            _ => return diagnostic,
+        };
+        let before = token_before(root, offset);
+
+        if let Some(open) = before.as_ref().and_then(|token| unclosed(token, wanted)) {
+            let message = format!("unclosed `{}`", open.text());
+            diagnostic.secondary.push(Label::new(open.text_range(), message));
+        }
+        // A missing `;` at the end of a binding. Either what came instead can
+        // follow a binding, or the binding went on after a line break, and
+        // the `;` goes before that line break.
+        let end = match error {
+            ParseError::UnexpectedWanted(got, ..)
+                if *got != TOKEN_CURLY_B_CLOSE && *got != TOKEN_IN =>
+            {
+                line_end_before(root, offset)
+            }
+            _ => before,
+        };
+        if let (Some(end), &[TOKEN_SEMICOLON]) = (end, wanted) {
+            diagnostic.fix = Some(Fix {
+                message: "insert `;`".into(),
+                edit: TextEdit::insert(end.text_range().end(), ";"),
+            });
+        }
+        diagnostic
+    }
+    /// Describe syntax that parses, but is deprecated
+    fn from_deprecated(element: &NodeOrToken<SyntaxNode, SyntaxToken>) -> Option<Self> {
+        let (code, message, range, note) = match element {
+            NodeOrToken::Node(node) if node.kind() == NODE_LEGACY_LET => (
+                "W001",
+                "`let { ... }` is deprecated",
+                node.first_token()?.text_range(),
+                "use `let ... in body` instead",
+            ),
+            NodeOrToken::Token(token) if token.kind() == TOKEN_URI => (
+                "W002",
+                "unquoted URIs are deprecated",
+                token.text_range(),
+                "put the URI in a string",
+            ),
+            _ => return None,
+        };
+        Some(Self {
+            code,
+            severity: Severity::Warning,
+            message: message.into(),
+            primary: Label::new(range, "deprecated"),
+            secondary: Vec::new(),
+            notes: vec![note.into()],
+            fix: None,
+        })
+    }
+}
+
+impl AST {
+    /// Return all errors in the tree as diagnostics, followed by warnings
+    /// about deprecated syntax
+    pub fn diagnostics(&self) -> Vec<Diagnostic> {
+        let root = self.node();
+        // Several errors may suggest the same fix, apply each one only once
+        let mut fixed: Vec<(TextEdit, AST)> = Vec::new();
+        let mut diagnostics: Vec<Diagnostic> = self
+            .errors()
+            .iter()
+            .map(|error| {
+                let mut diagnostic = Diagnostic::from_error_in(error, &root);
+                if let Some(fix) = &diagnostic.fix {
+                    let ast = match fixed.iter().find(|(edit, _)| *edit == fix.edit) {
+                        Some((_, ast)) => ast,
+                        None => {
+                            fixed.push((fix.edit.clone(), self.reparse(fix.edit.clone())));
+                            &fixed.last().unwrap().1
+                        }
+                    };
+                    if !is_fixed_by(ast, diagnostic.primary.range, &fix.edit) {
+                        diagnostic.fix = None;
+                    }
+                }
+                diagnostic
+            })
+            .collect();
+        diagnostics.extend(
+            root.descendants_with_tokens()
+                .filter_map(|element| Diagnostic::from_deprecated(&element)),
+        );
+        diagnostics
+    }
+}
+
+/// Returns true if `fixed`, the tree after applying `edit`, has no errors up
+/// to the end of `range`, so the edit is safe to suggest
+fn is_fixed_by(fixed: &AST, range: TextRange, edit: &TextEdit) -> bool {
+    let end = range.end() + TextUnit::of_str(&edit.insert) - edit.delete.len();
+    let eof = fixed.node().text_range().end();
+    fixed.errors().iter().all(|error| error_range(error).map_or(eof, |r| r.start()) >= end)
+}
+
+fn wanted_label(wanted: &[SyntaxKind]) -> String {
+    format!("wanted {}", Wanted(wanted))
+}
+/// Return the last token that isn't trivia before `offset`
+fn token_before(root: &SyntaxNode, offset: TextUnit) -> Option<SyntaxToken> {
+    let mut token = root.token_at_offset(offset).left_biased();
+    while let Some(current) = token {
+        if !current.kind().is_trivia() && current.text_range().end() <= offset {
+            return Some(current);
+        }
+        token = current.prev_token();
+    }
+    None
+}
+/// Return the last token that isn't trivia before the line break in front of
+/// `offset`, unless something else that ends a binding comes first
+fn line_end_before(root: &SyntaxNode, offset: TextUnit) -> Option<SyntaxToken> {
+    let mut token = token_before(root, offset);
+    while let Some(current) = token {
+        if matches!(current.kind(), TOKEN_ASSIGN | TOKEN_SEMICOLON | TOKEN_CURLY_B_OPEN | TOKEN_LET)
+        {
+            return None;
+        }
+        let prev = current.prev_token()?;
+        if prev.kind() == TOKEN_WHITESPACE && prev.text().contains('\n') {
+            return token_before(root, prev.text_range().start());
+        }
+        token = token_before(root, current.text_range().start());
+    }
+    None
+}
+/// Return the innermost opening delimiter around `token` that isn't closed,
+/// and is closed by one of the `wanted` tokens if there are any
+fn unclosed(token: &SyntaxToken, wanted: &[SyntaxKind]) -> Option<SyntaxToken> {
+    token.parent().ancestors().find_map(|node| {
+        let mut open = None;
+        for child in node.children_with_tokens() {
+            if let NodeOrToken::Token(child) = child {
+                match (&open, closing(child.kind())) {
+                    (None, Some(close)) => open = Some((child, close)),
+                    (Some((_, close)), _) if child.kind() == *close => return None,
+                    _ => (),
+                }
+            }
+        }
+        open.filter(|(_, close)| wanted.is_empty() || wanted.contains(close)).map(|(open, _)| open)
+    })
+}
+fn closing(open: SyntaxKind) -> Option<SyntaxKind> {
+    match open {
+        TOKEN_CURLY_B_OPEN => Some(TOKEN_CURLY_B_CLOSE),
+        TOKEN_SQUARE_B_OPEN => Some(TOKEN_SQUARE_B_CLOSE),
+        TOKEN_PAREN_OPEN => Some(TOKEN_PAREN_CLOSE),
+        TOKEN_INTERPOL_START => Some(TOKEN_INTERPOL_END),
+        TOKEN_DYNAMIC_START => Some(TOKEN_DYNAMIC_END),
+        TOKEN_STRING_START => Some(TOKEN_STRING_END),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn range(start: u32, end: u32) -> TextRange {
+        TextRange::from_to(start.into(), end.into())
+    }
+
+    #[test]
+    fn codes() {
+        let cases = [
+            ("1 2 )", "E002"),
+            ("{ a = 1; b = ; }", "E003"),
+            ("{ a }@b@c: 1", "E003"),
+            ("[ 1", "E005"),
+            ("{ a = 1", "E006"),
+            ("''\n  a", "E008"),
+            ("1.5e", "E012"),
+            ("{ a = 1; } ^", "E014"),
+        ];
+        for &(code, expected) in &cases {
+            let diagnostics = crate::parse(code).diagnostics();
+            assert_eq!(diagnostics[0].code, expected, "{}", code);
+            assert_eq!(diagnostics[0].severity, Severity::Error);
+        }
+        let diagnostic = Diagnostic::from_error(&ParseError::UnexpectedEOF, 3.into());
+        assert_eq!(diagnostic.primary, Label::new(range(3, 3), "more input wanted"));
+    }
+    #[test]
+    fn unclosed() {
+        let diagnostics = crate::parse("{ a = [ 1 (2").diagnostics();
+        let eof = diagnostics.iter().find(|d| d.code == "E005").unwrap();
+        assert_eq!(eof.secondary, vec![Label::new(range(10, 11), "unclosed `(`")]);
+
+        let diagnostics = crate::parse("[\n  \"a ${b\n").diagnostics();
+        let secondary: Vec<_> = diagnostics.iter().flat_map(|d| d.secondary.clone()).collect();
+        assert!(secondary.contains(&Label::new(range(7, 9), "unclosed `${`")));
+        assert!(secondary.contains(&Label::new(range(4, 5), "unclosed `\"`")));
+    }
+    #[test]
+    fn fixes() {
+        for &code in &["{ a = 1 }", "let a = 1 in a", "{ a = 1; b = { c = 2 }; }", "{ a = 1"] {
+            let ast = crate::parse(code);
+            let diagnostics = ast.diagnostics();
+            let fix = diagnostics[0].fix.as_ref().unwrap();
+            assert_eq!(fix.message, "insert `;`");
+
+            // Only the first error is fixed, not the ones following from it
+            assert!(diagnostics[1..].iter().all(|d| d.fix.is_none()), "{}", code);
+            let fixed = ast.reparse(fix.edit.clone());
+            assert!(fixed.diagnostics().iter().all(|d| d.fix.is_none()), "{}", code);
+            if code.ends_with('}') || code.ends_with('a') {
+                assert_eq!(fixed.errors(), Vec::new(), "{}", code);
+            }
+        }
+        // The `;` goes before the line break the binding went on after
+        for &(code, offset) in &[("{ a = 1\n b = 2; }", 7), ("let a = f x # c\n  b = 2; in a", 11)]
+        {
+            let ast = crate::parse(code);
+            let fix = ast.diagnostics()[0].fix.clone().unwrap();
+            assert_eq!(fix.edit, TextEdit::insert(offset.into(), ";"), "{}", code);
+            assert_eq!(ast.reparse(fix.edit).errors(), Vec::new(), "{}", code);
+        }
+        // Adding a `;` here would not help
+        for &code in &["{ a = 1 b = 2; }", "{ a =\n 1 b = 2; }"] {
+            let diagnostics = crate::parse(code).diagnostics();
+            assert!(diagnostics.iter().all(|d| d.fix.is_none()), "{}", code);
+        }
+    }
+    #[test]
+    fn warnings() {
+        let diagnostics = crate::parse("let { body = http://a; }").diagnostics();
+        let found: Vec<_> =
+            diagnostics.iter().map(|d| (d.code, d.severity, d.primary.range)).collect();
+        assert_eq!(
+            found,
+            vec![
+                ("W001", Severity::Warning, range(0, 3)),
+                ("W002", Severity::Warning, range(13, 21))
+            ]
+        );
+        let diagnostics = crate::parse("let { body = 1 }").diagnostics();
+        assert_eq!(diagnostics.last().unwrap().code, "W001");
+        assert_eq!(diagnostics[0].severity, Severity::Error);
+    }
+}
@@ -0,0 +1,565 @@
+//! Extract documentation from library files, like nixpkgs' `lib/strings.nix`.
+//!
+//! The functions are the entries of the set the file evaluates to, looking
+//! through `{ lib }:` arguments and `let ... in` blocks. An entry is
+//! documented by the comment above it:
+//!
+//! ```nix
+//! {
+//!   /* Join a list of strings with a separator.
+//!
+//!      Type: concatStringsSep :: string -> [string] -> string
+//!
+//!      Example:
+//!        concatStringsSep "/" ["usr" "local" "bin"]
+//!        => "usr/local/bin"
+//!   */
+//!   concatStringsSep =
+//!     # The separator to put between the strings
+//!     sep:
+//!     # The strings to join
+//!     list: builtins.concatStringsSep sep list;
+//! }
+//! ```
+//!
+//! Sections can also be written as Markdown headings, such as `# Type` and
+//! `# Example`, like in `/** */` doc comments.
+
+use std::fmt::Write;
+
+use crate::{
+    comments::{Comment, Comments},
+    line_index::{ColumnUnit, LineIndex},
+    types::*,
+    value::indention,
+};
+
+/// The documentation of a whole file
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Docs {
+    /// The comment at the start of the file
+    pub description: Option<String>,
+    pub entries: Vec<Entry>,
+}
+
+/// One documented entry of the set
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Entry {
+    /// The key of the entry, such as `concatStringsSep`
+    pub name: String,
+    /// The line the entry starts on, counting from 1
+    pub line: u32,
+    /// The documentation without the sections below
+    pub description: Option<String>,
+    /// The `Type:` section
+    pub type_: Option<String>,
+    /// The `Example:` section
+    pub example: Option<String>,
+    /// The arguments of the function, in order
+    pub args: Vec<Argument>,
+}
+
+/// An argument of a function
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Argument {
+    /// `x: ...`
+    Flat(Arg),
+    /// `{ a, b ? 1, ... }@args: ...`
+    Pattern { entries: Vec<Arg>, bind: Option<String>, ellipsis: bool },
+}
+
+/// A named argument and its comment
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Arg {
+    pub name: String,
+    pub doc: Option<String>,
+}
+
+impl Docs {
+    /// Extract the documentation of the file parsed into `root`
+    pub fn extract(root: &Root) -> Self {
+        let index = LineIndex::new(&root.node().text().to_string());
+        let description = root
+            .node()
+            .first_token()
+            .and_then(|token| {
+                if token.kind() == crate::SyntaxKind::TOKEN_WHITESPACE {
+                    token.next_token()
+                } else {
+                    Some(token)
+                }
+            })
+            .and_then(Comment::cast)
+            .map(|comment| comment.text())
+            .filter(|text| !text.is_empty());
+
+        let mut entries = Vec::new();
+        if let Some(set) = find_set(root.inner()) {
+            for entry in set.entries() {
+                let offset = entry.node().text_range().start();
+                let line = index.line_col(offset, ColumnUnit::Utf8).line + 1;
+                entries.extend(extract_entry(&entry, line));
+            }
+        }
+        Self { description, entries }
+    }
+
+    /// Render the documentation as Markdown, with each name prefixed by
+    /// `prefix` and a dot, unless it's empty
+    pub fn to_markdown(&self, prefix: &str) -> String {
+        let mut out = String::new();
+        if let Some(description) = &self.description {
+            writeln!(out, "{}\n", description).unwrap();
+        }
+        for entry in &self.entries {
+            if prefix.is_empty() {
+                writeln!(out, "## `{}`\n", entry.name).unwrap();
+            } else {
+                writeln!(out, "## `{}.{}`\n", prefix, entry.name).unwrap();
+            }
+            if let Some(description) = &entry.description {
+                writeln!(out, "{}\n", description).unwrap();
+            }
+            if let Some(type_) = &entry.type_ {
+                writeln!(out, "**Type**\n\n```\n{}\n```\n", type_).unwrap();
+            }
+            for arg in &entry.args {
+                match arg {
+                    Argument::Flat(arg) => {
+                        let doc = arg.doc.as_deref().unwrap_or("Function argument");
+                        writeln!(out, "`{}`\n\n: {}\n", arg.name, indent(doc)).unwrap();
+                    }
+                    Argument::Pattern { entries, bind, ellipsis } => {
+                        let mut names: Vec<_> = entries.iter().map(|e| e.name.as_str()).collect();
+                        if *ellipsis {
+                            names.push("...");
+                        }
+                        let bind = bind.as_ref().map(|bind| format!("@{}", bind));
+                        let pattern =
+                            format!("{{ {} }}{}", names.join(", "), bind.unwrap_or_default());
+                        writeln!(out, "`{}`\n\n: Structured function argument\n", pattern).unwrap();
+                        for arg in entries {
+                            match &arg.doc {
+                                Some(doc) => writeln!(out, "  - `{}`: {}", arg.name, indent(doc)),
+                                None => writeln!(out, "  - `{}`", arg.name),
+                            }
+                            .unwrap();
+                        }
+                        if !entries.is_empty() {
+                            out.push('\n');
+                        }
+                    }
+                }
+            }
+            if let Some(example) = &entry.example {
+                writeln!(out, "**Example**\n\n```nix\n{}\n```\n", example).unwrap();
+            }
+        }
+        out.truncate(out.trim_end().len());
+        out.push('\n');
+        out
+    }
+
+    /// Render the documentation as JSON, with an object per entry
+    pub fn to_json(&self) -> String {
+        let mut out = String::new();
+        out.push_str("{\"description\":");
+        json_option(&mut out, self.description.as_deref());
+        out.push_str(",\"entries\":[");
+        for (i, entry) in self.entries.iter().enumerate() {
+            if i > 0 {
+                out.push(',');
+            }
+            out.push_str("{\"name\":");
+            json_string(&mut out, &entry.name);
+            write!(out, ",\"line\":{}", entry.line).unwrap();
+            out.push_str(",\"description\":");
+            json_option(&mut out, entry.description.as_deref());
+            out.push_str(",\"type\":");
+            json_option(&mut out, entry.type_.as_deref());
+            out.push_str(",\"example\":");
+            json_option(&mut out, entry.example.as_deref());
+            out.push_str(",\"args\":[");
+            for (i, arg) in entry.args.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                match arg {
+                    Argument::Flat(arg) => json_arg(&mut out, arg),
+                    Argument::Pattern { entries, bind, ellipsis } => {
+                        out.push_str("{\"pattern\":[");
+                        for (i, arg) in entries.iter().enumerate() {
+                            if i > 0 {
+                                out.push(',');
+                            }
+                            json_arg(&mut out, arg);
+                        }
+                        out.push_str("],\"bind\":");
+                        json_option(&mut out, bind.as_deref());
+                        write!(out, ",\"ellipsis\":{}}}", ellipsis).unwrap();
+                    }
+                }
+            }
+            out.push_str("]}");
+        }
+        out.push_str("]}");
+        out
+    }
+}
+
+/// Find the set a file evaluates to, looking through function arguments and
+/// `let ... in` blocks around it
+fn find_set(mut expr: Option<Expr>) -> Option<AttrSet> {
+    loop {
+        expr = match expr? {
+            Expr::AttrSet(set) => return Some(set),
+            Expr::Lambda(lambda) => lambda.body(),
+            Expr::LetIn(let_in) => let_in.body(),
+            Expr::With(with) => with.body(),
+            Expr::Assert(assert) => assert.body(),
+            Expr::Paren(paren) => paren.inner(),
+            _ => return None,
+        };
+    }
+}
+
+/// Extract an entry that's a function or has a comment
+fn extract_entry(entry: &KeyValue, line: u32) -> Option<Entry> {
+    let name = entry.key()?.node().to_string();
+    let lambda = entry.value().and_then(Expr::downcast::<Lambda>);
+
+    let comments = entry.comments();
+    let doc = match comments.doc() {
+        Some(doc) => Some(doc.text()),
+        None => lambda.as_ref().and_then(|lambda| lambda.comments().doc().map(Comment::text)),
+    };
+    let doc = doc.or_else(|| join(comments.leading.iter()));
+    if doc.is_none() && lambda.is_none() {
+        return None;
+    }
+
+    let mut args = Vec::new();
+    let mut next = lambda;
+    while let Some(lambda) = next {
+        match lambda.arg() {
+            Some(Param::Ident(ident)) => {
+                let name = match ident.as_str() {
+                    Some(name) => name.to_string(),
+                    None => break,
+                };
+                let comments = lambda.comments();
+                let doc = join(arg_comments(&comments).filter(|comment| !comment.is_doc()));
+                args.push(Argument::Flat(Arg { name, doc }));
+            }
+            Some(Param::Pattern(pattern)) => {
+                let entries = pattern
+                    .entries()
+                    .filter_map(|entry| {
+                        let name = entry.name()?.as_str()?.to_string();
+                        Some(Arg { name, doc: join(arg_comments(&entry.comments())) })
+                    })
+                    .collect();
+                let bind = pattern.at().and_then(|at| at.as_str().map(String::from));
+                args.push(Argument::Pattern { entries, bind, ellipsis: pattern.ellipsis() });
+            }
+            None => break,
+        }
+        next = lambda.body().and_then(Expr::downcast::<Lambda>);
+    }
+
+    let mut entry = Entry { name, line, args, ..Entry::default() };
+    if let Some(doc) = doc {
+        parse_sections(&doc, &mut entry);
+    }
+    Some(entry)
+}
+
+/// Return the comments that can document an argument
+fn arg_comments(comments: &Comments) -> impl Iterator<Item = &Comment> {
+    comments.leading.iter().chain(&comments.trailing).chain(&comments.dangling)
+}
+
+/// Join the text of several comments, or return None if there's none
+fn join<'a>(comments: impl Iterator<Item = &'a Comment>) -> Option<String> {
+    let texts: Vec<_> = comments.map(Comment::text).filter(|text| !text.is_empty()).collect();
+    Some(texts.join("\n")).filter(|text| !text.is_empty())
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Section {
+    Description,
+    Type,
+    Example,
+}
+
+/// Split a comment into the description and the `Type:` and `Example:`
+/// sections. Any other Markdown heading is kept in the description.
+fn parse_sections(doc: &str, entry: &mut Entry) {
+    // The first line follows the comment marker, so it's indented differently
+    let mut lines = doc.lines();
+    let first = lines.next().unwrap_or("").trim();
+    let rest: Vec<_> = lines.collect();
+    let rest = dedent(&rest);
+
+    let mut sections: Vec<(Section, Vec<&str>)> = vec![(Section::Description, vec![first])];
+    let mut fenced = false;
+    for line in rest.lines() {
+        let trimmed = line.trim();
+        if trimmed.starts_with("```") {
+            fenced = !fenced;
+        } else if !fenced {
+            if let Some((section, inline)) = heading(line) {
+                sections.push((section, inline.into_iter().collect()));
+                if section == Section::Description {
+                    sections.last_mut().unwrap().1.push(line);
+                }
+                continue;
+            }
+        }
+        sections.last_mut().unwrap().1.push(line);
+    }
+
+    let mut description = Vec::new();
+    for (section, lines) in sections {
+        let text = trim_lines(&dedent(&lines));
+        match section {
+            Section::Description => description.push(text),
+            Section::Type => entry.type_ = Some(unfence(&text)),
+            Section::Example => entry.example = Some(unfence(&text)),
+        }
+    }
+    description.retain(|text| !text.is_empty());
+    entry.description = Some(description.join("\n\n")).filter(|text| !text.is_empty());
+}
+
+/// Recognize a section heading and any text after it on the same line.
+/// Markdown headings are only recognized at the start of a line, since
+/// indented examples may contain comments.
+fn heading(line: &str) -> Option<(Section, Option<&str>)> {
+    let trimmed = line.trim();
+    for (name, section) in
+        &[("Type:", Section::Type), ("Example:", Section::Example), ("Examples:", Section::Example)]
+    {
+        if let Some(inline) = trimmed.strip_prefix(name) {
+            let inline = inline.trim();
+            return Some((*section, Some(inline).filter(|inline| !inline.is_empty())));
+        }
+    }
+    let title = line.trim_start_matches('#');
+    if title.len() == line.len() || line.len() - title.len() > 6 || !title.starts_with(' ') {
+        return None;
+    }
+    match title.trim().to_lowercase().as_str() {
+        "type" => Some((Section::Type, None)),
+        "example" | "examples" => Some((Section::Example, None)),
+        _ => Some((Section::Description, None)),
+    }
+}
+
+/// Remove the indention common to all non-empty lines
+fn dedent(lines: &[&str]) -> String {
+    let common = lines
+        .iter()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| indention(line).count())
+        .min()
+        .unwrap_or(0);
+    let lines: Vec<_> = lines
+        .iter()
+        .map(|line| line.char_indices().nth(common).map_or("", |(i, _)| &line[i..]).trim_end())
+        .collect();
+    lines.join("\n")
+}
+
+/// Remove empty lines at the start and end
+fn trim_lines(text: &str) -> String {
+    let lines: Vec<_> = text.lines().collect();
+    let start = lines.iter().position(|line| !line.is_empty()).unwrap_or(lines.len());
+    let end = lines.iter().rposition(|line| !line.is_empty()).map_or(start, |end| end + 1);
+    lines[start..end].join("\n")
+}
+
+/// Remove a code fence around the text, if any
+fn unfence(text: &str) -> String {
+    let lines: Vec<_> = text.lines().collect();
+    match (lines.first(), lines.last()) {
+        (Some(first), Some(last))
+            if lines.len() >= 2 && first.starts_with("```") && *last == "```" =>
+        {
+            lines[1..lines.len() - 1].join("\n")
+        }
+        _ => text.to_string(),
+    }
+}
+
+/// Indent all but the first line to continue a Markdown list item
+fn indent(text: &str) -> String {
+    text.replace('\n', "\n  ")
+}
+
+fn json_string(out: &mut String, s: &str) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => write!(out, "\\u{:04x}", c as u32).unwrap(),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+fn json_option(out: &mut String, s: Option<&str>) {
+    match s {
+        Some(s) => json_string(out, s),
+        None => out.push_str("null"),
+    }
+}
+fn json_arg(out: &mut String, arg: &Arg) {
+    out.push_str("{\"name\":");
+    json_string(out, &arg.name);
+    out.push_str(",\"doc\":");
+    json_option(out, arg.doc.as_deref());
+    out.push('}');
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CODE: &str = r#"/* String manipulation functions. */
+{ lib }:
+let
+  inherit (builtins) length;
+in
+rec {
+  inherit (builtins) stringLength;
+
+  /* Concatenate a list of strings.
+
+    Type: concatStrings :: [string] -> string
+
+     Example:
+       concatStrings ["foo" "bar"]
+       => "foobar"
+  */
+  concatStrings = builtins.concatStringsSep "";
+
+  intersperse =
+    # Separator to add between elements
+    separator:
+    # Input list
+    list: lib.tail (lib.concatMap (x: [separator x]) list);
+
+  /**
+    Make a "directory".
+
+    More text.
+
+    # Type
+
+    ```
+    mkDir :: AttrSet -> Derivation
+    ```
+
+    # Notes
+
+    Not a section.
+
+    # Examples
+
+    ```nix
+    # Not a heading
+    mkDir { name = "x"; }
+    ```
+  */
+  mkDir = { name, # The name
+    quote ? false, ... }@args: null;
+
+  notAFunction = 1;
+}
+"#;
+
+    fn docs() -> Docs {
+        Docs::extract(&crate::parse(CODE).root())
+    }
+
+    #[test]
+    fn extract() {
+        let docs = docs();
+        assert_eq!(docs.description.as_deref(), Some("String manipulation functions."));
+        let names: Vec<_> = docs.entries.iter().map(|entry| entry.name.as_str()).collect();
+        assert_eq!(names, ["concatStrings", "intersperse", "mkDir"]);
+
+        let concat = &docs.entries[0];
+        assert_eq!(concat.line, 17);
+        assert_eq!(concat.description.as_deref(), Some("Concatenate a list of strings."));
+        assert_eq!(concat.type_.as_deref(), Some("concatStrings :: [string] -> string"));
+        assert_eq!(
+            concat.example.as_deref(),
+            Some("concatStrings [\"foo\" \"bar\"]\n=> \"foobar\"")
+        );
+        assert!(concat.args.is_empty());
+
+        let intersperse = &docs.entries[1];
+        assert_eq!(intersperse.description, None);
+        let arg =
+            |name: &str, doc: Option<&str>| Arg { name: name.into(), doc: doc.map(String::from) };
+        assert_eq!(
+            intersperse.args,
+            [
+                Argument::Flat(arg("separator", Some("Separator to add between elements"))),
+                Argument::Flat(arg("list", Some("Input list"))),
+            ]
+        );
+
+        let mk_dir = &docs.entries[2];
+        assert_eq!(
+            mk_dir.description.as_deref(),
+            Some("Make a \"directory\".\n\nMore text.\n\n# Notes\n\nNot a section.")
+        );
+        assert_eq!(mk_dir.type_.as_deref(), Some("mkDir :: AttrSet -> Derivation"));
+        assert_eq!(mk_dir.example.as_deref(), Some("# Not a heading\nmkDir { name = \"x\"; }"));
+        assert_eq!(
+            mk_dir.args,
+            [Argument::Pattern {
+                entries: vec![arg("name", Some("The name")), arg("quote", None)],
+                bind: Some("args".into()),
+                ellipsis: true,
+            }]
+        );
+    }
+    #[test]
+    fn markdown() {
+        let markdown = docs().to_markdown("lib.strings");
+        assert!(markdown
+            .starts_with("String manipulation functions.\n\n## `lib.strings.concatStrings`\n"));
+        assert!(markdown.contains("\n**Type**\n\n```\nconcatStrings :: [string] -> string\n```\n"));
+        assert!(markdown.contains("\n`separator`\n\n: Separator to add between elements\n"));
+        assert!(markdown.contains("\n`{ name, quote, ... }@args`\n\n: Structured function argument\n\n  - `name`: The name\n  - `quote`\n"));
+        assert!(markdown.ends_with("```nix\n# Not a heading\nmkDir { name = \"x\"; }\n```\n"));
+    }
+    #[test]
+    fn json() {
+        let docs = Docs {
+            description: None,
+            entries: vec![Entry {
+                name: "f".into(),
+                line: 2,
+                description: Some("Say \"hi\"\n\tback\\slash \u{1}".into()),
+                args: vec![
+                    Argument::Flat(Arg { name: "x".into(), doc: None }),
+                    Argument::Pattern { entries: Vec::new(), bind: None, ellipsis: false },
+                ],
+                ..Entry::default()
+            }],
+        };
+        assert_eq!(
+            docs.to_json(),
+            r#"{"description":null,"entries":[{"name":"f","line":2,"description":"Say \"hi\"\n\tback\\slash \u0001","type":null,"example":null,"args":[{"name":"x","doc":null},{"pattern":[],"bind":null,"ellipsis":false}]}]}"#
+        );
+    }
+}
@@ -0,0 +1,694 @@
+//! Helpers for producing modified copies of a tree, reusing all untouched
+//! green nodes
+
+use rowan::{GreenNode, GreenToken, Language};
+
+use crate::{
+    types::{Expr, Root, TypedNode},
+    NixLanguage, NodeOrToken, SmolStr, SyntaxElement,
+    SyntaxKind::{self, *},
+    SyntaxNode,
+};
+
+pub(crate) type GreenElement = NodeOrToken<GreenNode, GreenToken>;
+
+/// Return the green counterpart of an element
+pub(crate) fn green(element: &SyntaxElement) -> GreenElement {
+    match element {
+        NodeOrToken::Node(node) => NodeOrToken::Node(node.green().clone()),
+        NodeOrToken::Token(token) => NodeOrToken::Token(token.green().clone()),
+    }
+}
+
+/// Replace `node` with a new green node of the same kind and return the
+/// resulting tree
+pub(crate) fn new_root(node: &SyntaxNode, replacement: GreenNode) -> Root {
+    Root::cast(SyntaxNode::new_root(node.replace_with(replacement))).unwrap()
+}
+
+fn newlines(element: &SyntaxElement) -> Option<usize> {
+    match element {
+        NodeOrToken::Token(token) if token.kind() == TOKEN_WHITESPACE => {
+            Some(token.text().matches('\n').count())
+        }
+        _ => None,
+    }
+}
+fn is_comment(element: &SyntaxElement) -> bool {
+    element.kind() == TOKEN_COMMENT
+}
+/// Returns true if the comment at `index` is the first thing on its line
+fn owns_line(children: &[SyntaxElement], index: usize) -> bool {
+    index == 0 || newlines(&children[index - 1]).is_some_and(|n| n > 0)
+}
+/// Returns true if the comment at `index` is followed by a node on the same
+/// line, such as `/* c */ b = 2;`, so that it belongs to that node instead
+fn leads_node(children: &[SyntaxElement], index: usize) -> bool {
+    let next = match children.get(index + 1) {
+        Some(space) if newlines(space) == Some(0) => children.get(index + 2),
+        next => next,
+    };
+    next.is_some_and(|next| next.as_node().is_some() || is_comment(next))
+}
+/// Extend `end` over a comment that follows on the same line
+fn trailing_comment(children: &[SyntaxElement], end: usize) -> usize {
+    let comment = match children.get(end) {
+        Some(space) if newlines(space) == Some(0) => end + 1,
+        _ => end,
+    };
+    match children.get(comment) {
+        Some(element) if is_comment(element) && !leads_node(children, comment) => comment + 1,
+        _ => end,
+    }
+}
+
+/// Return the range of siblings owned by the child at `index`: The child
+/// itself, any comments in front of it on the same line or on the lines
+/// directly above it (not separated by an empty line), and a comment
+/// following it on the same line.
+pub(crate) fn attached_range(children: &[SyntaxElement], index: usize) -> (usize, usize) {
+    let mut start = index;
+    // Comments in front of it on the same line
+    loop {
+        if start >= 1 && is_comment(&children[start - 1]) {
+            start -= 1;
+        } else if start >= 2
+            && newlines(&children[start - 1]) == Some(0)
+            && is_comment(&children[start - 2])
+        {
+            start -= 2;
+        } else {
+            break;
+        }
+    }
+    // Comments on the lines above
+    loop {
+        if start >= 1 && is_comment(&children[start - 1]) && owns_line(children, start - 1) {
+            start -= 1;
+        } else if start >= 2
+            && newlines(&children[start - 1]).is_some_and(|n| n <= 1)
+            && is_comment(&children[start - 2])
+            && owns_line(children, start - 2)
+        {
+            start -= 2;
+        } else {
+            break;
+        }
+    }
+    (start, trailing_comment(children, index + 1))
+}
+
+/// Merge two whitespace tokens that became adjacent because everything in
+/// between was removed. The result keeps the indention of the latter and
+/// never has more line breaks than either of them.
+fn merge_whitespace(before: &str, after: &str, after_line_comment: bool) -> SmolStr {
+    let before_lines = before.matches('\n').count();
+    let after_lines = after.matches('\n').count();
+    if before_lines > 0 && after_lines > 0 {
+        let indent = &after[after.rfind('\n').unwrap() + 1..];
+        let mut merged = "\n".repeat(before_lines.min(after_lines));
+        merged.push_str(indent);
+        SmolStr::new(merged)
+    } else if after_lines == 0 && after_line_comment {
+        // A `#` comment must still be terminated by its line break
+        SmolStr::new(before)
+    } else {
+        SmolStr::new(after)
+    }
+}
+
+fn green_kind(element: &GreenElement) -> SyntaxKind {
+    let kind = match element {
+        NodeOrToken::Node(node) => node.kind(),
+        NodeOrToken::Token(token) => token.kind(),
+    };
+    NixLanguage::kind_from_raw(kind)
+}
+
+/// Rebuild `parent` from the elements that are not marked as removed,
+/// cleaning up the whitespace left behind
+fn rebuild(parent: &SyntaxNode, children: &[SyntaxElement], removed: &[bool]) -> GreenNode {
+    let mut output: Vec<GreenElement> = Vec::with_capacity(children.len());
+    let mut last_removed = false;
+    for (child, &removed) in children.iter().zip(removed) {
+        if removed {
+            last_removed = true;
+            continue;
+        }
+        let len = output.len();
+        match (output.last(), child) {
+            (Some(NodeOrToken::Token(prev)), NodeOrToken::Token(token))
+                if last_removed
+                    && green_kind(&output[len - 1]) == TOKEN_WHITESPACE
+                    && token.kind() == TOKEN_WHITESPACE =>
+            {
+                let after_line_comment = len >= 2
+                    && match &output[len - 2] {
+                        NodeOrToken::Token(comment) => {
+                            green_kind(&output[len - 2]) == TOKEN_COMMENT
+                                && comment.text().starts_with('#')
+                        }
+                        NodeOrToken::Node(_) => false,
+                    };
+                let text = merge_whitespace(prev.text(), token.text(), after_line_comment);
+                output[len - 1] = NodeOrToken::Token(GreenToken::new(
+                    NixLanguage::kind_to_raw(TOKEN_WHITESPACE),
+                    text,
+                ));
+            }
+            _ => output.push(green(child)),
+        }
+        last_removed = false;
+    }
+    GreenNode::new(NixLanguage::kind_to_raw(parent.kind()), output)
+}
+
+/// Return a copy of the tree with every child of `parent` matching `remove`
+/// taken out, together with its attached comments. If `separator` is given,
+/// one adjacent separator token (such as the `,` between pattern entries) is
+/// removed alongside each child.
+pub(crate) fn remove_children<F>(
+    parent: &SyntaxNode,
+    mut remove: F,
+    separator: Option<SyntaxKind>,
+) -> Root
+where
+    F: FnMut(&SyntaxElement) -> bool,
+{
+    let children: Vec<SyntaxElement> = parent.children_with_tokens().collect();
+    let mut removed = vec![false; children.len()];
+
+    let targets: Vec<usize> = (0..children.len()).filter(|&i| remove(&children[i])).collect();
+    // Go backwards, so a trailing entry can claim the separator in front of
+    // it before the entry in front of that looks for one.
+    for &index in targets.iter().rev() {
+        let (start, mut end) = attached_range(&children, index);
+        if let Some(separator) = separator {
+            let significant = |&i: &usize| !removed[i] && !children[i].kind().is_trivia();
+            let next = (end..children.len()).find(significant);
+            let prev = (0..start).rev().find(significant);
+            if next.is_some_and(|i| children[i].kind() == separator) {
+                end = trailing_comment(&children, next.unwrap() + 1);
+            } else if let Some(prev) = prev.filter(|&i| children[i].kind() == separator) {
+                removed[prev] = true;
+            }
+        }
+        for removed in &mut removed[start..end] {
+            *removed = true;
+        }
+    }
+
+    new_root(parent, rebuild(parent, &children, &removed))
+}
+
+fn token(kind: SyntaxKind, text: &str) -> GreenElement {
+    NodeOrToken::Token(GreenToken::new(NixLanguage::kind_to_raw(kind), SmolStr::new(text)))
+}
+fn whitespace(text: &str) -> GreenElement {
+    token(TOKEN_WHITESPACE, text)
+}
+fn wrap(
+    kind: SyntaxKind,
+    open: (SyntaxKind, &str),
+    node: GreenNode,
+    close: (SyntaxKind, &str),
+) -> GreenNode {
+    let children = vec![token(open.0, open.1), NodeOrToken::Node(node), token(close.0, close.1)];
+    GreenNode::new(NixLanguage::kind_to_raw(kind), children)
+}
+
+/// Where a node is placed inside its parent, for the purpose of deciding
+/// whether it needs to be wrapped to be parsed back the same way
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Position {
+    /// Any expression is accepted, such as the body of a lambda
+    Expr,
+    /// Only an attribute name is accepted, such as a part of a key
+    Attr,
+    /// An operand of an operator, or the function being applied
+    Operand,
+    /// Only a simple value is accepted, such as a list item
+    Value,
+    /// The set an attribute is selected from, where a number or path would
+    /// swallow the dot
+    Selected,
+    /// The operand of `-` or `!`, where a path would swallow the operator
+    Unary,
+    /// Only these kinds of nodes are accepted, such as a key or an argument
+    Only(&'static [SyntaxKind]),
+}
+fn position(parent: SyntaxKind, index: usize, kind: SyntaxKind) -> Position {
+    match (parent, index, kind) {
+        (_, _, NODE_KEY_VALUE) | (_, _, NODE_INHERIT) => {
+            Position::Only(&[NODE_KEY_VALUE, NODE_INHERIT])
+        }
+        (_, _, NODE_KEY) => Position::Only(&[NODE_KEY]),
+        (_, _, NODE_INHERIT_FROM) => Position::Only(&[NODE_INHERIT_FROM]),
+        (_, _, NODE_PAT_ENTRY) => Position::Only(&[NODE_PAT_ENTRY]),
+        (_, _, NODE_PAT_BIND) => Position::Only(&[NODE_PAT_BIND]),
+        (_, _, NODE_STRING_INTERPOL) => Position::Only(&[NODE_STRING_INTERPOL]),
+        (NODE_LAMBDA, 0, _) => Position::Only(&[NODE_IDENT, NODE_PATTERN]),
+        (NODE_PAT_ENTRY, 0, _) | (NODE_PAT_BIND, _, _) | (NODE_INHERIT, _, _) => {
+            Position::Only(&[NODE_IDENT])
+        }
+        (NODE_OR_DEFAULT, 0, _) => Position::Only(&[NODE_SELECT]),
+        (NODE_ROOT, _, _)
+        | (NODE_PAREN, _, _)
+        | (NODE_STRING_INTERPOL, _, _)
+        | (NODE_DYNAMIC, _, _)
+        | (NODE_INHERIT_FROM, _, _)
+        | (NODE_ASSERT, _, _)
+        | (NODE_IF_ELSE, _, _)
+        | (NODE_WITH, _, _)
+        | (NODE_LET_IN, _, _)
+        | (NODE_LAMBDA, 1, _)
+        | (NODE_KEY_VALUE, 1, _)
+        | (NODE_PAT_ENTRY, 1, _) => Position::Expr,
+        (NODE_KEY, _, _) | (NODE_SELECT, 1, _) => Position::Attr,
+        (NODE_SELECT, 0, _) => Position::Selected,
+        (NODE_UNARY_OP, _, _) => Position::Unary,
+        (NODE_BIN_OP, _, _) | (NODE_APPLY, 0, _) => Position::Operand,
+        _ => Position::Value,
+    }
+}
+/// Returns true if a node of this kind is parsed as one value wherever a
+/// value is expected
+fn is_atomic(kind: SyntaxKind) -> bool {
+    matches!(
+        kind,
+        NODE_PAREN
+            | NODE_ATTR_SET
+            | NODE_LIST
+            | NODE_STRING
+            | NODE_PATH_WITH_INTERPOL
+            | NODE_LITERAL
+            | NODE_IDENT
+            | NODE_SELECT
+    )
+}
+/// Wrap `node` in parenthesis (or `${}` for attribute names) if it would
+/// otherwise not be parsed back as one unit in the given position, or
+/// return None if it can't be put there at all
+fn fit(node: GreenNode, position: Position) -> Option<GreenNode> {
+    let kind = NixLanguage::kind_from_raw(node.kind());
+    if let Position::Only(kinds) = position {
+        return Some(node).filter(|_| kinds.contains(&kind));
+    }
+    if kind == NODE_DYNAMIC && position == Position::Attr {
+        return Some(node);
+    }
+    Expr::cast(SyntaxNode::new_root(node.clone()))?;
+    Some(match position {
+        Position::Expr => node,
+        Position::Attr if matches!(kind, NODE_IDENT | NODE_STRING) => node,
+        Position::Attr => {
+            wrap(NODE_DYNAMIC, (TOKEN_DYNAMIC_START, "${"), node, (TOKEN_DYNAMIC_END, "}"))
+        }
+        Position::Selected if kind == NODE_LITERAL || is_path(&node) => paren(node),
+        Position::Unary if is_path(&node) => paren(node),
+        _ if is_atomic(kind) => node,
+        Position::Operand | Position::Unary if kind == NODE_APPLY => node,
+        _ => paren(node),
+    })
+}
+fn paren(node: GreenNode) -> GreenNode {
+    wrap(NODE_PAREN, (TOKEN_PAREN_OPEN, "("), node, (TOKEN_PAREN_CLOSE, ")"))
+}
+/// Returns true if the node is a path, with or without interpolation
+fn is_path(node: &GreenNode) -> bool {
+    match NixLanguage::kind_from_raw(node.kind()) {
+        NODE_PATH_WITH_INTERPOL => true,
+        NODE_LITERAL => node
+            .children()
+            .next()
+            .is_some_and(|token| matches!(NixLanguage::kind_from_raw(token.kind()), TOKEN_PATH)),
+        _ => false,
+    }
+}
+/// Strip the root node (and the trivia around its expression) off of a
+/// replacement that was taken straight from a parse
+fn unroot(node: &SyntaxNode) -> GreenNode {
+    match node.kind() {
+        NODE_ROOT => match node.first_child() {
+            Some(inner) => unroot(&inner),
+            None => node.green().clone(),
+        },
+        _ => node.green().clone(),
+    }
+}
+
+/// Return a copy of the tree with `node` replaced by `replacement`, which is
+/// wrapped if needed to be parsed back in the same position, or None if the
+/// replacement can't go there
+pub(crate) fn replace(node: &SyntaxNode, replacement: &SyntaxNode) -> Option<Root> {
+    let replacement = unroot(replacement);
+    let parent = match node.parent() {
+        Some(parent) => parent,
+        None => {
+            let replacement = fit(replacement, Position::Expr)?;
+            let root = GreenNode::new(
+                NixLanguage::kind_to_raw(NODE_ROOT),
+                std::iter::once(NodeOrToken::Node(replacement)),
+            );
+            return Root::cast(SyntaxNode::new_root(root));
+        }
+    };
+    let index = parent.children().position(|child| child == *node).unwrap();
+    let replacement = fit(replacement, position(parent.kind(), index, node.kind()))?;
+    let children = parent.children_with_tokens().map(|child| match child {
+        NodeOrToken::Node(ref child) if child == node => NodeOrToken::Node(replacement.clone()),
+        child => green(&child),
+    });
+    let children: Vec<_> = children.collect();
+    Some(new_root(&parent, GreenNode::new(NixLanguage::kind_to_raw(parent.kind()), children)))
+}
+
+/// Return a copy of the tree with `node` removed from its parent, or None if
+/// the parent does not allow it to be removed
+pub(crate) fn remove(node: &SyntaxNode) -> Option<Root> {
+    let parent = node.parent()?;
+    let separator = match (parent.kind(), node.kind()) {
+        (NODE_LIST, _) => None,
+        (NODE_ATTR_SET, NODE_KEY_VALUE)
+        | (NODE_ATTR_SET, NODE_INHERIT)
+        | (NODE_LET_IN, NODE_KEY_VALUE)
+        | (NODE_LET_IN, NODE_INHERIT)
+        | (NODE_LEGACY_LET, NODE_KEY_VALUE)
+        | (NODE_LEGACY_LET, NODE_INHERIT)
+        | (NODE_INHERIT, NODE_IDENT) => None,
+        (NODE_PATTERN, NODE_PAT_ENTRY) => Some(TOKEN_COMMA),
+        _ => return None,
+    };
+    Some(remove_children(&parent, |child| child.as_node() == Some(node), separator))
+}
+
+/// Return a copy of the tree with `item` inserted into `parent` as a sibling
+/// of the existing children matching `is_item`, so that it becomes the
+/// `index`th of them. The whitespace in front of the neighbouring item is
+/// reused to separate the new one, so the layout of the parent is kept.
+pub(crate) fn insert<F>(parent: &SyntaxNode, index: usize, item: GreenNode, is_item: F) -> Root
+where
+    F: Fn(&SyntaxElement) -> bool,
+{
+    let children: Vec<SyntaxElement> = parent.children_with_tokens().collect();
+    let items: Vec<usize> = (0..children.len()).filter(|&i| is_item(&children[i])).collect();
+    let separator = |i: usize| match i.checked_sub(1).map(|i| &children[i]) {
+        Some(NodeOrToken::Token(token)) if token.kind() == TOKEN_WHITESPACE => {
+            token.text().as_str()
+        }
+        _ => " ",
+    };
+    let item = NodeOrToken::Node(item);
+
+    let mut output: Vec<GreenElement> = children.iter().map(green).collect();
+    match (items.get(index), items.last()) {
+        (Some(&next), _) => {
+            let (start, _) = attached_range(&children, next);
+            output.splice(start..start, vec![item, whitespace(separator(start))]);
+        }
+        (None, Some(&last)) => {
+            let (start, end) = attached_range(&children, last);
+            let mut space = separator(start).to_string();
+            if end > last + 1
+                && children[end - 1].to_string().starts_with('#')
+                && !space.contains('\n')
+            {
+                // There is a line comment to close first, so start a new line
+                // lined up with the last item
+                let root = parent.ancestors().last().unwrap().to_string();
+                let offset = children[start].text_range().start().to_usize();
+                let line = root[..offset].rfind('\n').map_or(0, |i| i + 1);
+                space = format!("\n{:1$}", "", root[line..offset].chars().count());
+            }
+            output.splice(end..end, vec![whitespace(&space), item]);
+        }
+        (None, None) => {
+            // Insert right after the opening token, such as `{` or `let`
+            let open = (0..children.len())
+                .rev()
+                .find(|&i| {
+                    matches!(
+                        children[i].kind(),
+                        TOKEN_CURLY_B_OPEN | TOKEN_SQUARE_B_OPEN | TOKEN_LET
+                    )
+                })
+                .map_or(0, |i| i + 1);
+            let mut new = vec![whitespace(" "), item];
+            if children.get(open).map_or(true, |next| next.kind() != TOKEN_WHITESPACE) {
+                new.push(whitespace(" "));
+            }
+            output.splice(open..open, new);
+        }
+    }
+    new_root(parent, GreenNode::new(NixLanguage::kind_to_raw(parent.kind()), output))
+}
+
+/// Insert an item into a list, wrapping it in parenthesis if needed, or
+/// return None if it's not an expression
+pub(crate) fn insert_item(list: &SyntaxNode, index: usize, item: &SyntaxNode) -> Option<Root> {
+    let item = fit(unroot(item), Position::Value)?;
+    Some(insert(list, index, item, |child| child.as_node().is_some()))
+}
+/// Insert an entry into a set or let expression
+pub(crate) fn insert_entry(set: &SyntaxNode, index: usize, entry: &SyntaxNode) -> Root {
+    insert(set, index, entry.green().clone(), |child| {
+        matches!(child.kind(), NODE_KEY_VALUE | NODE_INHERIT)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{types::*, SyntaxKind::*, SyntaxNode};
+
+    /// Check that the edited tree is exactly what parsing its text gives
+    fn reparse(root: Root) -> String {
+        let output = root.node().to_string();
+        let ast = crate::parse(&output);
+        assert!(ast.errors().is_empty(), "invalid output: {}", output);
+        assert!(ast.root().dump().to_string() == root.dump().to_string(), "changed: {}", output);
+        output
+    }
+    fn expr(code: &str) -> SyntaxNode {
+        crate::parse(code).root().inner().unwrap().node().clone()
+    }
+
+    fn filter_set(code: &str, keep: &[&str]) -> String {
+        let ast = crate::parse(code);
+        let set = ast.root().inner().and_then(Expr::downcast::<AttrSet>).unwrap();
+        let root = set.filter_entries(|entry| {
+            let key = entry.key().unwrap().node().to_string();
+            keep.contains(&key.as_str())
+        });
+        reparse(root)
+    }
+    fn filter_pattern(code: &str, keep: &[&str]) -> String {
+        let ast = crate::parse(code);
+        let lambda = ast.root().inner().and_then(Expr::downcast::<Lambda>).unwrap();
+        let pattern = lambda.arg().and_then(Param::downcast::<Pattern>).unwrap();
+        let root =
+            pattern.filter_entries(|entry| keep.contains(&entry.name().unwrap().as_str().unwrap()));
+        reparse(root)
+    }
+
+    #[test]
+    fn filter_set_inline() {
+        assert_eq!(filter_set("{ a = 2; b = 3; }", &["b"]), "{ b = 3; }");
+        assert_eq!(filter_set("{ a = 2; b = 3; }", &["a"]), "{ a = 2; }");
+        assert_eq!(filter_set("{ a = 2; b = 3; }", &[]), "{ }");
+        assert_eq!(filter_set("rec { a = 2; inherit b; c = 3; }", &[]), "rec { inherit b; }");
+        let code = "{ a = 1; /* c */ b = 2; }";
+        assert_eq!(filter_set(code, &["a"]), "{ a = 1; }");
+        assert_eq!(filter_set(code, &["b"]), "{ /* c */ b = 2; }");
+    }
+    #[test]
+    fn filter_set_multiline() {
+        let code = "{\n  # doc for a\n  a = 1; # about a\n\n  b = 2;\n  c = 3; # about c\n}";
+        assert_eq!(filter_set(code, &["b", "c"]), "{\n  b = 2;\n  c = 3; # about c\n}");
+        assert_eq!(
+            filter_set(code, &["a", "c"]),
+            "{\n  # doc for a\n  a = 1; # about a\n  c = 3; # about c\n}"
+        );
+        assert_eq!(
+            filter_set(code, &["a", "b"]),
+            "{\n  # doc for a\n  a = 1; # about a\n\n  b = 2;\n}"
+        );
+        assert_eq!(filter_set(code, &[]), "{\n}");
+    }
+    #[test]
+    fn filter_pattern_inline() {
+        assert_eq!(filter_pattern("{ a, b, c }: a", &["a", "c"]), "{ a, c }: a");
+        assert_eq!(filter_pattern("{ a, b, c }: a", &["b", "c"]), "{ b, c }: a");
+        assert_eq!(filter_pattern("{ a, b, c }: a", &["a", "b"]), "{ a, b }: a");
+        assert_eq!(filter_pattern("{ a, b, c }: a", &["a"]), "{ a }: a");
+        assert_eq!(filter_pattern("{ a, b ? 1, ... }@args: a", &["a"]), "{ a, ... }@args: a");
+        assert_eq!(filter_pattern("{ a, b }: a", &[]), "{ }: a");
+        assert_eq!(filter_pattern("{ a, /* b */ b }: a", &["a"]), "{ a }: a");
+        assert_eq!(filter_pattern("{ a /* a */, b }: a", &["b"]), "{ b }: a");
+    }
+    #[test]
+    fn filter_pattern_multiline() {
+        let code = "{ stdenv\n, fetchurl # for sources\n, lib\n}: stdenv";
+        assert_eq!(filter_pattern(code, &["stdenv", "lib"]), "{ stdenv\n, lib\n}: stdenv");
+        assert_eq!(
+            filter_pattern(code, &["fetchurl", "lib"]),
+            "{ fetchurl # for sources\n, lib\n}: stdenv"
+        );
+        assert_eq!(
+            filter_pattern(code, &["stdenv", "fetchurl"]),
+            "{ stdenv\n, fetchurl # for sources\n}: stdenv"
+        );
+
+        let code = "{ a, # about a\n  b\n}: a";
+        assert_eq!(filter_pattern(code, &["a"]), "{ a # about a\n}: a");
+    }
+
+    #[test]
+    fn replace() {
+        let ast = crate::parse("{ a = 1; b = [ x ]; c = f x; }");
+        let set = ast.root().inner().and_then(Expr::downcast::<AttrSet>).unwrap();
+        let mut entries = set.entries();
+        let a = entries.next().and_then(|e| e.value()).and_then(Expr::downcast::<Value>).unwrap();
+        let b = entries.next().and_then(|e| e.value()).and_then(Expr::downcast::<List>).unwrap();
+        let c = entries.next().and_then(|e| e.value()).and_then(Expr::downcast::<Apply>).unwrap();
+
+        let lambda = expr("x: x");
+        assert_eq!(reparse(a.replace_with(&lambda).unwrap()), "{ a = x: x; b = [ x ]; c = f x; }");
+        let item = b.items().next().and_then(Expr::downcast::<Ident>).unwrap();
+        assert_eq!(
+            reparse(item.replace_with(&lambda).unwrap()),
+            "{ a = 1; b = [ (x: x) ]; c = f x; }"
+        );
+        assert_eq!(
+            reparse(item.replace_with(&expr("y")).unwrap()),
+            "{ a = 1; b = [ y ]; c = f x; }"
+        );
+        let value = c.value().and_then(Expr::downcast::<Ident>).unwrap();
+        assert_eq!(
+            reparse(value.replace_with(&expr("g y")).unwrap()),
+            "{ a = 1; b = [ x ]; c = f (g y); }"
+        );
+        let lambda = c.lambda().and_then(Expr::downcast::<Ident>).unwrap();
+        assert_eq!(
+            reparse(lambda.replace_with(&expr("g y")).unwrap()),
+            "{ a = 1; b = [ x ]; c = g y x; }"
+        );
+
+        let key = set.entries().next().and_then(|e| e.key()).unwrap();
+        let ident = key.path().next().and_then(KeyPart::downcast::<Ident>).unwrap();
+        assert_eq!(
+            reparse(ident.replace_with(&expr("a + b")).unwrap()),
+            "{ ${a + b} = 1; b = [ x ]; c = f x; }"
+        );
+    }
+    #[test]
+    fn replace_slots() {
+        let ast = crate::parse("{ a.b = 1; } // (x: x) // ({ y }: y)");
+        let nodes: Vec<_> = ast.node().descendants().collect();
+        let find = |kind| nodes.iter().find(|node| node.kind() == kind).unwrap().clone();
+        let set = expr("{ }");
+
+        let key = Key::cast(find(NODE_KEY)).unwrap();
+        assert!(key.replace_with(&set).is_none());
+        assert_eq!(reparse(key.replace_with(&find(NODE_KEY)).unwrap()), ast.node().to_string());
+        let arg = Ident::cast(find(NODE_LAMBDA).first_child().unwrap()).unwrap();
+        assert!(arg.replace_with(&set).is_none());
+        assert_eq!(
+            reparse(arg.replace_with(&find(NODE_PATTERN)).unwrap()),
+            "{ a.b = 1; } // ({ y }: x) // ({ y }: y)"
+        );
+        let entry = PatEntry::cast(find(NODE_PAT_ENTRY)).unwrap();
+        assert!(entry.name().unwrap().replace_with(&set).is_none());
+        assert!(entry.replace_with(&expr("z")).is_none());
+        assert!(List::cast(expr("[ ]")).unwrap().push_item(&find(NODE_KEY_VALUE)).is_none());
+    }
+    #[test]
+    fn replace_everything() {
+        // Put every node in place of every other one, and check that the
+        // result is either refused or parses back to the same tree
+        let code = r#"{ inherit (a) b; c.${d}."e" = x: { f ? 1, ... }@g: h; } // [
+  "s${i}" ./p/${j} ./q 1.5 (k l) m.n or o (-p) (!q) (r ? s.t)
+] ++ (assert u; if v then w else with x; let y = z; in let { body = 1; })"#;
+        let ast = crate::parse(code);
+        assert!(ast.errors().is_empty());
+        let nodes: Vec<_> = ast.node().descendants().collect();
+        let mut replacements = nodes.clone();
+        let key = |node: &SyntaxNode| (node.kind(), node.first_token().map(|token| token.kind()));
+        replacements.sort_by_key(key);
+        replacements.dedup_by_key(|node| key(node));
+        for node in &nodes {
+            for replacement in &replacements {
+                if let Some(root) = super::replace(node, replacement) {
+                    reparse(root);
+                }
+            }
+        }
+    }
+    #[test]
+    fn replace_operands() {
+        let ast = crate::parse("a - b");
+        let op = ast.root().inner().and_then(Expr::downcast::<BinOp>).unwrap();
+        let lhs = op.lhs().and_then(Expr::downcast::<Ident>).unwrap();
+        assert_eq!(reparse(lhs.replace_with(&expr("c - d")).unwrap()), "(c - d) - b");
+        assert_eq!(reparse(lhs.replace_with(&expr("f x")).unwrap()), "f x - b");
+        assert_eq!(reparse(ast.root().replace_with(&expr("1")).unwrap()), "1");
+    }
+    #[test]
+    fn remove() {
+        let ast = crate::parse("{ a = [ 1 2 3 ]; inherit b c; }");
+        let set = ast.root().inner().and_then(Expr::downcast::<AttrSet>).unwrap();
+        let entry = set.entries().next().unwrap();
+        let list = entry.value().and_then(Expr::downcast::<List>).unwrap();
+        let inherit = set.inherits().next().unwrap();
+
+        assert_eq!(reparse(entry.remove().unwrap()), "{ inherit b c; }");
+        assert_eq!(reparse(inherit.remove().unwrap()), "{ a = [ 1 2 3 ]; }");
+        let item = list.items().nth(1).and_then(Expr::downcast::<Value>).unwrap();
+        assert_eq!(reparse(item.remove().unwrap()), "{ a = [ 1 3 ]; inherit b c; }");
+        let ident = inherit.idents().next().unwrap();
+        assert_eq!(reparse(ident.remove().unwrap()), "{ a = [ 1 2 3 ]; inherit c; }");
+        assert!(entry.key().unwrap().remove().is_none());
+    }
+    #[test]
+    fn insert_entries() {
+        let entry = {
+            let set = expr("{ x = 1; }");
+            AttrSet::cast(set).unwrap().entries().next().unwrap()
+        };
+        let insert = |code: &str, index: usize| {
+            let ast = crate::parse(code);
+            let root = match ast.root().inner() {
+                Some(Expr::AttrSet(set)) => set.insert_entry(index, &entry),
+                Some(Expr::LetIn(let_in)) => let_in.insert_entry(index, &entry),
+                _ => panic!("not a set or let"),
+            };
+            reparse(root)
+        };
+
+        assert_eq!(insert("{ }", 0), "{ x = 1; }");
+        assert_eq!(insert("{}", 0), "{ x = 1; }");
+        assert_eq!(insert("rec { a = 2; }", 0), "rec { x = 1; a = 2; }");
+        assert_eq!(insert("{ a = 2; }", 1), "{ a = 2; x = 1; }");
+        assert_eq!(insert("{\n  a = 2;\n  b = 3;\n}", 1), "{\n  a = 2;\n  x = 1;\n  b = 3;\n}");
+        assert_eq!(insert("{\n  a = 2; # about a\n}", 5), "{\n  a = 2; # about a\n  x = 1;\n}");
+        assert_eq!(insert("{ a = 2; # about a\n}", 5), "{ a = 2; # about a\n  x = 1;\n}");
+        assert_eq!(
+            insert("let a = 2; # about a\nin a", 5),
+            "let a = 2; # about a\n    x = 1;\nin a"
+        );
+        assert_eq!(insert("let in x", 0), "let x = 1; in x");
+        assert_eq!(insert("let\n  a = 2;\nin x", 1), "let\n  a = 2;\n  x = 1;\nin x");
+    }
+    #[test]
+    fn insert_items() {
+        let push = |code: &str, item: &str| {
+            let list = List::cast(expr(code)).unwrap();
+            reparse(list.push_item(&expr(item)).unwrap())
+        };
+        assert_eq!(push("[]", "1"), "[ 1 ]");
+        assert_eq!(push("[ 1 ]", "2"), "[ 1 2 ]");
+        assert_eq!(push("[\n  1\n]", "f x"), "[\n  1\n  (f x)\n]");
+
+        let list = List::cast(expr("[ 1 2 ]")).unwrap();
+        assert_eq!(reparse(list.insert_item(0, &expr("0")).unwrap()), "[ 0 1 2 ]");
+    }
+}
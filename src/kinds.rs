@@ -1,3 +1,5 @@
+use std::fmt;
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 #[repr(u16)]
 #[allow(non_camel_case_types)]
@@ -97,20 +99,198 @@ pub enum SyntaxKind {
     NODE_UNARY_OP,
     NODE_LITERAL,
     NODE_WITH,
+    // A path with interpolation, such as ./pkgs/${name}.nix
+    NODE_PATH_WITH_INTERPOL,
+
+    // Kinds added later go last, so the raw values of the others stay the
+    // same between versions
+    TOKEN_PIPE_RIGHT,
+    TOKEN_PIPE_LEFT,
 
     #[doc(hidden)]
     __LAST,
 }
 use SyntaxKind::*;
 
+/// How a chain of the same binary operator, such as `a - b - c`, is grouped
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Assoc {
+    /// `(a - b) - c`
+    Left,
+    /// `a <| (b <| c)`
+    Right,
+    /// The operator can't be chained, like `a == b == c`
+    None,
+}
+
+// Every kind that is always written the same way, with its text
+macro_rules! fixed_tokens {
+    ($($kind:ident => $text:literal,)*) => {
+        impl SyntaxKind {
+            /// Return the text of a token that is always written the same
+            /// way, such as `;` or `if`
+            pub fn token_text(self) -> Option<&'static str> {
+                match self {
+                    $($kind => Some($text),)*
+                    _ => None,
+                }
+            }
+            /// Return a name for this kind to show users, such as "`;`" or
+            /// "identifier"
+            pub fn name(self) -> &'static str {
+                match self {
+                    $($kind => concat!("`", $text, "`"),)*
+                    _ => self.description(),
+                }
+            }
+        }
+    };
+}
+fixed_tokens! {
+    TOKEN_ASSERT => "assert",
+    TOKEN_ELSE => "else",
+    TOKEN_IF => "if",
+    TOKEN_IN => "in",
+    TOKEN_INHERIT => "inherit",
+    TOKEN_LET => "let",
+    TOKEN_REC => "rec",
+    TOKEN_THEN => "then",
+    TOKEN_WITH => "with",
+
+    TOKEN_CURLY_B_OPEN => "{",
+    TOKEN_CURLY_B_CLOSE => "}",
+    TOKEN_SQUARE_B_OPEN => "[",
+    TOKEN_SQUARE_B_CLOSE => "]",
+    TOKEN_ASSIGN => "=",
+    TOKEN_AT => "@",
+    TOKEN_COLON => ":",
+    TOKEN_COMMA => ",",
+    TOKEN_DOT => ".",
+    TOKEN_ELLIPSIS => "...",
+    TOKEN_QUESTION => "?",
+    TOKEN_SEMICOLON => ";",
+
+    TOKEN_PAREN_OPEN => "(",
+    TOKEN_PAREN_CLOSE => ")",
+    TOKEN_CONCAT => "++",
+    TOKEN_INVERT => "!",
+    TOKEN_UPDATE => "//",
+
+    TOKEN_ADD => "+",
+    TOKEN_SUB => "-",
+    TOKEN_MUL => "*",
+    TOKEN_DIV => "/",
+
+    TOKEN_AND => "&&",
+    TOKEN_EQUAL => "==",
+    TOKEN_IMPLICATION => "->",
+    TOKEN_LESS => "<",
+    TOKEN_LESS_OR_EQ => "<=",
+    TOKEN_MORE => ">",
+    TOKEN_MORE_OR_EQ => ">=",
+    TOKEN_NOT_EQUAL => "!=",
+    TOKEN_OR => "||",
+    TOKEN_PIPE_RIGHT => "|>",
+    TOKEN_PIPE_LEFT => "<|",
+
+    TOKEN_DYNAMIC_END => "}",
+    TOKEN_DYNAMIC_START => "${",
+    TOKEN_INTERPOL_END => "}",
+    TOKEN_INTERPOL_START => "${",
+}
+
 impl SyntaxKind {
-    /// Returns true if this token is a literal, such as an integer or a string
-    pub fn is_literal(self) -> bool {
+    fn description(self) -> &'static str {
         match self {
-            TOKEN_FLOAT | TOKEN_INTEGER | TOKEN_PATH | TOKEN_URI => true,
-            _ => false,
+            TOKEN_COMMENT => "comment",
+            TOKEN_ERROR => "invalid token",
+            TOKEN_WHITESPACE => "whitespace",
+            TOKEN_FLOAT => "float",
+            TOKEN_IDENT => "identifier",
+            TOKEN_INTEGER => "integer",
+            TOKEN_PATH => "path",
+            TOKEN_URI => "URI",
+            TOKEN_STRING_CONTENT => "string content",
+            TOKEN_STRING_END => "end of string",
+            TOKEN_STRING_START => "string",
+
+            NODE_APPLY => "function application",
+            NODE_ASSERT => "assertion",
+            NODE_KEY => "attribute path",
+            NODE_DYNAMIC => "dynamic attribute",
+            NODE_ERROR => "error",
+            NODE_IDENT => "identifier",
+            NODE_IF_ELSE => "if expression",
+            NODE_SELECT => "attribute selection",
+            NODE_INHERIT => "inherit",
+            NODE_INHERIT_FROM => "inherit source",
+            NODE_STRING => "string",
+            NODE_STRING_INTERPOL => "interpolation",
+            NODE_LAMBDA => "function",
+            NODE_LEGACY_LET => "legacy let",
+            NODE_LET_IN => "let expression",
+            NODE_LIST => "list",
+            NODE_BIN_OP => "binary operation",
+            NODE_OR_DEFAULT => "or default",
+            NODE_PAREN => "parenthesized expression",
+            NODE_PATTERN => "pattern",
+            NODE_PAT_BIND => "pattern binding",
+            NODE_PAT_ENTRY => "pattern entry",
+            NODE_ROOT => "root",
+            NODE_ATTR_SET => "attribute set",
+            NODE_KEY_VALUE => "binding",
+            NODE_UNARY_OP => "unary operation",
+            NODE_LITERAL => "literal",
+            NODE_WITH => "with expression",
+            NODE_PATH_WITH_INTERPOL => "path",
+            _ => "unknown",
         }
     }
+    /// Returns true if this token is a keyword, such as `if`
+    pub fn is_keyword(self) -> bool {
+        matches!(
+            self,
+            TOKEN_ASSERT
+                | TOKEN_ELSE
+                | TOKEN_IF
+                | TOKEN_IN
+                | TOKEN_INHERIT
+                | TOKEN_LET
+                | TOKEN_REC
+                | TOKEN_THEN
+                | TOKEN_WITH
+        )
+    }
+    /// Returns true if this token is a unary or binary operator, such as `!`
+    /// or `+`
+    pub fn is_operator(self) -> bool {
+        self == TOKEN_INVERT || self.binary_op().is_some()
+    }
+    /// Return the precedence and associativity of a binary operator. Higher
+    /// precedence binds tighter, so `a + b * c` is `a + (b * c)`. This
+    /// describes the trees rnix builds.
+    pub fn binary_op(self) -> Option<(u8, Assoc)> {
+        Some(match self {
+            TOKEN_QUESTION => (12, Assoc::Left),
+            TOKEN_CONCAT => (11, Assoc::Right),
+            TOKEN_MUL | TOKEN_DIV => (10, Assoc::Left),
+            TOKEN_ADD | TOKEN_SUB => (9, Assoc::Left),
+            TOKEN_UPDATE => (7, Assoc::Right),
+            TOKEN_LESS | TOKEN_LESS_OR_EQ | TOKEN_MORE | TOKEN_MORE_OR_EQ => (6, Assoc::None),
+            TOKEN_EQUAL | TOKEN_NOT_EQUAL => (5, Assoc::None),
+            TOKEN_AND => (4, Assoc::Left),
+            TOKEN_OR => (3, Assoc::Left),
+            TOKEN_IMPLICATION => (2, Assoc::Right),
+            TOKEN_PIPE_RIGHT => (1, Assoc::Left),
+            TOKEN_PIPE_LEFT => (1, Assoc::Right),
+            _ => return None,
+        })
+    }
+
+    /// Returns true if this token is a literal, such as an integer or a string
+    pub fn is_literal(self) -> bool {
+        matches!(self, TOKEN_FLOAT | TOKEN_INTEGER | TOKEN_PATH | TOKEN_URI)
+    }
     /// Returns true if this token should be used as a function argument.
     /// ```ignore
     /// Example:
@@ -131,9 +311,63 @@ impl SyntaxKind {
     /// Returns true if this token is a comment, whitespace, or similar, and
     /// should be skipped over by the parser.
     pub fn is_trivia(self) -> bool {
-        match self {
-            TOKEN_COMMENT | TOKEN_ERROR | TOKEN_WHITESPACE => true,
-            _ => false,
+        matches!(self, TOKEN_COMMENT | TOKEN_ERROR | TOKEN_WHITESPACE)
+    }
+}
+
+impl fmt::Display for SyntaxKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{tokenizer::Tokenizer, NixLanguage};
+    use rowan::Language;
+
+    #[test]
+    fn token_text() {
+        assert_eq!(T![->].token_text(), Some("->"));
+        assert_eq!(T!["//"].token_text(), Some("//"));
+        assert_eq!(T![let].token_text(), Some("let"));
+        assert_eq!(TOKEN_IDENT.token_text(), None);
+        assert_eq!(TOKEN_STRING_START.token_text(), None);
+
+        // Every fixed token lexes back to itself
+        for raw in 0..__LAST as u16 {
+            let kind = NixLanguage::kind_from_raw(rowan::SyntaxKind(raw));
+            let text = match kind.token_text() {
+                Some(text) if !matches!(kind, TOKEN_DYNAMIC_START | TOKEN_DYNAMIC_END) => text,
+                _ => continue,
+            };
+            let tokens: Vec<_> = Tokenizer::new(text).collect();
+            let kind = if kind == TOKEN_INTERPOL_END { TOKEN_CURLY_B_CLOSE } else { kind };
+            if kind == TOKEN_INTERPOL_START {
+                assert_eq!(tokens[0], (TOKEN_DYNAMIC_START, text.into()));
+            } else {
+                assert_eq!(tokens, vec![(kind, text.into())]);
+            }
+            assert_eq!(kind.is_keyword(), text.chars().all(|c| c.is_ascii_alphabetic()));
         }
     }
+    #[test]
+    fn names() {
+        assert_eq!(TOKEN_SEMICOLON.name(), "`;`");
+        assert_eq!(TOKEN_IDENT.name(), "identifier");
+        assert_eq!(NODE_STRING.to_string(), "string");
+    }
+    #[test]
+    fn operators() {
+        assert!(T![!].is_operator());
+        assert!(T![?].is_operator());
+        assert!(!T![=].is_operator());
+        assert!(!T![if].is_operator());
+
+        assert!(T![*].binary_op().unwrap().0 > T![+].binary_op().unwrap().0);
+        assert_eq!(T![==].binary_op(), Some((5, Assoc::None)));
+        assert_eq!(T![<|].binary_op(), Some((1, Assoc::Right)));
+        assert_eq!(T![!].binary_op(), None);
+    }
 }
@@ -1,14 +1,32 @@
+// `map_or(true, ..)` and `repeat(..).take(n)` are kept over `is_none_or` and
+// `repeat_n`, which need Rust 1.82
+#![allow(clippy::unnecessary_map_or, clippy::manual_repeat_n)]
+
 #[macro_use]
 mod macros;
+pub mod comments;
+pub mod completeness;
+pub mod diagnostic;
+pub mod doc;
+mod edit;
 mod kinds;
+pub mod line_index;
+pub mod make;
 pub mod parser;
+mod reparse;
+pub mod report;
 pub mod tokenizer;
 pub mod types;
 pub mod value;
+pub mod visit;
 
 pub use self::{
-    kinds::SyntaxKind,
-    parser::AST,
+    completeness::Completeness,
+    diagnostic::Diagnostic,
+    kinds::{Assoc, SyntaxKind},
+    line_index::LineIndex,
+    parser::{Cancelled, Fragment, ParseOptions, AST},
+    reparse::TextEdit,
     value::{StrPart, Value as NixValue},
 };
 
@@ -17,7 +35,11 @@ pub use rowan::{
     TokenAtOffset, WalkEvent,
 };
 
-use self::tokenizer::Tokenizer;
+use self::{
+    parser::FragmentKind,
+    tokenizer::Tokenizer,
+    types::{Expr, Key, KeyValue, Pattern, Str, TypedNode, Wrapper},
+};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub enum NixLanguage {}
@@ -40,7 +62,106 @@ pub type SyntaxElement = rowan::NodeOrToken<SyntaxNode, SyntaxToken>;
 
 /// A convenience function for first tokenizing and then parsing given input
 pub fn parse(input: &str) -> AST {
-    parser::parse(Tokenizer::new(input))
+    parse_with_options(input, ParseOptions::default())
+}
+/// Like `parse`, but accepting the syntax chosen in `options`
+pub fn parse_with_options(input: &str, options: ParseOptions) -> AST {
+    if options.max_input_size.is_some_and(|max| input.len() > max) {
+        return parser::unparsed(input, options, FragmentKind::Expr);
+    }
+    let mut tokenizer = Tokenizer::new(input);
+    let ast = parser::parse_with_options(tokenizer.borrowed(), options);
+    with_lexical_errors(ast, &tokenizer)
+}
+/// Like `parse_with_options`, but stop early and return `Err(Cancelled)` once
+/// `is_cancelled` returns true. See `parser::parse_cancellable`.
+pub fn parse_cancellable(
+    input: &str,
+    options: ParseOptions,
+    is_cancelled: impl FnMut() -> bool,
+) -> Result<AST, Cancelled> {
+    if options.max_input_size.is_some_and(|max| input.len() > max) {
+        return Ok(parser::unparsed(input, options, FragmentKind::Expr));
+    }
+    let mut tokenizer = Tokenizer::new(input);
+    let ast = parser::parse_cancellable(tokenizer.borrowed(), options, is_cancelled)?;
+    Ok(with_lexical_errors(ast, &tokenizer))
+}
+/// Parse a single expression, like `parse` but returning the expression
+/// itself. This is None if there is no expression at all, such as for empty
+/// input.
+pub fn parse_expr(input: &str) -> Fragment<Option<Expr>> {
+    parse_expr_with_options(input, ParseOptions::default())
+}
+/// Like `parse_expr`, but accepting the syntax chosen in `options`
+pub fn parse_expr_with_options(input: &str, options: ParseOptions) -> Fragment<Option<Expr>> {
+    let ast = parse_with_options(input, options);
+    Fragment { node: ast.root().inner(), errors: ast.errors() }
+}
+/// Parse an attribute path on its own, like `services.nginx.enable`
+pub fn parse_attrpath(input: &str) -> Fragment<Key> {
+    parse_attrpath_with_options(input, ParseOptions::default())
+}
+/// Like `parse_attrpath`, but accepting the syntax chosen in `options`
+pub fn parse_attrpath_with_options(input: &str, options: ParseOptions) -> Fragment<Key> {
+    parse_fragment(input, Tokenizer::new(input), options, FragmentKind::AttrPath)
+}
+/// Parse a lambda pattern on its own, like `{ a, b ? 1, ... }` or
+/// `args@{ a, ... }`
+pub fn parse_pattern(input: &str) -> Fragment<Pattern> {
+    parse_pattern_with_options(input, ParseOptions::default())
+}
+/// Like `parse_pattern`, but accepting the syntax chosen in `options`
+pub fn parse_pattern_with_options(input: &str, options: ParseOptions) -> Fragment<Pattern> {
+    parse_fragment(input, Tokenizer::new(input), options, FragmentKind::Pattern)
+}
+/// Parse a single binding, like `x = 1;`
+pub fn parse_binding(input: &str) -> Fragment<KeyValue> {
+    parse_binding_with_options(input, ParseOptions::default())
+}
+/// Like `parse_binding`, but accepting the syntax chosen in `options`
+pub fn parse_binding_with_options(input: &str, options: ParseOptions) -> Fragment<KeyValue> {
+    parse_fragment(input, Tokenizer::new(input), options, FragmentKind::Binding)
+}
+/// Parse the contents of a `"` string without the quotes around it, such as
+/// `hello ${name}`
+pub fn parse_string_body(input: &str) -> Fragment<Str> {
+    parse_string_body_with_options(input, ParseOptions::default())
+}
+/// Like `parse_string_body`, but accepting the syntax chosen in `options`
+pub fn parse_string_body_with_options(input: &str, options: ParseOptions) -> Fragment<Str> {
+    let tokenizer = Tokenizer::new_string_body(input, false);
+    parse_fragment(input, tokenizer, options, FragmentKind::StringBody)
+}
+fn parse_fragment<N: TypedNode>(
+    input: &str,
+    mut tokenizer: Tokenizer,
+    options: ParseOptions,
+    kind: FragmentKind,
+) -> Fragment<N> {
+    let ast = if options.max_input_size.is_some_and(|max| input.len() > max) {
+        parser::unparsed(input, options, kind)
+    } else {
+        let ast = parser::parse_fragment(tokenizer.borrowed(), options, kind);
+        with_lexical_errors(ast, &tokenizer)
+    };
+    // The parser always starts the node, even if it can't parse anything
+    let node = ast.node().children().find_map(N::cast).unwrap();
+    Fragment { node, errors: ast.errors() }
+}
+/// Add the errors the tokenizer found, in the order they appear in the text
+fn with_lexical_errors(mut ast: AST, tokenizer: &Tokenizer) -> AST {
+    for &(err, range) in tokenizer.errors() {
+        let i = ast
+            .errors
+            .iter()
+            .position(|other| {
+                reparse::error_range(other).map_or(true, |r| r.start() >= range.end())
+            })
+            .unwrap_or(ast.errors.len());
+        ast.errors.insert(i, parser::ParseError::Lexical(err, range));
+    }
+    ast
 }
 
 #[cfg(test)]
@@ -51,10 +172,10 @@ mod tests {
     fn interpolation() {
         let ast = parse(include_str!("../test_data/general/interpolation.nix"));
 
-        let let_in = ast.root().inner().and_then(LetIn::cast).unwrap();
-        let set = let_in.body().and_then(AttrSet::cast).unwrap();
+        let let_in = ast.root().inner().and_then(Expr::downcast::<LetIn>).unwrap();
+        let set = let_in.body().and_then(Expr::downcast::<AttrSet>).unwrap();
         let entry = set.entries().nth(1).unwrap();
-        let value = entry.value().and_then(Str::cast).unwrap();
+        let value = entry.value().and_then(Expr::downcast::<Str>).unwrap();
 
         match &*value.parts() {
             &[
@@ -71,41 +192,190 @@ mod tests {
         }
     }
     #[test]
+    fn path_interpolation() {
+        let ast = parse("[ ./pkgs/${name}/default.nix ./plain/path ]");
+        let list = ast.root().inner().and_then(Expr::downcast::<List>).unwrap();
+        let mut items = list.items();
+
+        let path = items.next().and_then(Expr::downcast::<Path>).unwrap();
+        match &*path.parts() {
+            &[StrPart::Literal(ref s1), StrPart::Ast(ref interpol), StrPart::Literal(ref s2)]
+                if s1 == "./pkgs/" && interpol.to_string() == "${name}" && s2 == "/default.nix" => {
+            }
+            parts => panic!("did not match: {:#?}", parts),
+        }
+
+        let plain = items.next().and_then(Expr::downcast::<Value>).unwrap();
+        assert_eq!(
+            plain.to_value(),
+            Ok(NixValue::Path(crate::value::Anchor::Relative, "./plain/path".into()))
+        );
+    }
+    #[test]
     fn inherit() {
         let ast = parse(include_str!("../test_data/general/inherit.nix"));
 
-        let let_in = ast.root().inner().and_then(LetIn::cast).unwrap();
-        let set = let_in.body().and_then(AttrSet::cast).unwrap();
+        let let_in = ast.root().inner().and_then(Expr::downcast::<LetIn>).unwrap();
+        let set = let_in.body().and_then(Expr::downcast::<AttrSet>).unwrap();
         let inherit = set.inherits().nth(1).unwrap();
 
-        let from = inherit.from().unwrap().inner().and_then(Ident::cast).unwrap();
-        assert_eq!(from.as_str(), "set");
+        let from = inherit.from().unwrap().inner().and_then(Expr::downcast::<Ident>).unwrap();
+        assert_eq!(from.as_str(), Some("set"));
         let mut children = inherit.idents();
-        assert_eq!(children.next().unwrap().as_str(), "z");
-        assert_eq!(children.next().unwrap().as_str(), "a");
+        assert_eq!(children.next().unwrap().as_str(), Some("z"));
+        assert_eq!(children.next().unwrap().as_str(), Some("a"));
         assert!(children.next().is_none());
     }
     #[test]
+    fn isset() {
+        let ast = parse(r#"set ? a."b c".${d}"#);
+        let isset = ast.root().inner().and_then(Expr::downcast::<BinOp>).unwrap();
+        assert_eq!(isset.operator(), Some(BinOpKind::IsSet));
+
+        let key = isset.key().unwrap();
+        let mut path = key.path();
+        assert_eq!(path.next().and_then(KeyPart::downcast::<Ident>).unwrap().as_str(), Some("a"));
+        assert!(matches!(path.next(), Some(KeyPart::Str(_))));
+        assert!(matches!(path.next(), Some(KeyPart::Dynamic(_))));
+        assert!(path.next().is_none());
+    }
+    #[test]
+    fn typed_enums() {
+        let ast = parse("{ a, ... }@args: let inherit (x) y; b.${c} = [ 1 (d: d.e) ]; in b");
+        let lambda = ast.root().inner().and_then(Expr::downcast::<Lambda>).unwrap();
+        let pattern = lambda.arg().and_then(Param::downcast::<Pattern>).unwrap();
+        assert_eq!(pattern.at().unwrap().as_str(), Some("args"));
+
+        let let_in = lambda.body().and_then(Expr::downcast::<LetIn>).unwrap();
+        let mut bindings = let_in.bindings();
+        assert!(matches!(bindings.next(), Some(Binding::Inherit(_))));
+        let entry = bindings.next().and_then(Binding::downcast::<KeyValue>).unwrap();
+        assert!(bindings.next().is_none());
+
+        let parts: Vec<_> = entry.key().unwrap().path().collect();
+        assert!(matches!(&*parts, [KeyPart::Ident(_), KeyPart::Dynamic(_)]));
+        let items: Vec<_> =
+            entry.value().and_then(Expr::downcast::<List>).unwrap().items().collect();
+        assert!(matches!(&*items, [Expr::Value(_), Expr::Paren(_)]));
+
+        let inner = items[1].clone().downcast::<Paren>().and_then(|paren| paren.inner());
+        let select = inner
+            .and_then(Expr::downcast::<Lambda>)
+            .and_then(|lambda| lambda.body())
+            .and_then(Expr::downcast::<Select>)
+            .unwrap();
+        assert!(matches!(select.set(), Some(Expr::Ident(_))));
+        assert!(matches!(select.index(), Some(KeyPart::Ident(_))));
+        assert_eq!(Expr::from(select.clone()).node(), select.node());
+    }
+    #[test]
     fn math() {
         let ast = parse(include_str!("../test_data/general/math.nix"));
-        let root = ast.root().inner().and_then(BinOp::cast).unwrap();
-        let operation = root.lhs().and_then(BinOp::cast).unwrap();
+        let root = ast.root().inner().and_then(Expr::downcast::<BinOp>).unwrap();
+        let operation = root.lhs().and_then(Expr::downcast::<BinOp>).unwrap();
 
-        assert_eq!(root.operator(), BinOpKind::Add);
-        assert_eq!(operation.operator(), BinOpKind::Add);
+        assert_eq!(root.operator(), Some(BinOpKind::Add));
+        assert_eq!(operation.operator(), Some(BinOpKind::Add));
 
-        let lhs = operation.lhs().and_then(Value::cast).unwrap();
+        let lhs = operation.lhs().and_then(Expr::downcast::<Value>).unwrap();
         assert_eq!(lhs.to_value(), Ok(NixValue::Integer(1)));
 
-        let rhs = operation.rhs().and_then(BinOp::cast).unwrap();
-        assert_eq!(rhs.operator(), BinOpKind::Mul);
+        let rhs = operation.rhs().and_then(Expr::downcast::<BinOp>).unwrap();
+        assert_eq!(rhs.operator(), Some(BinOpKind::Mul));
+    }
+    #[test]
+    fn cancel() {
+        use super::{parse_cancellable, Cancelled, ParseOptions};
+        use std::sync::atomic::{AtomicBool, Ordering};
+
+        let cancelled = AtomicBool::new(false);
+        let is_cancelled = || cancelled.load(Ordering::Relaxed);
+        let ast = parse_cancellable("{ a = 1; }", ParseOptions::default(), is_cancelled).unwrap();
+        assert_eq!(ast.errors(), Vec::new());
+
+        cancelled.store(true, Ordering::Relaxed);
+        let result = parse_cancellable("{ a = 1; }", ParseOptions::default(), is_cancelled);
+        assert_eq!(result.err(), Some(Cancelled));
+    }
+    #[test]
+    fn fragments() {
+        use super::{parse_attrpath, parse_binding, parse_expr, parse_pattern, parse_string_body};
+        use crate::{parser::ParseError, TextRange};
+
+        let extra = |start: u32, end: u32| {
+            vec![ParseError::UnexpectedExtra(TextRange::from_to(start.into(), end.into()))]
+        };
+
+        let key = parse_attrpath(" services.nginx.\"enable\" ");
+        assert_eq!(key.errors, Vec::new());
+        let path: Vec<_> = key.node.path().map(|part| part.node().to_string()).collect();
+        assert_eq!(path, ["services", "nginx", "\"enable\""]);
+        assert_eq!(parse_attrpath("a.b c").errors, extra(4, 5));
+
+        let pattern = parse_pattern("args@{ a, b ? 1, ... }");
+        assert_eq!(pattern.errors, Vec::new());
+        assert_eq!(pattern.node.at().unwrap().as_str(), Some("args"));
+        assert!(pattern.node.ellipsis());
+        assert_eq!(pattern.node.entries().count(), 2);
+        assert_eq!(parse_pattern("{ a }@args").errors, Vec::new());
+        assert_eq!(parse_pattern("{ a } b").errors, extra(6, 7));
+
+        let binding = parse_binding("a.b = { c = 2; };");
+        assert_eq!(binding.errors, Vec::new());
+        assert_eq!(binding.node.key().unwrap().node().to_string(), "a.b");
+        assert!(binding.node.value().and_then(Expr::downcast::<AttrSet>).is_some());
+        assert_eq!(parse_binding("x = 1; y").errors, extra(7, 8));
+        assert!(!parse_binding("x = 1").errors.is_empty());
+
+        let expr = parse_expr("1 + 2");
+        assert_eq!(expr.errors, Vec::new());
+        assert!(expr.node.and_then(Expr::downcast::<BinOp>).is_some());
+        assert!(parse_expr("").node.is_none());
+        assert_eq!(parse_expr("1 )").errors, extra(2, 3));
+
+        let string = parse_string_body("hello ${name}!");
+        assert_eq!(string.errors, Vec::new());
+        match &*string.node.parts() {
+            [StrPart::Literal(hello), StrPart::Ast(_), StrPart::Literal(end)]
+                if hello == "hello " && end == "!" => {}
+            parts => panic!("did not match: {:#?}", parts),
+        }
+        // `\$` escapes the interpolation
+        let string = parse_string_body("a \\${b}\\n");
+        assert_eq!(string.errors, Vec::new());
+        assert_eq!(string.node.parts(), vec![StrPart::Literal("a ${b}\n".into())]);
+        assert_eq!(parse_string_body("a\"b").errors, extra(1, 3));
+    }
+    #[test]
+    fn fragments_with_options() {
+        use super::*;
+        use crate::parser::{Limit, ParseError};
+
+        let pipes = ParseOptions { pipe_operators: true, ..ParseOptions::default() };
+        assert!(!parse_expr("x |> f").errors.is_empty());
+        assert_eq!(parse_expr_with_options("x |> f", pipes).errors, Vec::new());
+        assert_eq!(parse_binding_with_options("a = x |> f;", pipes).errors, Vec::new());
+        assert_eq!(parse_pattern_with_options("{ a ? x |> f }", pipes).errors, Vec::new());
+        assert_eq!(parse_attrpath_with_options("a.${x |> f}", pipes).errors, Vec::new());
+        assert_eq!(parse_string_body_with_options("${x |> f}", pipes).errors, Vec::new());
+
+        let no_or = ParseOptions { or_as_ident: false, ..ParseOptions::default() };
+        assert_eq!(parse_binding("or = 1;").errors, Vec::new());
+        assert!(!parse_binding_with_options("or = 1;", no_or).errors.is_empty());
+
+        let no_legacy_let = ParseOptions { legacy_let: false, ..ParseOptions::default() };
+        assert!(!parse_binding_with_options("a = let { };", no_legacy_let).errors.is_empty());
+
+        let small = ParseOptions { max_input_size: Some(3), ..ParseOptions::default() };
+        let key = parse_attrpath_with_options("a.b.c", small);
+        assert_eq!(key.node.node().to_string(), "");
+        assert_eq!(key.node.node().parent().unwrap().to_string(), "a.b.c");
+        assert!(matches!(key.errors[..], [ParseError::LimitExceeded(Limit::InputSize, _)]));
+        assert_eq!(parse_attrpath_with_options("a.b", small).errors, Vec::new());
     }
     #[test]
     fn t_macro() {
         assert_eq!(T![@], SyntaxKind::TOKEN_AT);
-        assert!(match SyntaxKind::TOKEN_PAREN_OPEN {
-            T!["("] => true,
-            _ => false,
-        });
+        assert!(matches!(SyntaxKind::TOKEN_PAREN_OPEN, T!["("]));
     }
 }
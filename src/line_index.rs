@@ -0,0 +1,274 @@
+//! Conversions between text offsets and line/column positions
+
+use crate::{TextRange, TextUnit};
+
+/// The unit a column is counted in
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ColumnUnit {
+    /// Bytes of UTF-8, like `TextUnit`
+    Utf8,
+    /// UTF-16 code units, as used by the language server protocol
+    Utf16,
+    /// Unicode scalar values, like `str::chars`
+    Char,
+}
+
+/// A zero-based line and column
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct LineCol {
+    pub line: u32,
+    pub col: u32,
+}
+
+/// The start and end position of a range
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct LineColRange {
+    pub start: LineCol,
+    pub end: LineCol,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct WideChar {
+    start: TextUnit,
+    len_utf8: u8,
+    len_utf16: u8,
+}
+impl WideChar {
+    fn len(&self, unit: ColumnUnit) -> u32 {
+        match unit {
+            ColumnUnit::Utf8 => self.len_utf8 as u32,
+            ColumnUnit::Utf16 => self.len_utf16 as u32,
+            ColumnUnit::Char => 1,
+        }
+    }
+}
+
+/// An index of where lines start in a text, built once to look up positions
+/// in logarithmic time. Both `\n` and `\r\n` end a line, and neither counts
+/// as part of the line's columns.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LineIndex {
+    /// The start of every line, the first being 0
+    starts: Vec<TextUnit>,
+    /// The end of every line, before the line ending
+    ends: Vec<TextUnit>,
+    /// Every character that is more than one byte, in order
+    wide: Vec<WideChar>,
+    /// How many more bytes than UTF-16 code units there are before each
+    /// wide character, with a final entry for the total
+    extra_utf16: Vec<u32>,
+    /// How many more bytes than chars there are before each wide character,
+    /// with a final entry for the total
+    extra_char: Vec<u32>,
+}
+
+impl LineIndex {
+    /// Build an index of the text
+    pub fn new(text: &str) -> Self {
+        let mut index = Self {
+            starts: vec![0.into()],
+            ends: Vec::new(),
+            wide: Vec::new(),
+            extra_utf16: vec![0],
+            extra_char: vec![0],
+        };
+        let (mut utf16, mut chars) = (0, 0);
+        let mut prev = None;
+        for (i, c) in text.char_indices() {
+            let offset = TextUnit::from_usize(i);
+            if c == '\n' {
+                let cr = prev == Some('\r');
+                index.ends.push(offset - TextUnit::from_usize(cr as usize));
+                index.starts.push(offset + TextUnit::of_char(c));
+            } else if !c.is_ascii() {
+                let ch = WideChar {
+                    start: offset,
+                    len_utf8: c.len_utf8() as u8,
+                    len_utf16: c.len_utf16() as u8,
+                };
+                utf16 += ch.len(ColumnUnit::Utf8) - ch.len(ColumnUnit::Utf16);
+                chars += ch.len(ColumnUnit::Utf8) - 1;
+                index.wide.push(ch);
+                index.extra_utf16.push(utf16);
+                index.extra_char.push(chars);
+            }
+            prev = Some(c);
+        }
+        index.ends.push(TextUnit::of_str(text));
+        index
+    }
+    /// Return the number of lines, which is one more than the number of line
+    /// endings
+    pub fn line_count(&self) -> u32 {
+        self.starts.len() as u32
+    }
+    /// Return the range of a line, not including the line ending
+    pub fn line_range(&self, line: u32) -> Option<TextRange> {
+        let line = line as usize;
+        Some(TextRange::from_to(*self.starts.get(line)?, self.ends[line]))
+    }
+    /// Return the line and column of an offset. An offset inside a character,
+    /// or inside a `\r\n`, is rounded down.
+    ///
+    /// Panics if the offset is past the end of the text
+    pub fn line_col(&self, offset: TextUnit, unit: ColumnUnit) -> LineCol {
+        let end = *self.ends.last().unwrap();
+        assert!(offset <= end, "offset {} is out of bounds", offset);
+        let line = self.starts.partition_point(|&start| start <= offset) - 1;
+        let offset = offset.min(self.ends[line]);
+
+        let mut wide = self.wide_before(offset);
+        let offset = match wide.checked_sub(1).map(|i| self.wide[i]) {
+            Some(ch) if ch.start + TextUnit::from(ch.len_utf8 as u32) > offset => {
+                wide -= 1;
+                ch.start
+            }
+            _ => offset,
+        };
+        let first = self.wide_before(self.starts[line]);
+        let bytes = (offset - self.starts[line]).to_usize() as u32;
+        LineCol { line: line as u32, col: bytes - self.extra(unit, first, wide) }
+    }
+    /// Return the offset of a line and column, or None if that position is
+    /// past the end of the line or inside a character
+    pub fn offset(&self, pos: LineCol, unit: ColumnUnit) -> Option<TextUnit> {
+        let line = pos.line as usize;
+        let (start, end) = (*self.starts.get(line)?, self.ends[line]);
+        let first = self.wide_before(start);
+        let last = self.wide_before(end);
+
+        // The column of each wide character on this line only ever grows
+        let col_of = |i: usize| {
+            let bytes = (self.wide[i].start - start).to_usize() as u32;
+            bytes - self.extra(unit, first, i)
+        };
+        let (mut before, mut after) = (first, last);
+        while before < after {
+            let mid = before + (after - before) / 2;
+            if col_of(mid) < pos.col {
+                before = mid + 1;
+            } else {
+                after = mid;
+            }
+        }
+        if before > first && pos.col < col_of(before - 1) + self.wide[before - 1].len(unit) {
+            return None;
+        }
+        let bytes = pos.col.checked_add(self.extra(unit, first, before))?;
+        let offset = start + TextUnit::from(bytes);
+        if offset > end {
+            return None;
+        }
+        Some(offset)
+    }
+    /// Return the line and column of both ends of a range
+    pub fn range(&self, range: TextRange, unit: ColumnUnit) -> LineColRange {
+        LineColRange {
+            start: self.line_col(range.start(), unit),
+            end: self.line_col(range.end(), unit),
+        }
+    }
+
+    /// The number of wide characters that start before an offset
+    fn wide_before(&self, offset: TextUnit) -> usize {
+        self.wide.partition_point(|ch| ch.start < offset)
+    }
+    /// How many more bytes than `unit`s the wide characters `from..to` take
+    fn extra(&self, unit: ColumnUnit, from: usize, to: usize) -> u32 {
+        let extra = match unit {
+            ColumnUnit::Utf8 => return 0,
+            ColumnUnit::Utf16 => &self.extra_utf16,
+            ColumnUnit::Char => &self.extra_char,
+        };
+        extra[to] - extra[from]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pos(line: u32, col: u32) -> LineCol {
+        LineCol { line, col }
+    }
+
+    #[test]
+    fn lines() {
+        let index = LineIndex::new("a\r\nbc\n\nd");
+        assert_eq!(index.line_count(), 4);
+        assert_eq!(index.line_range(0), Some(TextRange::from_to(0.into(), 1.into())));
+        assert_eq!(index.line_range(1), Some(TextRange::from_to(3.into(), 5.into())));
+        assert_eq!(index.line_range(2), Some(TextRange::from_to(6.into(), 6.into())));
+        assert_eq!(index.line_range(3), Some(TextRange::from_to(7.into(), 8.into())));
+        assert_eq!(index.line_range(4), None);
+
+        let expected = [(0, 0), (0, 1), (0, 1), (1, 0), (1, 1), (1, 2), (2, 0), (3, 0), (3, 1)];
+        for (offset, &(line, col)) in expected.iter().enumerate() {
+            let offset = TextUnit::from_usize(offset);
+            assert_eq!(index.line_col(offset, ColumnUnit::Utf8), pos(line, col), "{}", offset);
+        }
+        assert_eq!(index.offset(pos(0, 1), ColumnUnit::Utf8), Some(1.into()));
+        assert_eq!(index.offset(pos(0, 2), ColumnUnit::Utf8), None);
+        assert_eq!(index.offset(pos(2, 0), ColumnUnit::Utf8), Some(6.into()));
+        assert_eq!(index.offset(pos(3, 1), ColumnUnit::Utf8), Some(8.into()));
+        assert_eq!(index.offset(pos(4, 0), ColumnUnit::Utf8), None);
+
+        let empty = LineIndex::new("");
+        assert_eq!(empty.line_count(), 1);
+        assert_eq!(empty.line_col(0.into(), ColumnUnit::Char), pos(0, 0));
+    }
+    #[test]
+    fn units() {
+        // 'é' is 2 bytes and 1 UTF-16 unit, '😀' is 4 bytes and 2 UTF-16 units
+        let text = "é\na😀b\n😀";
+        let index = LineIndex::new(text);
+        let b = TextUnit::from_usize(text.find('b').unwrap());
+        assert_eq!(index.line_col(b, ColumnUnit::Utf8), pos(1, 5));
+        assert_eq!(index.line_col(b, ColumnUnit::Utf16), pos(1, 3));
+        assert_eq!(index.line_col(b, ColumnUnit::Char), pos(1, 2));
+        for &unit in &[ColumnUnit::Utf8, ColumnUnit::Utf16, ColumnUnit::Char] {
+            assert_eq!(index.offset(index.line_col(b, unit), unit), Some(b));
+        }
+        // Inside a character
+        assert_eq!(index.line_col(1.into(), ColumnUnit::Utf16), pos(0, 0));
+        assert_eq!(index.line_col(6.into(), ColumnUnit::Char), pos(1, 1));
+        assert_eq!(index.offset(pos(1, 2), ColumnUnit::Utf8), None);
+        assert_eq!(index.offset(pos(1, 2), ColumnUnit::Utf16), None);
+        assert_eq!(index.offset(pos(2, 1), ColumnUnit::Utf16), None);
+        assert_eq!(index.offset(pos(2, 2), ColumnUnit::Utf16), Some(TextUnit::of_str(text)));
+        assert_eq!(index.offset(pos(2, 1), ColumnUnit::Char), Some(TextUnit::of_str(text)));
+
+        let range = TextRange::from_to(1.into(), b);
+        assert_eq!(
+            index.range(range, ColumnUnit::Utf16),
+            LineColRange { start: pos(0, 0), end: pos(1, 3) }
+        );
+    }
+    #[test]
+    fn round_trip() {
+        let text = "let\r\n  ä = \"😀\";\n  ö = ''\n    ∀x\r\n  '';\nin ä";
+        let index = LineIndex::new(text);
+        for &unit in &[ColumnUnit::Utf8, ColumnUnit::Utf16, ColumnUnit::Char] {
+            for (offset, c) in text.char_indices() {
+                let offset = TextUnit::from_usize(offset);
+                let pos = index.line_col(offset, unit);
+                if c == '\n' && text[..offset.to_usize()].ends_with('\r') {
+                    continue;
+                }
+                assert_eq!(index.offset(pos, unit), Some(offset), "{:?} {:?}", unit, pos);
+
+                // Compare with doing it the slow way
+                let before = &text[..offset.to_usize()];
+                let line = before.matches('\n').count() as u32;
+                let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+                let line_text = before[line_start..].trim_end_matches('\r');
+                let col = match unit {
+                    ColumnUnit::Utf8 => line_text.len(),
+                    ColumnUnit::Utf16 => line_text.encode_utf16().count(),
+                    ColumnUnit::Char => line_text.chars().count(),
+                };
+                assert_eq!(pos, LineCol { line, col: col as u32 });
+            }
+        }
+    }
+}
@@ -37,6 +37,7 @@ macro_rules! T {
 
     (&&)      => ($crate::SyntaxKind::TOKEN_AND);
     (==)      => ($crate::SyntaxKind::TOKEN_EQUAL);
+    (->)      => ($crate::SyntaxKind::TOKEN_IMPLICATION);
     (=>)      => ($crate::SyntaxKind::TOKEN_IMPLICATION);
     (<)       => ($crate::SyntaxKind::TOKEN_LESS);
     (<=)      => ($crate::SyntaxKind::TOKEN_LESS_OR_EQ);
@@ -44,4 +45,6 @@ macro_rules! T {
     (>=)      => ($crate::SyntaxKind::TOKEN_MORE_OR_EQ);
     (!=)      => ($crate::SyntaxKind::TOKEN_NOT_EQUAL);
     (||)      => ($crate::SyntaxKind::TOKEN_OR);
+    (|>)      => ($crate::SyntaxKind::TOKEN_PIPE_RIGHT);
+    (<|)      => ($crate::SyntaxKind::TOKEN_PIPE_LEFT);
 }
@@ -0,0 +1,724 @@
+//! Constructors for well-formed nodes, so code can be generated without
+//! pasting strings together. Every node is laid out on a single line with
+//! single spaces, exactly like the parser would see that text, and
+//! parenthesis are added where operator precedence requires them.
+
+use rowan::{GreenNode, GreenToken, Language};
+
+use crate::{
+    edit::GreenElement,
+    types::*,
+    value::StrPart,
+    Assoc, NixLanguage, NodeOrToken, SmolStr,
+    SyntaxKind::{self, *},
+    SyntaxNode,
+};
+
+/// A small tree builder which, unlike rowan's, can embed existing nodes.
+/// Whitespace is queued and only emitted in front of the next element, on
+/// the same level as the parser would put it.
+struct Builder {
+    stack: Vec<(SyntaxKind, Vec<GreenElement>)>,
+    space: bool,
+}
+impl Builder {
+    fn new(kind: SyntaxKind) -> Self {
+        Self { stack: vec![(kind, Vec::new())], space: false }
+    }
+    fn flush_space(&mut self) {
+        if self.space {
+            self.space = false;
+            let space = GreenToken::new(NixLanguage::kind_to_raw(TOKEN_WHITESPACE), " ".into());
+            self.stack.last_mut().unwrap().1.push(NodeOrToken::Token(space));
+        }
+    }
+    fn push(&mut self, element: GreenElement) {
+        self.flush_space();
+        self.stack.last_mut().unwrap().1.push(element);
+    }
+    fn token(mut self, kind: SyntaxKind, text: &str) -> Self {
+        let token = GreenToken::new(NixLanguage::kind_to_raw(kind), SmolStr::new(text));
+        self.push(NodeOrToken::Token(token));
+        self
+    }
+    fn space(mut self) -> Self {
+        self.space = true;
+        self
+    }
+    fn node(mut self, node: GreenNode) -> Self {
+        self.push(NodeOrToken::Node(node));
+        self
+    }
+    fn start(mut self, kind: SyntaxKind) -> Self {
+        self.flush_space();
+        self.stack.push((kind, Vec::new()));
+        self
+    }
+    fn finish(mut self) -> Self {
+        let (kind, children) = self.stack.pop().unwrap();
+        let node = GreenNode::new(NixLanguage::kind_to_raw(kind), children);
+        self.stack.last_mut().unwrap().1.push(NodeOrToken::Node(node));
+        self
+    }
+    fn green(mut self) -> GreenNode {
+        assert_eq!(self.stack.len(), 1, "unfinished node");
+        let (kind, children) = self.stack.pop().unwrap();
+        GreenNode::new(NixLanguage::kind_to_raw(kind), children)
+    }
+    fn build<T: TypedNode>(self) -> T {
+        T::cast(SyntaxNode::new_root(self.green())).unwrap()
+    }
+}
+
+/// How tightly an expression binds, to decide where parenthesis are needed.
+/// Expressions such as lambdas extend as far to the right as possible, and
+/// have the lowest level.
+fn level(node: &SyntaxNode) -> u8 {
+    match node.kind() {
+        NODE_PAREN
+        | NODE_ATTR_SET
+        | NODE_LIST
+        | NODE_STRING
+        | NODE_PATH_WITH_INTERPOL
+        | NODE_LITERAL
+        | NODE_IDENT
+        | NODE_SELECT
+        | NODE_DYNAMIC => 15,
+        NODE_APPLY | NODE_OR_DEFAULT => 14,
+        NODE_UNARY_OP => match UnaryOp::cast(node.clone()).and_then(|op| op.operator()) {
+            Some(UnaryOpKind::Negate) => 13,
+            Some(UnaryOpKind::Invert) => 8,
+            None => 0,
+        },
+        NODE_BIN_OP => BinOp::cast(node.clone()).and_then(|op| op.operator()).map_or(0, precedence),
+        _ => 0,
+    }
+}
+fn precedence(op: BinOpKind) -> u8 {
+    op.to_token().binary_op().unwrap().0
+}
+fn is_literal_of(node: &SyntaxNode, kinds: &[SyntaxKind]) -> bool {
+    if node.kind() == NODE_PATH_WITH_INTERPOL {
+        return kinds.contains(&TOKEN_PATH);
+    }
+    Value::cast(node.clone())
+        .and_then(|value| value.first_token())
+        .is_some_and(|token| kinds.contains(&token.kind()))
+}
+
+/// Return the node, wrapped in parenthesis unless it binds at least as
+/// tightly as `min`
+fn operand(node: &SyntaxNode, min: u8) -> GreenNode {
+    if level(node) >= min {
+        node.green().clone()
+    } else {
+        parenthesized(node)
+    }
+}
+fn parenthesized(inner: &SyntaxNode) -> GreenNode {
+    Builder::new(NODE_PAREN)
+        .token(TOKEN_PAREN_OPEN, "(")
+        .node(inner.green().clone())
+        .token(TOKEN_PAREN_CLOSE, ")")
+        .green()
+}
+
+/// Returns true if `name` can be written as a plain identifier
+pub fn is_valid_ident(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars.next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-' || c == '\'')
+        && !matches!(
+            name,
+            "assert" | "else" | "if" | "in" | "inherit" | "let" | "rec" | "then" | "with"
+        )
+}
+
+/// Create an identifier. Panics if `name` isn't a valid identifier, see
+/// `is_valid_ident`.
+pub fn ident(name: &str) -> Ident {
+    assert!(is_valid_ident(name), "invalid identifier: {:?}", name);
+    Builder::new(NODE_IDENT).token(TOKEN_IDENT, name).build()
+}
+/// Escape the content of a string. A trailing `$` is escaped too if an
+/// interpolation follows.
+fn escape(content: &str, interpol_next: bool) -> String {
+    let mut escaped = String::with_capacity(content.len());
+    let mut chars = content.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            '$' if chars.peek() == Some(&'{') || (interpol_next && chars.peek().is_none()) => {
+                escaped.push_str("\\$")
+            }
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+/// Create a string literal, escaping the content as needed
+pub fn string(content: &str) -> Str {
+    interpolated_string(vec![StrPart::Literal(content.to_string())])
+}
+/// Create a string such as `"a${b}"`. Literal parts are escaped as needed,
+/// and interpolated expressions are used as they are.
+pub fn interpolated_string<I>(parts: I) -> Str
+where
+    I: IntoIterator<Item = StrPart>,
+{
+    fn literal(builder: Builder, content: &mut String, interpol_next: bool) -> Builder {
+        let escaped = escape(content, interpol_next);
+        content.clear();
+        if escaped.is_empty() {
+            builder
+        } else {
+            builder.token(TOKEN_STRING_CONTENT, &escaped)
+        }
+    }
+    let mut builder = Builder::new(NODE_STRING).token(TOKEN_STRING_START, "\"");
+    let mut content = String::new();
+    for part in parts {
+        match part {
+            StrPart::Literal(text) => content.push_str(&text),
+            StrPart::Ast(node) => {
+                builder = literal(builder, &mut content, true)
+                    .start(NODE_STRING_INTERPOL)
+                    .token(TOKEN_INTERPOL_START, "${")
+                    .node(node.green().clone())
+                    .token(TOKEN_INTERPOL_END, "}")
+                    .finish();
+            }
+        }
+    }
+    literal(builder, &mut content, false).token(TOKEN_STRING_END, "\"").build()
+}
+/// Create an integer literal. Negative numbers are made by wrapping this in
+/// a negating `unary_op`, which is what the parser does too. Panics if the
+/// value is larger than `i64::MAX`, as Nix can't read it back.
+pub fn int(value: u64) -> Value {
+    assert!(value <= i64::MAX as u64, "invalid integer literal: {}", value);
+    Builder::new(NODE_LITERAL).token(TOKEN_INTEGER, &value.to_string()).build()
+}
+/// Create a float literal. Panics if the value is negative, infinite or NaN.
+pub fn float(value: f64) -> Value {
+    assert!(value.is_finite() && value.is_sign_positive(), "invalid float literal: {}", value);
+    let mut text = value.to_string();
+    if !text.contains('.') {
+        text.push_str(".0");
+    }
+    Builder::new(NODE_LITERAL).token(TOKEN_FLOAT, &text).build()
+}
+/// Create the name of an attribute: An identifier if possible, otherwise a
+/// string
+pub fn attr_name(name: &str) -> SyntaxNode {
+    if is_valid_ident(name) {
+        ident(name).node().clone()
+    } else {
+        string(name).node().clone()
+    }
+}
+/// Create an attribute path such as `a.b."c d"`
+pub fn key(path: &[&str]) -> Key {
+    key_parts(path.iter().map(|name| KeyPart::cast(attr_name(name)).unwrap()))
+}
+/// Create an attribute path from any parts, such as `a.${b}`. Panics if
+/// there are no parts.
+pub fn key_parts<I>(parts: I) -> Key
+where
+    I: IntoIterator<Item = KeyPart>,
+{
+    let mut builder = Builder::new(NODE_KEY);
+    let mut first = true;
+    for part in parts {
+        if !first {
+            builder = builder.token(TOKEN_DOT, ".");
+        }
+        first = false;
+        builder = builder.node(part.node().green().clone());
+    }
+    assert!(!first, "empty attribute path");
+    builder.build()
+}
+/// Create a dynamic attribute name such as `${a}`
+pub fn dynamic(inner: &Expr) -> Dynamic {
+    Builder::new(NODE_DYNAMIC)
+        .token(TOKEN_DYNAMIC_START, "${")
+        .node(inner.node().green().clone())
+        .token(TOKEN_DYNAMIC_END, "}")
+        .build()
+}
+/// Create an entry such as `a.b = value;`
+pub fn key_value(path: &[&str], value: &Expr) -> KeyValue {
+    key_value_at(&key(path), value)
+}
+/// Create an entry with an existing attribute path, such as `${a} = value;`
+pub fn key_value_at(key: &Key, value: &Expr) -> KeyValue {
+    Builder::new(NODE_KEY_VALUE)
+        .node(key.node().green().clone())
+        .space()
+        .token(TOKEN_ASSIGN, "=")
+        .space()
+        .node(value.node().green().clone())
+        .token(TOKEN_SEMICOLON, ";")
+        .build()
+}
+fn inherit_names(mut builder: Builder, names: &[&str]) -> Builder {
+    for name in names {
+        builder = builder.space().node(ident(name).node().green().clone());
+    }
+    builder.token(TOKEN_SEMICOLON, ";")
+}
+/// Create an entry such as `inherit a b;`. Panics if a name isn't a valid
+/// identifier.
+pub fn inherit(names: &[&str]) -> Inherit {
+    inherit_names(Builder::new(NODE_INHERIT).token(TOKEN_INHERIT, "inherit"), names).build()
+}
+/// Create an entry such as `inherit (from) a b;`. Panics if a name isn't a
+/// valid identifier.
+pub fn inherit_from(from: &Expr, names: &[&str]) -> Inherit {
+    let builder = Builder::new(NODE_INHERIT)
+        .token(TOKEN_INHERIT, "inherit")
+        .space()
+        .start(NODE_INHERIT_FROM)
+        .token(TOKEN_PAREN_OPEN, "(")
+        .node(from.node().green().clone())
+        .token(TOKEN_PAREN_CLOSE, ")")
+        .finish();
+    inherit_names(builder, names).build()
+}
+fn entries<I>(mut builder: Builder, entries: I) -> Builder
+where
+    I: IntoIterator,
+    I::Item: Into<Binding>,
+{
+    for entry in entries {
+        builder = builder.space().node(entry.into().node().green().clone());
+    }
+    builder
+}
+/// Create a set such as `{ a = 1; }`
+pub fn attr_set<I>(entries: I) -> AttrSet
+where
+    I: IntoIterator,
+    I::Item: Into<Binding>,
+{
+    let builder = Builder::new(NODE_ATTR_SET).token(TOKEN_CURLY_B_OPEN, "{");
+    self::entries(builder, entries).space().token(TOKEN_CURLY_B_CLOSE, "}").build()
+}
+/// Create a recursive set such as `rec { a = 1; }`
+pub fn rec_attr_set<I>(entries: I) -> AttrSet
+where
+    I: IntoIterator,
+    I::Item: Into<Binding>,
+{
+    let builder = Builder::new(NODE_ATTR_SET).token(TOKEN_REC, "rec").space();
+    let builder = builder.token(TOKEN_CURLY_B_OPEN, "{");
+    self::entries(builder, entries).space().token(TOKEN_CURLY_B_CLOSE, "}").build()
+}
+/// Create a `let ... in body` expression
+pub fn let_in<I>(entries: I, body: &Expr) -> LetIn
+where
+    I: IntoIterator,
+    I::Item: Into<Binding>,
+{
+    let builder = Builder::new(NODE_LET_IN).token(TOKEN_LET, "let");
+    self::entries(builder, entries)
+        .space()
+        .token(TOKEN_IN, "in")
+        .space()
+        .node(body.node().green().clone())
+        .build()
+}
+/// Create a legacy `let { ...; body = value; }` expression
+pub fn legacy_let<I>(entries: I) -> LegacyLet
+where
+    I: IntoIterator,
+    I::Item: Into<Binding>,
+{
+    let builder = Builder::new(NODE_LEGACY_LET).token(TOKEN_LET, "let").space();
+    let builder = builder.token(TOKEN_CURLY_B_OPEN, "{");
+    self::entries(builder, entries).space().token(TOKEN_CURLY_B_CLOSE, "}").build()
+}
+/// Create a list such as `[ a b ]`. Items are put in parenthesis if needed.
+pub fn list<I>(items: I) -> List
+where
+    I: IntoIterator,
+    I::Item: Into<Expr>,
+{
+    let mut builder = Builder::new(NODE_LIST).token(TOKEN_SQUARE_B_OPEN, "[");
+    for item in items {
+        builder = builder.space().node(operand(item.into().node(), 15));
+    }
+    builder.space().token(TOKEN_SQUARE_B_CLOSE, "]").build()
+}
+/// Create a pattern entry such as `a` or `a ? default`
+pub fn pat_entry(name: &str, default: Option<&Expr>) -> PatEntry {
+    let builder = Builder::new(NODE_PAT_ENTRY).node(ident(name).node().green().clone());
+    match default {
+        Some(default) => {
+            let default = default.node().green().clone();
+            builder.space().token(TOKEN_QUESTION, "?").space().node(default).build()
+        }
+        None => builder.build(),
+    }
+}
+/// Create a pattern such as `{ a, b ? 1, ... }@args`
+pub fn pattern<I>(entries: I, ellipsis: bool, at: Option<&str>) -> Pattern
+where
+    I: IntoIterator<Item = PatEntry>,
+{
+    let mut builder = Builder::new(NODE_PATTERN).token(TOKEN_CURLY_B_OPEN, "{");
+    let mut first = true;
+    for entry in entries {
+        if !first {
+            builder = builder.token(TOKEN_COMMA, ",");
+        }
+        first = false;
+        builder = builder.space().node(entry.node().green().clone());
+    }
+    if ellipsis {
+        if !first {
+            builder = builder.token(TOKEN_COMMA, ",");
+        }
+        builder = builder.space().token(TOKEN_ELLIPSIS, "...");
+    }
+    builder = builder.space().token(TOKEN_CURLY_B_CLOSE, "}");
+    if let Some(at) = at {
+        builder = builder
+            .start(NODE_PAT_BIND)
+            .token(TOKEN_AT, "@")
+            .node(ident(at).node().green().clone())
+            .finish();
+    }
+    builder.build()
+}
+/// Create a lambda such as `x: body` or `{ x }: body`
+pub fn lambda(arg: &Param, body: &Expr) -> Lambda {
+    Builder::new(NODE_LAMBDA)
+        .node(arg.node().green().clone())
+        .token(TOKEN_COLON, ":")
+        .space()
+        .node(body.node().green().clone())
+        .build()
+}
+/// Create an `if condition then body else else_body` expression
+pub fn if_else(condition: &Expr, body: &Expr, else_body: &Expr) -> IfElse {
+    Builder::new(NODE_IF_ELSE)
+        .token(TOKEN_IF, "if")
+        .space()
+        .node(condition.node().green().clone())
+        .space()
+        .token(TOKEN_THEN, "then")
+        .space()
+        .node(body.node().green().clone())
+        .space()
+        .token(TOKEN_ELSE, "else")
+        .space()
+        .node(else_body.node().green().clone())
+        .build()
+}
+/// Create a `with namespace; body` expression
+pub fn with(namespace: &Expr, body: &Expr) -> With {
+    Builder::new(NODE_WITH)
+        .token(TOKEN_WITH, "with")
+        .space()
+        .node(namespace.node().green().clone())
+        .token(TOKEN_SEMICOLON, ";")
+        .space()
+        .node(body.node().green().clone())
+        .build()
+}
+/// Create an `assert condition; body` expression
+pub fn assert(condition: &Expr, body: &Expr) -> Assert {
+    Builder::new(NODE_ASSERT)
+        .token(TOKEN_ASSERT, "assert")
+        .space()
+        .node(condition.node().green().clone())
+        .token(TOKEN_SEMICOLON, ";")
+        .space()
+        .node(body.node().green().clone())
+        .build()
+}
+/// Create a function application such as `f x`
+pub fn apply(lambda: &Expr, value: &Expr) -> Apply {
+    let (lambda, value) = (operand(lambda.node(), 14), operand(value.node(), 15));
+    Builder::new(NODE_APPLY).node(lambda).space().node(value).build()
+}
+/// Create a binary operation such as `a + b`. Returns None if the operator
+/// is `?` and the right hand side isn't an identifier or a string, see
+/// `has_attr` and `has_attr_at` to check for any attribute path.
+pub fn bin_op(lhs: &Expr, op: BinOpKind, rhs: &Expr) -> Option<BinOp> {
+    let (lhs, rhs) = (lhs.node(), rhs.node());
+    let (lhs_min, rhs_min) = operand_levels(lhs, op, rhs);
+    let rhs = match (op, rhs.kind()) {
+        (BinOpKind::IsSet, NODE_IDENT) | (BinOpKind::IsSet, NODE_STRING) => {
+            Builder::new(NODE_KEY).node(rhs.green().clone()).green()
+        }
+        (BinOpKind::IsSet, _) => return None,
+        _ => operand(rhs, rhs_min),
+    };
+    Some(build_bin_op(operand(lhs, lhs_min), op, rhs))
+}
+/// The levels the left and right hand side of an operator must bind at
+fn operand_levels(lhs: &SyntaxNode, op: BinOpKind, rhs: &SyntaxNode) -> (u8, u8) {
+    let (precedence, assoc) = op.to_token().binary_op().unwrap();
+    match (op, assoc) {
+        (BinOpKind::PipeRight, _) | (BinOpKind::PipeLeft, _) => {
+            // Pipes only chain with themselves
+            let chains = |node: &SyntaxNode| {
+                BinOp::cast(node.clone()).is_some_and(|node| node.operator() == Some(op))
+            };
+            let lhs_chains = assoc == Assoc::Left && chains(lhs);
+            let rhs_chains = assoc == Assoc::Right && chains(rhs);
+            (precedence + !lhs_chains as u8, precedence + !rhs_chains as u8)
+        }
+        (_, Assoc::Left) => (precedence, precedence + 1),
+        (_, Assoc::Right) => (precedence + 1, precedence),
+        (_, Assoc::None) => (precedence + 1, precedence + 1),
+    }
+}
+/// Create the operation from operands that are already parenthesized
+fn build_bin_op(lhs: GreenNode, op: BinOpKind, rhs: GreenNode) -> BinOp {
+    let token = op.to_token();
+    Builder::new(NODE_BIN_OP)
+        .node(lhs)
+        .space()
+        .token(token, token.token_text().unwrap())
+        .space()
+        .node(rhs)
+        .build()
+}
+/// Create a check for an attribute such as `set ? a.b`
+pub fn has_attr(set: &Expr, path: &[&str]) -> BinOp {
+    has_attr_at(set, &key(path))
+}
+/// Create a check with an existing attribute path, such as `set ? ${a}`
+pub fn has_attr_at(set: &Expr, key: &Key) -> BinOp {
+    let (lhs_min, _) = operand_levels(set.node(), BinOpKind::IsSet, key.node());
+    build_bin_op(operand(set.node(), lhs_min), BinOpKind::IsSet, key.node().green().clone())
+}
+/// Create a unary operation such as `!a` or `-a`
+pub fn unary_op(op: UnaryOpKind, value: &Expr) -> UnaryOp {
+    let (token, text, min) = match op {
+        UnaryOpKind::Invert => (TOKEN_INVERT, "!", 8),
+        UnaryOpKind::Negate => (TOKEN_SUB, "-", 13),
+    };
+    let value = value.node();
+    // `-./path` would be read as one path
+    let value = if is_literal_of(value, &[TOKEN_PATH]) {
+        parenthesized(value)
+    } else {
+        operand(value, min)
+    };
+    Builder::new(NODE_UNARY_OP).token(token, text).node(value).build()
+}
+/// Create an attribute selection such as `set.attr`
+pub fn select(set: &Expr, attr: &str) -> Select {
+    let set = set.node();
+    // `1.a` or `./path.a` would be read as one literal
+    let set = if is_literal_of(set, &[TOKEN_INTEGER, TOKEN_FLOAT, TOKEN_PATH]) {
+        parenthesized(set)
+    } else {
+        operand(set, 15)
+    };
+    Builder::new(NODE_SELECT)
+        .node(set)
+        .token(TOKEN_DOT, ".")
+        .node(attr_name(attr).green().clone())
+        .build()
+}
+/// Create a selection with a fallback such as `set.attr or default`
+pub fn or_default(select: &Select, default: &Expr) -> OrDefault {
+    Builder::new(NODE_OR_DEFAULT)
+        .node(select.node().green().clone())
+        .space()
+        .token(TOKEN_IDENT, "or")
+        .space()
+        .node(operand(default.node(), 15))
+        .build()
+}
+/// Wrap an expression in parenthesis
+pub fn paren(inner: &Expr) -> Paren {
+    Paren::cast(SyntaxNode::new_root(parenthesized(inner.node()))).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Check that parsing the text of a node gives back the same tree
+    fn check<T: TypedNode>(node: T) -> String {
+        let text = node.node().to_string();
+        let options = crate::ParseOptions { pipe_operators: true, ..Default::default() };
+        let ast = crate::parse_with_options(&text, options);
+        assert!(ast.errors().is_empty(), "invalid output: {}", text);
+        let parsed = ast.root().inner().and_then(|expr| T::cast(expr.node().clone()));
+        let parsed = parsed.expect("parsed as a different kind");
+        assert_eq!(parsed.dump().to_string(), node.dump().to_string(), "tree mismatch: {}", text);
+        text
+    }
+    fn x() -> Expr {
+        ident("x").into()
+    }
+    fn n(value: u64) -> Expr {
+        int(value).into()
+    }
+    fn op(lhs: &Expr, op: BinOpKind, rhs: &Expr) -> Expr {
+        bin_op(lhs, op, rhs).expect("invalid operands").into()
+    }
+
+    #[test]
+    fn literals() {
+        assert_eq!(check(ident("a-b'")), "a-b'");
+        assert_eq!(check(string("")), "\"\"");
+        assert_eq!(check(string("a \"b\" \\ ${c} $d\n")), r#""a \"b\" \\ \${c} $d\n""#);
+        assert_eq!(check(int(42)), "42");
+        assert_eq!(check(int(i64::MAX as u64)), i64::MAX.to_string());
+        assert_eq!(check(float(1.5)), "1.5");
+        assert_eq!(check(float(100.0)), "100.0");
+        let entry = key_value(&["a", "b c", "if"], &n(1));
+        assert_eq!(check(attr_set(vec![entry])), "{ a.\"b c\".\"if\" = 1; }");
+    }
+    #[test]
+    fn sets() {
+        let entry = key_value(&["a", "b"], &n(1));
+        assert_eq!(entry.node().to_string(), "a.b = 1;");
+        assert_eq!(check(attr_set(Vec::<Binding>::new())), "{ }");
+        assert_eq!(check(attr_set(vec![entry.clone()])), "{ a.b = 1; }");
+        assert_eq!(
+            check(rec_attr_set(vec![entry.clone(), entry.clone()])),
+            "rec { a.b = 1; a.b = 1; }"
+        );
+        assert_eq!(check(let_in(vec![entry], &x())), "let a.b = 1; in x");
+        assert_eq!(check(legacy_let(vec![key_value(&["body"], &x())])), "let { body = x; }");
+        assert_eq!(check(list(vec![n(1), x()])), "[ 1 x ]");
+        assert_eq!(check(list(Vec::<Expr>::new())), "[ ]");
+    }
+    #[test]
+    fn bindings() {
+        let inherits: Vec<Binding> = vec![
+            inherit(&["a", "b"]).into(),
+            inherit_from(&x(), &["c"]).into(),
+            inherit_from(&op(&x(), BinOpKind::Update, &x()), &[]).into(),
+            key_value(&["d"], &n(1)).into(),
+        ];
+        assert_eq!(
+            check(let_in(inherits, &x())),
+            "let inherit a b; inherit (x) c; inherit (x // x); d = 1; in x"
+        );
+        let dynamic = dynamic(&op(&x(), BinOpKind::Add, &x()));
+        let path = key_parts(vec![ident("a").into(), dynamic.into(), string("b c").into()]);
+        let entry = key_value_at(&path, &n(1));
+        assert_eq!(check(attr_set(vec![entry])), "{ a.${x + x}.\"b c\" = 1; }");
+        assert_eq!(check(has_attr(&x(), &["a"])), "x ? a");
+        assert_eq!(check(has_attr_at(&x(), &path)), "x ? a.${x + x}.\"b c\"");
+    }
+    #[test]
+    fn strings() {
+        let parts = vec![
+            StrPart::Literal("a$".into()),
+            StrPart::Ast(x().node().clone()),
+            StrPart::Literal("$".into()),
+            StrPart::Literal("{b}\"".into()),
+            StrPart::Ast(op(&x(), BinOpKind::Add, &n(1)).node().clone()),
+        ];
+        assert_eq!(check(interpolated_string(parts)), r#""a\$${x}\${b}\"${x + 1}""#);
+        assert_eq!(check(interpolated_string(vec![StrPart::Ast(x().node().clone())])), "\"${x}\"");
+        assert_eq!(check(interpolated_string(vec![])), "\"\"");
+        let default = or_default(&select(&x(), "a"), &n(1));
+        assert_eq!(check(default.clone()), "x.a or 1");
+        assert_eq!(
+            check(or_default(&select(&x(), "a"), &apply(&x(), &x()).into())),
+            "x.a or (x x)"
+        );
+        assert_eq!(check(apply(&x(), &default.into())), "x (x.a or 1)");
+    }
+    #[test]
+    fn lambdas() {
+        assert_eq!(check(lambda(&ident("x").into(), &x())), "x: x");
+        let entries = vec![pat_entry("a", None), pat_entry("b", Some(&n(1)))];
+        let pat = pattern(entries.clone(), false, None);
+        assert_eq!(check(lambda(&pat.into(), &x())), "{ a, b ? 1 }: x");
+        let pat = pattern(entries, true, Some("args"));
+        assert_eq!(check(lambda(&pat.into(), &x())), "{ a, b ? 1, ... }@args: x");
+        let pat = pattern(vec![], true, None);
+        assert_eq!(check(lambda(&pat.into(), &x())), "{ ... }: x");
+        let pat = pattern(vec![], false, None);
+        assert_eq!(check(lambda(&pat.into(), &x())), "{ }: x");
+    }
+    #[test]
+    fn keywords() {
+        assert_eq!(check(if_else(&x(), &n(1), &n(2))), "if x then 1 else 2");
+        assert_eq!(check(with(&x(), &x())), "with x; x");
+        assert_eq!(check(assert(&x(), &x())), "assert x; x");
+        assert_eq!(check(select(&x(), "y")), "x.y");
+        assert_eq!(check(select(&n(1), "y")), "(1).y");
+        let path = crate::parse("./a/${x}").root().inner().unwrap();
+        assert_eq!(check(select(&path, "y")), "(./a/${x}).y");
+    }
+    #[test]
+    fn precedence() {
+        let f = ident("f").into();
+        let fx: Expr = apply(&f, &x()).into();
+        assert_eq!(check(fx.clone()), "f x");
+        assert_eq!(check(apply(&fx, &x())), "f x x");
+        assert_eq!(check(apply(&f, &fx)), "f (f x)");
+        assert_eq!(check(list(vec![fx.clone()])), "[ (f x) ]");
+
+        let add = op(&n(1), BinOpKind::Add, &n(2));
+        let mul = op(&n(1), BinOpKind::Mul, &n(2));
+        assert_eq!(check(op(&add, BinOpKind::Mul, &n(3))), "(1 + 2) * 3");
+        assert_eq!(check(op(&n(3), BinOpKind::Mul, &add)), "3 * (1 + 2)");
+        assert_eq!(check(op(&mul, BinOpKind::Add, &n(3))), "1 * 2 + 3");
+        assert_eq!(check(op(&add, BinOpKind::Sub, &add)), "1 + 2 - (1 + 2)");
+        assert_eq!(check(op(&fx, BinOpKind::Add, &fx)), "f x + f x");
+
+        let less = op(&n(1), BinOpKind::Less, &n(2));
+        assert_eq!(check(op(&less, BinOpKind::Less, &n(3))), "(1 < 2) < 3");
+
+        let neg: Expr = unary_op(UnaryOpKind::Negate, &n(1)).into();
+        assert_eq!(check(neg.clone()), "-1");
+        assert_eq!(check(op(&n(1), BinOpKind::Sub, &neg)), "1 - -1");
+        assert_eq!(check(unary_op(UnaryOpKind::Negate, &add)), "-(1 + 2)");
+        let not = unary_op(UnaryOpKind::Invert, &x()).into();
+        assert_eq!(check(op(&not, BinOpKind::Add, &n(1))), "(!x) + 1");
+        assert_eq!(check(unary_op(UnaryOpKind::Invert, &add)), "!1 + 2");
+
+        let lam = lambda(&ident("x").into(), &x()).into();
+        assert_eq!(check(apply(&lam, &x())), "(x: x) x");
+        assert_eq!(check(op(&x(), BinOpKind::Update, &lam)), "x // (x: x)");
+        let entry = key_value(&["a"], &lam);
+        assert_eq!(check(attr_set(vec![entry])), "{ a = x: x; }");
+    }
+    #[test]
+    fn isset() {
+        assert_eq!(check(has_attr(&x(), &["a", "b c"])), "x ? a.\"b c\"");
+        assert_eq!(check(op(&x(), BinOpKind::IsSet, &x())), "x ? x");
+        assert!(bin_op(&x(), BinOpKind::IsSet, &n(1)).is_none());
+        assert_eq!(check(op(&x(), BinOpKind::IsSet, &string("a b").into())), "x ? \"a b\"");
+        assert!(bin_op(&x(), BinOpKind::IsSet, &op(&x(), BinOpKind::Add, &x())).is_none());
+        let isset = has_attr(&x(), &["a"]).into();
+        assert_eq!(check(has_attr(&isset, &["b"])), "x ? a ? b");
+        let add = op(&n(1), BinOpKind::Add, &n(2));
+        assert_eq!(check(has_attr(&add, &["a"])), "(1 + 2) ? a");
+        assert_eq!(check(op(&isset, BinOpKind::Add, &n(1))), "x ? a + 1");
+    }
+    #[test]
+    fn pipes() {
+        let f = ident("f").into();
+        let right = op(&x(), BinOpKind::PipeRight, &f);
+        assert_eq!(check(right.clone()), "x |> f");
+        assert_eq!(check(op(&right, BinOpKind::PipeRight, &f)), "x |> f |> f");
+        assert_eq!(check(op(&x(), BinOpKind::PipeRight, &right)), "x |> (x |> f)");
+        let left = op(&f, BinOpKind::PipeLeft, &x());
+        assert_eq!(check(op(&f, BinOpKind::PipeLeft, &left)), "f <| f <| x");
+        assert_eq!(check(op(&left, BinOpKind::PipeLeft, &x())), "(f <| x) <| x");
+        assert_eq!(check(op(&left, BinOpKind::PipeRight, &f)), "(f <| x) |> f");
+        let imp = op(&x(), BinOpKind::Implication, &x());
+        assert_eq!(check(op(&imp, BinOpKind::PipeRight, &f)), "x -> x |> f");
+    }
+}
@@ -1,6 +1,7 @@
 //! The parser: turns a series of tokens into an AST
 
 use std::{
+    cell::Cell,
     collections::{HashSet, VecDeque},
     fmt,
 };
@@ -9,13 +10,111 @@ use cbitset::BitSet256;
 use rowan::{Checkpoint, GreenNode, GreenNodeBuilder, Language, SmolStr, TextRange, TextUnit};
 
 use crate::{
+    tokenizer::LexError,
     types::{Root, TypedNode},
     NixLanguage,
     SyntaxKind::{self, *},
     SyntaxNode,
 };
 
-const OR: &'static str = "or";
+const OR: &str = "or";
+
+/// Syntax that can be turned on or off using `ParseOptions`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum SyntaxFeature {
+    /// The pipe operators `|>` and `<|`, experimental since Nix 2.24
+    PipeOperators,
+    /// Unquoted URIs such as `https://nixos.org`, deprecated by RFC 45
+    UriLiterals,
+    /// The legacy `let { ... body = ...; }` syntax
+    LegacyLet,
+    /// `or` used as an identifier instead of a keyword
+    OrAsIdent,
+}
+
+impl fmt::Display for SyntaxFeature {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            SyntaxFeature::PipeOperators => "pipe operator",
+            SyntaxFeature::UriLiterals => "URI literal",
+            SyntaxFeature::LegacyLet => "legacy let syntax",
+            SyntaxFeature::OrAsIdent => "`or` as an identifier",
+        })
+    }
+}
+
+/// A limit on the input that can be set in `ParseOptions`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Limit {
+    /// How deeply expressions may nest
+    Depth,
+    /// How many tokens are parsed
+    Tokens,
+    /// How many bytes of input are parsed
+    InputSize,
+}
+
+impl fmt::Display for Limit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Limit::Depth => "nesting depth limit",
+            Limit::Tokens => "token limit",
+            Limit::InputSize => "input size limit",
+        })
+    }
+}
+
+/// The default for `ParseOptions::max_depth`, which is deeper than any
+/// handwritten code goes while leaving plenty of room on a 2 MiB stack
+pub const DEFAULT_MAX_DEPTH: usize = 1000;
+
+/// Options for which syntax the parser accepts. The default accepts all
+/// syntax that Nix itself accepts without experimental features.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct ParseOptions {
+    /// Accept the pipe operators `|>` and `<|`
+    pub pipe_operators: bool,
+    /// Accept unquoted URIs
+    pub uri_literals: bool,
+    /// Accept the legacy `let { ... }` syntax
+    pub legacy_let: bool,
+    /// Accept `or` as an identifier
+    pub or_as_ident: bool,
+    /// How deeply expressions may nest. Once this is hit, the parser stops
+    /// and keeps the rest of the text in the tree as an error node. Without
+    /// a limit, deeply nested input can overflow the stack.
+    pub max_depth: Option<usize>,
+    /// How many tokens to parse. The text after that is kept in the tree as
+    /// a single error token.
+    pub max_tokens: Option<usize>,
+    /// How many bytes of input to parse. Larger input isn't parsed at all and
+    /// is kept in the tree as a single error token.
+    pub max_input_size: Option<usize>,
+}
+impl Default for ParseOptions {
+    fn default() -> Self {
+        Self {
+            pipe_operators: false,
+            uri_literals: true,
+            legacy_let: true,
+            or_as_ident: true,
+            max_depth: Some(DEFAULT_MAX_DEPTH),
+            max_tokens: None,
+            max_input_size: None,
+        }
+    }
+}
+impl ParseOptions {
+    /// Returns true if the feature is turned on
+    pub fn enabled(&self, feature: SyntaxFeature) -> bool {
+        match feature {
+            SyntaxFeature::PipeOperators => self.pipe_operators,
+            SyntaxFeature::UriLiterals => self.uri_literals,
+            SyntaxFeature::LegacyLet => self.legacy_let,
+            SyntaxFeature::OrAsIdent => self.or_as_ident,
+        }
+    }
+}
 
 /// An error that occurred during parsing
 #[derive(Clone, Debug, PartialEq)]
@@ -32,6 +131,14 @@ pub enum ParseError {
     UnexpectedEOF,
     /// UnexpectedWanted is used when specific tokens are expected, but the end of file is reached
     UnexpectedEOFWanted(Box<[SyntaxKind]>),
+    /// DisabledFeature is used when syntax is found that is turned off in the `ParseOptions`
+    DisabledFeature(SyntaxFeature, TextRange),
+    /// Lexical is used when the tokenizer could not make sense of the text in
+    /// the range
+    Lexical(LexError, TextRange),
+    /// LimitExceeded is used when one of the limits in the `ParseOptions` is
+    /// hit, and the text in the range was not parsed
+    LimitExceeded(Limit, TextRange),
 }
 
 impl fmt::Display for ParseError {
@@ -45,18 +152,28 @@ impl fmt::Display for ParseError {
             }
             ParseError::UnexpectedWanted(got, range, kinds) => write!(
                 f,
-                "unexpected {:?} at {}..{}, wanted any of {:?}",
+                "unexpected {} at {}..{}, wanted {}",
                 got,
                 range.start(),
                 range.end(),
-                kinds
+                Wanted(kinds)
             ),
             ParseError::UnexpectedDoubleBind(range) => {
                 write!(f, "unexpected double bind at {}..{}", range.start(), range.end())
             }
             ParseError::UnexpectedEOF => write!(f, "unexpected end of file"),
             ParseError::UnexpectedEOFWanted(kinds) => {
-                write!(f, "unexpected end of file, wanted any of {:?}", kinds)
+                write!(f, "unexpected end of file, wanted {}", Wanted(kinds))
+            }
+            ParseError::DisabledFeature(feature, range) => {
+                write!(f, "{} at {}..{} is not enabled", feature, range.start(), range.end())
+            }
+            ParseError::Lexical(err, range) => match err.start() {
+                Some(start) => write!(f, "{} starting at {}..{}", err, start.start(), start.end()),
+                None => write!(f, "{} at {}..{}", err, range.start(), range.end()),
+            },
+            ParseError::LimitExceeded(limit, range) => {
+                write!(f, "{} exceeded at {}..{}", limit, range.start(), range.end())
             }
         }
     }
@@ -64,11 +181,41 @@ impl fmt::Display for ParseError {
 
 impl std::error::Error for ParseError {}
 
+/// Formats a list of wanted kinds, such as "any of `;`, `}`"
+pub(crate) struct Wanted<'a>(pub(crate) &'a [SyntaxKind]);
+impl fmt::Display for Wanted<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.0.len() > 1 {
+            f.write_str("any of ")?;
+        }
+        for (i, kind) in self.0.iter().enumerate() {
+            if i > 0 {
+                f.write_str(", ")?;
+            }
+            write!(f, "{}", kind)?;
+        }
+        Ok(())
+    }
+}
+
+/// Returned instead of an AST when parsing is cancelled
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Cancelled;
+
+impl fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("parsing was cancelled")
+    }
+}
+
+impl std::error::Error for Cancelled {}
+
 /// The result of a parse
 #[derive(Clone)]
 pub struct AST {
-    node: GreenNode,
-    errors: Vec<ParseError>,
+    pub(crate) node: GreenNode,
+    pub(crate) errors: Vec<ParseError>,
+    pub(crate) options: ParseOptions,
 }
 impl AST {
     /// Return the root node
@@ -79,6 +226,10 @@ impl AST {
     pub fn root(&self) -> Root {
         Root::cast(self.node()).unwrap()
     }
+    /// Return the options this was parsed with
+    pub fn options(&self) -> ParseOptions {
+        self.options
+    }
     /// Return all errors in the tree, if any
     pub fn errors(&self) -> Vec<ParseError> {
         let ranges: HashSet<_> = self
@@ -88,6 +239,8 @@ impl AST {
                 ParseError::UnexpectedWanted(_, t, _) => Some(t),
                 ParseError::UnexpectedDoubleBind(t) => Some(t),
                 ParseError::UnexpectedExtra(t) => Some(t),
+                ParseError::Lexical(_, t) => Some(t),
+                ParseError::LimitExceeded(_, t) => Some(t),
                 _ => None,
             })
             .collect();
@@ -111,31 +264,52 @@ impl AST {
     }
 }
 
+/// A piece of code parsed on its own, such as an attribute path. The parent
+/// of the node is a `NODE_ROOT`, which also holds the trivia around it and
+/// any input after it.
+#[derive(Clone, Debug)]
+pub struct Fragment<N> {
+    pub node: N,
+    pub errors: Vec<ParseError>,
+}
+
 struct Parser<I>
 where
     I: Iterator<Item = (SyntaxKind, SmolStr)>,
 {
     builder: GreenNodeBuilder<'static>,
     errors: Vec<ParseError>,
+    options: ParseOptions,
 
     trivia_buffer: Vec<I::Item>,
     buffer: VecDeque<I::Item>,
     iter: I,
     index: usize,
+    depth: usize,
+    // How many errors there were when the depth limit was hit
+    too_deep: Option<usize>,
+    tokens: usize,
+    // The length of the text past the token limit
+    truncated: Option<usize>,
 }
 impl<I> Parser<I>
 where
     I: Iterator<Item = (SyntaxKind, SmolStr)>,
 {
-    fn new(iter: I) -> Self {
+    fn new(iter: I, options: ParseOptions) -> Self {
         Self {
             builder: GreenNodeBuilder::new(),
             errors: Vec::new(),
+            options,
 
             trivia_buffer: Vec::with_capacity(1),
             buffer: VecDeque::with_capacity(1),
             iter,
             index: 0,
+            depth: 0,
+            too_deep: None,
+            tokens: 0,
+            truncated: None,
         }
     }
 
@@ -143,9 +317,31 @@ where
         TextUnit::from_usize(self.index)
     }
 
+    fn next_token(&mut self) -> Option<(SyntaxKind, SmolStr)> {
+        if self.options.max_tokens.is_some_and(|max| self.tokens >= max) {
+            if self.truncated.is_some() {
+                return None;
+            }
+            // Keep the tree lossless by putting the rest in one error token
+            let rest = self.iter.by_ref().fold(String::new(), |mut rest, (_, s)| {
+                rest.push_str(&s);
+                rest
+            });
+            if rest.is_empty() {
+                return None;
+            }
+            self.truncated = Some(rest.len());
+            return Some((TOKEN_ERROR, SmolStr::new(rest)));
+        }
+        self.tokens += 1;
+        self.iter.next()
+    }
     fn peek_raw(&mut self) -> Option<&(SyntaxKind, SmolStr)> {
+        if self.too_deep.is_some() {
+            return None;
+        }
         if self.buffer.is_empty() {
-            if let Some(token) = self.iter.next() {
+            if let Some(token) = self.next_token() {
                 self.buffer.push_back(token);
             }
         }
@@ -153,7 +349,7 @@ where
     }
     fn drain_trivia_buffer(&mut self) {
         for (t, s) in self.trivia_buffer.drain(..) {
-            self.index += s.as_bytes().len();
+            self.index += s.len();
             self.builder.token(NixLanguage::kind_to_raw(t), s)
         }
     }
@@ -184,14 +380,21 @@ where
         self.get_text_position()
     }
     fn bump(&mut self) {
-        let next = self.buffer.pop_front().or_else(|| self.iter.next());
+        let next = if self.too_deep.is_some() {
+            None
+        } else {
+            match self.buffer.pop_front() {
+                Some(token) => Some(token),
+                None => self.next_token(),
+            }
+        };
         match next {
             Some((token, s)) => {
                 if token.is_trivia() {
                     self.trivia_buffer.push((token, s))
                 } else {
                     self.drain_trivia_buffer();
-                    self.index += s.as_bytes().len();
+                    self.index += s.len();
                     self.builder.token(NixLanguage::kind_to_raw(token), s)
                 }
             }
@@ -244,11 +447,63 @@ where
     }
     fn expect_ident(&mut self) {
         if self.expect_peek_any(&[TOKEN_IDENT]).is_some() {
+            if self.peek_data().is_some_and(|(_, s)| s == OR) {
+                self.check_feature(SyntaxFeature::OrAsIdent, OR.len());
+            }
             self.start_node(NODE_IDENT);
             self.bump();
             self.finish_node()
         }
     }
+    /// Record an error if the syntax starting at the next token, `len` bytes
+    /// long, is disabled
+    fn check_feature(&mut self, feature: SyntaxFeature, len: usize) {
+        if !self.options.enabled(feature) {
+            self.eat_trivia();
+            let start = self.get_text_position();
+            let range = TextRange::offset_len(start, TextUnit::from_usize(len));
+            self.errors.push(ParseError::DisabledFeature(feature, range));
+        }
+    }
+
+    /// Go one level deeper, or stop parsing if that would exceed the depth
+    /// limit. Returns false if parsing stopped.
+    fn enter(&mut self) -> bool {
+        if self.options.max_depth.is_some_and(|max| self.depth >= max) {
+            // Pretend the input ends here so the parser unwinds, and drop the
+            // errors that causes. The rest of the input is put in an error
+            // node at the root afterwards, where it doesn't add to the depth.
+            if self.too_deep.is_none() {
+                self.eat_trivia();
+                self.too_deep = Some(self.errors.len());
+            }
+            return false;
+        }
+        self.depth += 1;
+        true
+    }
+    /// Run `parse` one level deeper, unless that would exceed the depth limit
+    fn nested(&mut self, parse: impl FnOnce(&mut Self) -> Checkpoint) -> Checkpoint {
+        if !self.enter() {
+            return self.builder.checkpoint();
+        }
+        let checkpoint = parse(self);
+        self.depth -= 1;
+        checkpoint
+    }
+    /// Put the input left after hitting the depth limit in an error node
+    fn finish_too_deep(&mut self) {
+        if let Some(errors) = self.too_deep.take() {
+            self.errors.truncate(errors);
+            let start = self.start_error_node();
+            while self.peek().is_some() {
+                self.bump();
+            }
+            let end = self.finish_error_node();
+            let range = TextRange::from_to(start, end);
+            self.errors.push(ParseError::LimitExceeded(Limit::Depth, range));
+        }
+    }
 
     fn parse_dynamic(&mut self) {
         self.start_node(NODE_DYNAMIC);
@@ -270,13 +525,7 @@ where
                 TOKEN_INTERPOL_START,
             ]) {
                 Some(TOKEN_STRING_CONTENT) => self.bump(),
-                Some(TOKEN_INTERPOL_START) => {
-                    self.start_node(NODE_STRING_INTERPOL);
-                    self.bump();
-                    self.parse_expr();
-                    self.expect(TOKEN_INTERPOL_END);
-                    self.finish_node();
-                }
+                Some(TOKEN_INTERPOL_START) => self.parse_interpol(),
                 // handled by expect_peek_any
                 _ => break,
             }
@@ -285,6 +534,25 @@ where
 
         self.finish_node();
     }
+    fn parse_interpol(&mut self) {
+        self.start_node(NODE_STRING_INTERPOL);
+        self.bump();
+        self.parse_expr();
+        self.expect(TOKEN_INTERPOL_END);
+        self.finish_node();
+    }
+    /// Parse the contents of a string without the quotes around it
+    fn parse_string_body(&mut self) {
+        self.start_node(NODE_STRING);
+        loop {
+            match self.peek() {
+                Some(TOKEN_STRING_CONTENT) => self.bump(),
+                Some(TOKEN_INTERPOL_START) => self.parse_interpol(),
+                _ => break,
+            }
+        }
+        self.finish_node();
+    }
     fn next_attr(&mut self) {
         match self.peek() {
             Some(TOKEN_DYNAMIC_START) => self.parse_dynamic(),
@@ -357,6 +625,30 @@ where
             }
         }
     }
+    /// Parse a lambda pattern on its own, such as `args@{ a, b ? 1, ... }`
+    fn parse_lone_pattern(&mut self) {
+        self.start_node(NODE_PATTERN);
+        let bound = self.peek() == Some(TOKEN_IDENT);
+        if bound {
+            self.start_node(NODE_PAT_BIND);
+            self.expect_ident();
+            self.expect(TOKEN_AT);
+            self.finish_node();
+        }
+        if self.expect_peek_any(&[TOKEN_CURLY_B_OPEN]).is_some() {
+            self.bump();
+            self.parse_pattern(bound);
+        }
+        self.finish_node();
+    }
+    fn parse_key_value(&mut self) {
+        self.start_node(NODE_KEY_VALUE);
+        self.parse_attr();
+        self.expect(TOKEN_ASSIGN);
+        self.parse_expr();
+        self.expect(TOKEN_SEMICOLON);
+        self.finish_node();
+    }
     fn parse_set(&mut self, until: SyntaxKind) {
         loop {
             match self.peek() {
@@ -381,19 +673,15 @@ where
                     self.expect(TOKEN_SEMICOLON);
                     self.finish_node();
                 }
-                Some(_) => {
-                    self.start_node(NODE_KEY_VALUE);
-                    self.parse_attr();
-                    self.expect(TOKEN_ASSIGN);
-                    self.parse_expr();
-                    self.expect(TOKEN_SEMICOLON);
-                    self.finish_node();
-                }
+                Some(_) => self.parse_key_value(),
             }
         }
         self.bump(); // the final close, like '}'
     }
     fn parse_val(&mut self) -> Checkpoint {
+        self.nested(Self::parse_val_inner)
+    }
+    fn parse_val_inner(&mut self) -> Checkpoint {
         let peek = match self.peek() {
             Some(it) => it,
             None => {
@@ -425,10 +713,10 @@ where
             TOKEN_CURLY_B_OPEN => {
                 // Do a lookahead:
                 let mut peek = [None, None];
-                for i in 0..2 {
+                for peek in &mut peek {
                     let mut token;
-                    peek[i] = loop {
-                        token = self.iter.next();
+                    *peek = loop {
+                        token = self.next_token();
                         let kind = token.as_ref().map(|&(t, _)| t);
                         if let Some(token) = token {
                             self.buffer.push_back(token);
@@ -479,7 +767,29 @@ where
             }
             TOKEN_DYNAMIC_START => self.parse_dynamic(),
             TOKEN_STRING_START => self.parse_string(),
+            TOKEN_PATH => {
+                self.bump();
+                // The tokenizer only emits an interpolation right after a path
+                // if it is part of the path
+                if self.peek_raw().map(|&(t, _)| t) == Some(TOKEN_INTERPOL_START) {
+                    self.start_node_at(checkpoint, NODE_PATH_WITH_INTERPOL);
+                    loop {
+                        match self.peek_raw().map(|&(t, _)| t) {
+                            Some(TOKEN_PATH) => self.bump(),
+                            Some(TOKEN_INTERPOL_START) => self.parse_interpol(),
+                            _ => break,
+                        }
+                    }
+                } else {
+                    self.start_node_at(checkpoint, NODE_LITERAL);
+                }
+                self.finish_node();
+            }
             t if t.is_literal() => {
+                if t == TOKEN_URI {
+                    let len = self.peek_data().map_or(0, |(_, s)| s.len());
+                    self.check_feature(SyntaxFeature::UriLiterals, len);
+                }
                 self.start_node(NODE_LITERAL);
                 self.bump();
                 self.finish_node();
@@ -534,12 +844,17 @@ where
             }
         };
 
+        let depth = self.depth;
         while self.peek() == Some(TOKEN_DOT) {
+            if !self.enter() {
+                break;
+            }
             self.start_node_at(checkpoint, NODE_SELECT);
             self.bump();
             self.next_attr();
             self.finish_node();
         }
+        self.depth = depth;
         if self.peek_data().map(|&(t, ref s)| t == TOKEN_IDENT && s == OR).unwrap_or(false) {
             self.start_node_at(checkpoint, NODE_OR_DEFAULT);
             self.bump();
@@ -551,11 +866,16 @@ where
     fn parse_fn(&mut self) -> Checkpoint {
         let checkpoint = self.parse_val();
 
+        let depth = self.depth;
         while self.peek().map(|t| t.is_fn_arg()).unwrap_or(false) {
+            if !self.enter() {
+                break;
+            }
             self.start_node_at(checkpoint, NODE_APPLY);
             self.parse_val();
             self.finish_node();
         }
+        self.depth = depth;
         checkpoint
     }
     fn parse_negate(&mut self) -> Checkpoint {
@@ -563,7 +883,7 @@ where
             let checkpoint = self.checkpoint();
             self.start_node(NODE_UNARY_OP);
             self.bump();
-            self.parse_negate();
+            self.nested(Self::parse_negate);
             self.finish_node();
             checkpoint
         } else {
@@ -577,7 +897,11 @@ where
         ops: &[SyntaxKind],
     ) -> Checkpoint {
         let checkpoint = next(self);
+        let depth = self.depth;
         while self.peek().map(|t| ops.contains(&t)).unwrap_or(false) {
+            if !self.enter() {
+                break;
+            }
             self.start_node_at(checkpoint, NODE_BIN_OP);
             self.bump();
             next(self);
@@ -586,13 +910,42 @@ where
                 break;
             }
         }
+        self.depth = depth;
+        checkpoint
+    }
+    /// Like `handle_operation`, but `a op b op c` nests to the right, as
+    /// `a op (b op c)`
+    fn handle_operation_right(
+        &mut self,
+        next: fn(&mut Self) -> Checkpoint,
+        op: SyntaxKind,
+    ) -> Checkpoint {
+        let checkpoint = next(self);
+        if self.peek() == Some(op) {
+            self.start_node_at(checkpoint, NODE_BIN_OP);
+            self.bump();
+            self.nested(|parser| parser.handle_operation_right(next, op));
+            self.finish_node();
+        }
         checkpoint
     }
     fn parse_isset(&mut self) -> Checkpoint {
-        self.handle_operation(false, Self::parse_negate, &[TOKEN_QUESTION])
+        let checkpoint = self.parse_negate();
+        let depth = self.depth;
+        while self.peek() == Some(TOKEN_QUESTION) {
+            if !self.enter() {
+                break;
+            }
+            self.start_node_at(checkpoint, NODE_BIN_OP);
+            self.bump();
+            self.parse_attr();
+            self.finish_node();
+        }
+        self.depth = depth;
+        checkpoint
     }
     fn parse_concat(&mut self) -> Checkpoint {
-        self.handle_operation(false, Self::parse_isset, &[TOKEN_CONCAT])
+        self.handle_operation_right(Self::parse_isset, TOKEN_CONCAT)
     }
     fn parse_mul(&mut self) -> Checkpoint {
         self.handle_operation(false, Self::parse_concat, &[TOKEN_MUL, TOKEN_DIV])
@@ -605,7 +958,7 @@ where
             let checkpoint = self.checkpoint();
             self.start_node(NODE_UNARY_OP);
             self.bump();
-            self.parse_invert();
+            self.nested(Self::parse_invert);
             self.finish_node();
             checkpoint
         } else {
@@ -613,7 +966,7 @@ where
         }
     }
     fn parse_merge(&mut self) -> Checkpoint {
-        self.handle_operation(false, Self::parse_invert, &[TOKEN_UPDATE])
+        self.handle_operation_right(Self::parse_invert, TOKEN_UPDATE)
     }
     fn parse_compare(&mut self) -> Checkpoint {
         self.handle_operation(
@@ -632,21 +985,66 @@ where
         self.handle_operation(false, Self::parse_and, &[TOKEN_OR])
     }
     fn parse_implication(&mut self) -> Checkpoint {
-        self.handle_operation(false, Self::parse_or, &[TOKEN_IMPLICATION])
+        self.handle_operation_right(Self::parse_or, TOKEN_IMPLICATION)
+    }
+    fn parse_pipe(&mut self) -> Checkpoint {
+        let checkpoint = self.parse_implication();
+        match self.peek() {
+            Some(TOKEN_PIPE_RIGHT) => {
+                let depth = self.depth;
+                while self.peek() == Some(TOKEN_PIPE_RIGHT) {
+                    if !self.enter() {
+                        break;
+                    }
+                    self.start_node_at(checkpoint, NODE_BIN_OP);
+                    self.check_feature(SyntaxFeature::PipeOperators, 2);
+                    self.bump();
+                    self.parse_implication();
+                    self.finish_node();
+                }
+                self.depth = depth;
+            }
+            Some(TOKEN_PIPE_LEFT) => self.parse_pipe_left(checkpoint),
+            _ => (),
+        }
+        checkpoint
+    }
+    fn parse_pipe_left(&mut self, checkpoint: Checkpoint) {
+        // `f <| g <| x` means `f (g x)`, so this one nests to the right
+        if self.peek() == Some(TOKEN_PIPE_LEFT) {
+            self.start_node_at(checkpoint, NODE_BIN_OP);
+            self.check_feature(SyntaxFeature::PipeOperators, 2);
+            self.bump();
+            self.nested(|parser| {
+                let checkpoint = parser.parse_implication();
+                parser.parse_pipe_left(checkpoint);
+                checkpoint
+            });
+            self.finish_node();
+        }
     }
     #[inline(always)]
     fn parse_math(&mut self) -> Checkpoint {
         // Always point this to the lowest-level math function there is
-        self.parse_implication()
+        self.parse_pipe()
     }
     /// Parse Nix code into an AST
     pub fn parse_expr(&mut self) -> Checkpoint {
+        self.nested(Self::parse_expr_inner)
+    }
+    fn parse_expr_inner(&mut self) -> Checkpoint {
         match self.peek() {
             Some(TOKEN_LET) => {
                 let checkpoint = self.checkpoint();
+                let start = self.get_text_position();
                 self.bump();
 
                 if self.peek() == Some(TOKEN_CURLY_B_OPEN) {
+                    if !self.options.legacy_let {
+                        let range = TextRange::from_to(start, self.get_text_position());
+                        self.errors
+                            .push(ParseError::DisabledFeature(SyntaxFeature::LegacyLet, range));
+                    }
                     self.start_node_at(checkpoint, NODE_LEGACY_LET);
                     self.bump();
                     self.parse_set(TOKEN_CURLY_B_CLOSE);
@@ -696,14 +1094,73 @@ where
     }
 }
 
-/// Parse tokens into an AST
-pub fn parse<I>(iter: I) -> AST
+/// Parse tokens into an AST. The text of each token can be anything that
+/// turns into a `SmolStr`, such as the `&str` from `Tokenizer::borrowed`.
+pub fn parse<I, S>(iter: I) -> AST
+where
+    I: IntoIterator<Item = (SyntaxKind, S)>,
+    S: Into<SmolStr>,
+{
+    parse_with_options(iter, ParseOptions::default())
+}
+/// Parse tokens into an AST, accepting the syntax chosen in `options`
+pub fn parse_with_options<I, S>(iter: I, options: ParseOptions) -> AST
 where
-    I: IntoIterator<Item = (SyntaxKind, SmolStr)>,
+    I: IntoIterator<Item = (SyntaxKind, S)>,
+    S: Into<SmolStr>,
 {
-    let mut parser = Parser::new(iter.into_iter());
+    parse_fragment(iter, options, FragmentKind::Expr)
+}
+
+/// The kinds of code `parse_fragment` can parse
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum FragmentKind {
+    /// An expression, like a whole file
+    Expr,
+    /// An attribute path like `a.b."c"`, parsed into a `NODE_KEY`
+    AttrPath,
+    /// A lambda pattern like `{ a, b ? 1, ... }`, parsed into a `NODE_PATTERN`
+    Pattern,
+    /// A binding like `a = 1;`, parsed into a `NODE_KEY_VALUE`
+    Binding,
+    /// The contents of a string without the quotes, parsed into a
+    /// `NODE_STRING`. The tokens should come from a tokenizer that starts
+    /// inside a string.
+    StringBody,
+}
+impl FragmentKind {
+    /// The kind of node this is parsed into, if it's not an expression
+    fn node_kind(self) -> Option<SyntaxKind> {
+        match self {
+            FragmentKind::Expr => None,
+            FragmentKind::AttrPath => Some(NODE_KEY),
+            FragmentKind::Pattern => Some(NODE_PATTERN),
+            FragmentKind::Binding => Some(NODE_KEY_VALUE),
+            FragmentKind::StringBody => Some(NODE_STRING),
+        }
+    }
+}
+
+/// Parse tokens into a `NODE_ROOT` holding one piece of code of the given
+/// kind. Anything after it is an `UnexpectedExtra` error.
+pub(crate) fn parse_fragment<I, S>(iter: I, options: ParseOptions, kind: FragmentKind) -> AST
+where
+    I: IntoIterator<Item = (SyntaxKind, S)>,
+    S: Into<SmolStr>,
+{
+    let iter = iter.into_iter().map(|(kind, text)| (kind, text.into()));
+    let mut parser = Parser::new(iter, options);
     parser.builder.start_node(NixLanguage::kind_to_raw(NODE_ROOT));
-    parser.parse_expr();
+    match kind {
+        FragmentKind::Expr => {
+            parser.parse_expr();
+        }
+        FragmentKind::AttrPath => parser.parse_attr(),
+        FragmentKind::Pattern => parser.parse_lone_pattern(),
+        FragmentKind::Binding => parser.parse_key_value(),
+        FragmentKind::StringBody => parser.parse_string_body(),
+    }
+    parser.finish_too_deep();
     parser.eat_trivia();
     if parser.peek().is_some() {
         let start = parser.start_error_node();
@@ -714,15 +1171,113 @@ where
         parser.errors.push(ParseError::UnexpectedExtra(TextRange::from_to(start, end)));
         parser.eat_trivia();
     }
+    if let Some(len) = parser.truncated {
+        let end = parser.get_text_position();
+        let range = TextRange::from_to(end - TextUnit::from_usize(len), end);
+        parser.errors.push(ParseError::LimitExceeded(Limit::Tokens, range));
+    }
+    parser.builder.finish_node();
+    AST { node: parser.builder.finish(), errors: parser.errors, options }
+}
+
+/// How many tokens `parse_cancellable` reads between checks for cancellation
+const CANCEL_CHECK_INTERVAL: usize = 64;
+
+/// Parse tokens into an AST like `parse_with_options`, but stop early and
+/// return `Err(Cancelled)` once `is_cancelled` returns true. It is called
+/// before the first token and then every so often while parsing, so it
+/// should be cheap, such as loading an `AtomicBool`.
+pub fn parse_cancellable<I, S, F>(
+    iter: I,
+    options: ParseOptions,
+    mut is_cancelled: F,
+) -> Result<AST, Cancelled>
+where
+    I: IntoIterator<Item = (SyntaxKind, S)>,
+    S: Into<SmolStr>,
+    F: FnMut() -> bool,
+{
+    let cancelled = Cell::new(false);
+    let mut iter = iter.into_iter();
+    let mut count = 0;
+    // Ending the tokens early makes the parser finish quickly
+    let tokens = std::iter::from_fn(|| {
+        if cancelled.get() {
+            return None;
+        }
+        if count % CANCEL_CHECK_INTERVAL == 0 && is_cancelled() {
+            cancelled.set(true);
+            return None;
+        }
+        count += 1;
+        iter.next()
+    });
+    let ast = parse_with_options(tokens, options);
+    if cancelled.get() {
+        return Err(Cancelled);
+    }
+    Ok(ast)
+}
+
+/// Return an AST that keeps all of the input in a single error token, for
+/// input over the size limit. Fragments other than expressions get an empty
+/// node in front of it.
+pub(crate) fn unparsed(input: &str, options: ParseOptions, kind: FragmentKind) -> AST {
+    let mut builder = GreenNodeBuilder::new();
+    builder.start_node(NixLanguage::kind_to_raw(NODE_ROOT));
+    if let Some(kind) = kind.node_kind() {
+        builder.start_node(NixLanguage::kind_to_raw(kind));
+        builder.finish_node();
+    }
+    builder.token(NixLanguage::kind_to_raw(TOKEN_ERROR), SmolStr::new(input));
+    builder.finish_node();
+    let range = TextRange::offset_len(0.into(), TextUnit::of_str(input));
+    AST {
+        node: builder.finish(),
+        errors: vec![ParseError::LimitExceeded(Limit::InputSize, range)],
+        options,
+    }
+}
+
+/// Parse tokens that make up exactly one value, such as a set or a list.
+/// Returns None if there are tokens left over or the end of input is hit
+/// while parsing, since then the value would not parse the same on its own.
+pub(crate) fn parse_value<I, S>(
+    iter: I,
+    options: ParseOptions,
+) -> Option<(GreenNode, Vec<ParseError>)>
+where
+    I: IntoIterator<Item = (SyntaxKind, S)>,
+    S: Into<SmolStr>,
+{
+    let iter = iter.into_iter().map(|(kind, text)| (kind, text.into()));
+    let mut parser = Parser::new(iter, options);
+    parser.builder.start_node(NixLanguage::kind_to_raw(NODE_ROOT));
+    parser.parse_val();
+    if parser.too_deep.is_some() {
+        return None;
+    }
+    let complete = parser.peek_raw().is_none();
     parser.builder.finish_node();
-    AST { node: parser.builder.finish(), errors: parser.errors }
+    let root = parser.builder.finish();
+
+    let eof = parser
+        .errors
+        .iter()
+        .any(|err| matches!(err, ParseError::UnexpectedEOF | ParseError::UnexpectedEOFWanted(_)));
+    if !complete || eof || root.children().len() != 1 {
+        return None;
+    }
+    let node = root.children().next()?.into_node()?.clone();
+    Some((node, parser.errors))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
-    use std::{ffi::OsStr, fmt::Write, fs, path::PathBuf};
+    use crate::Assoc;
+    use std::{cmp::Ordering, ffi::OsStr, fmt::Write, fs, path::PathBuf};
 
     #[test]
     fn whitespace_attachment_for_incomplete_code1() {
@@ -732,7 +1287,7 @@ mod tests {
     pred:
 
 ";
-        let ast = crate::parse(&code);
+        let ast = crate::parse(code);
         let actual = format!("{}", ast.root().dump());
         // The core thing we want to check here is that `\n\n` belongs to the
         // root node, and not to some incomplete inner node.
@@ -772,7 +1327,7 @@ NODE_ROOT 0..50 {
     fn whitespace_attachment_for_incomplete_code2() {
         let code = "{} =
 ";
-        let ast = crate::parse(&code);
+        let ast = crate::parse(code);
         let actual = format!("{}", ast.root().dump());
         assert_eq!(
             actual.trim(),
@@ -792,6 +1347,186 @@ NODE_ROOT 0..5 {
         );
     }
 
+    #[test]
+    fn lexical_errors() {
+        let ast = crate::parse("{\n  a = ''\n    abc");
+        assert_eq!(ast.errors()[0].to_string(), "unterminated multiline string starting at 8..10");
+        let ast = crate::parse("[ 1.0e ]");
+        assert_eq!(
+            ast.errors(),
+            vec![ParseError::Lexical(
+                LexError::MissingExponent,
+                TextRange::from_to(2.into(), 6.into())
+            )]
+        );
+    }
+    #[test]
+    fn deep_nesting() {
+        let n = 5_000;
+        let cases = [
+            format!("{}1{}", "(".repeat(n), ")".repeat(n)),
+            format!("{}{}", "[".repeat(n), "]".repeat(n)),
+            format!("{}1{}", "{ a = ".repeat(n), "; }".repeat(n)),
+            format!("{}1{}", "\"${".repeat(n), "}\"".repeat(n)),
+            format!("{}1", "x: ".repeat(n)),
+            format!("{}1", "let a = 1; in ".repeat(n)),
+            format!("{}1", "-".repeat(n)),
+            format!("{}true", "!".repeat(n)),
+            format!("a{}", ".a".repeat(n)),
+            format!("a{}", " a".repeat(n)),
+            format!("1{}", " + 1".repeat(n)),
+            format!("f{}", " <| f".repeat(n)),
+            format!("[ {} ]", "(a ".repeat(n)),
+        ];
+        for code in &cases {
+            let options = ParseOptions { pipe_operators: true, ..ParseOptions::default() };
+            let ast = crate::parse_with_options(code, options);
+            assert!(ast.node().text() == code.as_str(), "{}", &code[..20]);
+            let depth = ast.errors().into_iter().find_map(|err| match err {
+                ParseError::LimitExceeded(Limit::Depth, range) => Some(range),
+                _ => None,
+            });
+            assert!(depth.is_some(), "{}", &code[..20]);
+        }
+        let ast = crate::parse(&format!("{}1{}", "[".repeat(10), "]".repeat(10)));
+        assert_eq!(ast.errors(), Vec::new());
+        let options = ParseOptions { max_depth: Some(10), ..ParseOptions::default() };
+        let ast = crate::parse_with_options(
+            "[ [ [ [ [ [ [ [ [ [ [ [ 1 2 ] ] ] ] ] ] ] ] ] ] ] 3 ]",
+            options,
+        );
+        assert_eq!(
+            ast.errors(),
+            vec![ParseError::LimitExceeded(Limit::Depth, TextRange::from_to(18.into(), 53.into()))]
+        );
+    }
+    #[test]
+    fn limits() {
+        let code = "{ a = 1; b = [ 2 3 ]; }";
+        let options = ParseOptions { max_tokens: Some(8), ..ParseOptions::default() };
+        let ast = crate::parse_with_options(code, options);
+        assert_eq!(ast.node().text(), code);
+        assert_eq!(
+            ast.errors()[1..],
+            [ParseError::LimitExceeded(Limit::Tokens, TextRange::from_to(8.into(), 23.into()))]
+        );
+        let options = ParseOptions { max_tokens: Some(30), ..ParseOptions::default() };
+        assert_eq!(crate::parse_with_options(code, options).errors(), Vec::new());
+
+        let options = ParseOptions { max_input_size: Some(10), ..ParseOptions::default() };
+        let ast = crate::parse_with_options(code, options);
+        assert_eq!(ast.node().text(), code);
+        assert_eq!(
+            ast.errors(),
+            vec![ParseError::LimitExceeded(
+                Limit::InputSize,
+                TextRange::from_to(0.into(), 23.into())
+            )]
+        );
+        assert_eq!(crate::parse_with_options("[ 1 ]", options).errors(), Vec::new());
+    }
+    #[test]
+    fn cancellation() {
+        let code = format!("[ {}]", "{ a = 1; } ".repeat(1000));
+        let tokenizer = crate::tokenizer::Tokenizer::new(&code);
+        let mut checks = 0;
+        let result = parse_cancellable(tokenizer, ParseOptions::default(), || {
+            checks += 1;
+            checks > 10
+        });
+        assert_eq!(result.err(), Some(Cancelled));
+        assert_eq!(checks, 11);
+
+        let tokenizer = crate::tokenizer::Tokenizer::new(&code);
+        let ast = parse_cancellable(tokenizer, ParseOptions::default(), || false).unwrap();
+        assert!(ast.node == crate::parse(&code).node);
+        assert_eq!(ast.errors(), Vec::new());
+    }
+    #[test]
+    fn disabled_features() {
+        let strict = ParseOptions {
+            pipe_operators: false,
+            uri_literals: false,
+            legacy_let: false,
+            or_as_ident: false,
+            ..ParseOptions::default()
+        };
+        let cases = [
+            ("x |> f", SyntaxFeature::PipeOperators, 2..4),
+            ("f <| x", SyntaxFeature::PipeOperators, 2..4),
+            ("[ https://nixos.org ]", SyntaxFeature::UriLiterals, 2..19),
+            (" let { body = 1; }", SyntaxFeature::LegacyLet, 1..4),
+            ("{ or = 1; }", SyntaxFeature::OrAsIdent, 2..4),
+        ];
+        for (code, feature, range) in cases.iter().cloned() {
+            let range = TextRange::from_to((range.start as u32).into(), (range.end as u32).into());
+            let ast = crate::parse_with_options(code, strict);
+            assert_eq!(ast.errors(), vec![ParseError::DisabledFeature(feature, range)], "{}", code);
+            // The tree is the same whether the feature is on or not
+            let enabled = crate::parse_with_options(
+                code,
+                ParseOptions { pipe_operators: true, ..ParseOptions::default() },
+            );
+            assert_eq!(enabled.errors(), Vec::new(), "{}", code);
+            assert_eq!(ast.node, enabled.node, "{}", code);
+        }
+    }
+    #[test]
+    fn pipe_associativity() {
+        let options = ParseOptions { pipe_operators: true, ..ParseOptions::default() };
+        let dump = |code| {
+            let ast = crate::parse_with_options(code, options);
+            assert_eq!(ast.errors(), Vec::new());
+            let mut out = String::new();
+            for node in ast.node().descendants() {
+                if node.kind() == NODE_BIN_OP {
+                    writeln!(out, "{}", node.text()).unwrap();
+                }
+            }
+            out
+        };
+        assert_eq!(dump("x |> f |> g"), "x |> f |> g\nx |> f\n");
+        assert_eq!(dump("g <| f <| x"), "g <| f <| x\nf <| x\n");
+        assert_eq!(dump("x |> a -> b"), "x |> a -> b\na -> b\n");
+    }
+
+    #[test]
+    fn binary_op_table() {
+        // The precedence table in kinds.rs should match the trees built here
+        let options = ParseOptions { pipe_operators: true, ..ParseOptions::default() };
+        let ops: Vec<SyntaxKind> = (0..__LAST as u16)
+            .map(|raw| NixLanguage::kind_from_raw(rowan::SyntaxKind(raw)))
+            .filter(|kind| kind.binary_op().is_some())
+            .collect();
+        for &first in &ops {
+            for &second in &ops {
+                let (prec1, assoc) = first.binary_op().unwrap();
+                let (prec2, _) = second.binary_op().unwrap();
+                let left = match prec1.cmp(&prec2) {
+                    Ordering::Greater => true,
+                    Ordering::Less => false,
+                    // Only the same pipe operator chains
+                    _ if prec1 == 1 && first != second => continue,
+                    _ => match assoc {
+                        Assoc::Left => true,
+                        Assoc::Right => false,
+                        Assoc::None => continue,
+                    },
+                };
+                let code = format!(
+                    "a {} b {} c",
+                    first.token_text().unwrap(),
+                    second.token_text().unwrap()
+                );
+                let ast = crate::parse_with_options(&code, options);
+                assert_eq!(ast.errors(), Vec::new(), "{}", code);
+                let root = ast.node().first_child().unwrap();
+                let lhs = root.first_child().unwrap();
+                assert_eq!(lhs.kind() == NODE_BIN_OP, left, "{}", code);
+            }
+        }
+    }
+
     fn test_dir(name: &str) {
         let dir: PathBuf = ["test_data", name].iter().collect();
 
@@ -0,0 +1,469 @@
+//! Incremental reparsing: update an AST after a text edit without parsing
+//! all of the text again
+
+use rowan::{GreenNode, GreenToken, Language, TextRange, TextUnit, TokenAtOffset};
+
+use crate::{
+    parser::{self, ParseError, ParseOptions, AST},
+    tokenizer::Tokenizer,
+    NixLanguage, NodeOrToken,
+    SyntaxKind::{self, *},
+    SyntaxNode, SyntaxToken,
+};
+
+/// A change to the text of an AST: the text in `delete` is replaced by
+/// `insert`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TextEdit {
+    /// The range of the old text to remove
+    pub delete: TextRange,
+    /// The text to insert in its place
+    pub insert: String,
+}
+impl TextEdit {
+    /// Create an edit replacing a range of text
+    pub fn replace(delete: TextRange, insert: impl Into<String>) -> Self {
+        Self { delete, insert: insert.into() }
+    }
+    /// Create an edit inserting text at an offset
+    pub fn insert(offset: TextUnit, insert: impl Into<String>) -> Self {
+        Self::replace(TextRange::offset_len(offset, 0.into()), insert)
+    }
+    /// Create an edit deleting a range of text
+    pub fn delete(delete: TextRange) -> Self {
+        Self::replace(delete, String::new())
+    }
+    /// Apply the edit to `text`, which starts at `offset` in the whole text
+    fn apply(&self, text: &str, offset: TextUnit) -> String {
+        let start = (self.delete.start() - offset).to_usize();
+        let end = (self.delete.end() - offset).to_usize();
+        let mut text = text.to_owned();
+        text.replace_range(start..end, &self.insert);
+        text
+    }
+}
+
+impl AST {
+    /// Apply an edit to the text of this AST and parse the result. When the
+    /// edit stays inside a single token, or inside a balanced `{}`, `[]` or
+    /// `()` block, only that part is relexed and reparsed and the rest of the
+    /// tree is reused. Otherwise everything is parsed again. Either way, the
+    /// returned AST is identical to what parsing the new text would produce.
+    /// With a token or input size limit set, everything is always parsed
+    /// again, since whether the limit is hit depends on all of the text.
+    ///
+    /// Panics if the edit is out of bounds.
+    pub fn reparse(&self, edit: TextEdit) -> AST {
+        let root = self.node();
+        assert!(
+            edit.delete.end() <= root.text_range().end(),
+            "edit {} is out of bounds",
+            edit.delete
+        );
+        let limited = self.options.max_tokens.is_some() || self.options.max_input_size.is_some();
+        let reparsed = if limited {
+            None
+        } else {
+            reparse_token(self, &root, &edit).or_else(|| reparse_block(self, &root, &edit))
+        };
+        reparsed.unwrap_or_else(|| {
+            let text = edit.apply(&root.text().to_string(), 0.into());
+            crate::parse_with_options(&text, self.options)
+        })
+    }
+}
+
+fn is_path_char(c: char) -> bool {
+    matches!(c, 'a'..='z' | 'A'..='Z' | '0'..='9' | '_' | '.' | '+' | '-' | '/' | '<')
+}
+
+/// Move the range of an error by applying `shift` to its offsets
+fn shift_error(err: &ParseError, shift: impl Fn(TextUnit) -> TextUnit) -> ParseError {
+    let shift_range =
+        |range: TextRange| TextRange::from_to(shift(range.start()), shift(range.end()));
+    match err {
+        ParseError::Unexpected(range) => ParseError::Unexpected(shift_range(*range)),
+        ParseError::UnexpectedExtra(range) => ParseError::UnexpectedExtra(shift_range(*range)),
+        ParseError::UnexpectedWanted(got, range, kinds) => {
+            ParseError::UnexpectedWanted(*got, shift_range(*range), kinds.clone())
+        }
+        ParseError::UnexpectedDoubleBind(range) => {
+            ParseError::UnexpectedDoubleBind(shift_range(*range))
+        }
+        ParseError::DisabledFeature(feature, range) => {
+            ParseError::DisabledFeature(*feature, shift_range(*range))
+        }
+        ParseError::Lexical(err, range) => {
+            ParseError::Lexical(err.map_start(shift_range), shift_range(*range))
+        }
+        ParseError::LimitExceeded(limit, range) => {
+            ParseError::LimitExceeded(*limit, shift_range(*range))
+        }
+        err => err.clone(),
+    }
+}
+/// Offsets after an element that changed from `old` to `new` move along
+fn shift_after(old: TextRange, new: TextRange) -> impl Fn(TextUnit) -> TextUnit + Copy {
+    move |offset| if offset >= old.end() { offset - old.end() + new.end() } else { offset }
+}
+pub(crate) fn error_range(err: &ParseError) -> Option<TextRange> {
+    match err {
+        ParseError::Unexpected(range)
+        | ParseError::UnexpectedExtra(range)
+        | ParseError::UnexpectedWanted(_, range, _)
+        | ParseError::UnexpectedDoubleBind(range)
+        | ParseError::DisabledFeature(_, range)
+        | ParseError::Lexical(_, range)
+        | ParseError::LimitExceeded(_, range) => Some(*range),
+        ParseError::UnexpectedEOF | ParseError::UnexpectedEOFWanted(_) => None,
+    }
+}
+
+/// Try to relex the single token containing the edit
+fn reparse_token(ast: &AST, root: &SyntaxNode, edit: &TextEdit) -> Option<AST> {
+    let tokens = match root.token_at_offset(edit.delete.start()) {
+        TokenAtOffset::None => return None,
+        TokenAtOffset::Single(token) => vec![token],
+        TokenAtOffset::Between(left, right) => vec![left, right],
+    };
+    tokens.into_iter().filter(|token| edit.delete.is_subrange(&token.text_range())).find_map(
+        |token| {
+            let green = relex_token(&token, edit)?;
+            let old = token.text_range();
+            let new = TextRange::offset_len(old.start(), TextUnit::of_str(green.text()));
+            Some(AST {
+                node: token.replace_with(green),
+                errors: ast
+                    .errors
+                    .iter()
+                    .map(|err| shift_error(err, shift_after(old, new)))
+                    .collect(),
+                options: ast.options,
+            })
+        },
+    )
+}
+fn relex_token(token: &SyntaxToken, edit: &TextEdit) -> Option<GreenToken> {
+    let kind = token.kind();
+    let text = edit.apply(token.text(), token.text_range().start());
+    if text.is_empty() {
+        return None;
+    }
+
+    let (new_kind, new_text) = match kind {
+        TOKEN_WHITESPACE | TOKEN_COMMENT | TOKEN_IDENT => {
+            // `or` is only a keyword in some places, and whether `.5` is a
+            // float depends on what comes before it
+            if kind == TOKEN_IDENT
+                && (text == "or"
+                    || token.text() == "or"
+                    || token.next_token().is_some_and(|next| next.text().starts_with('.')))
+            {
+                return None;
+            }
+            // Line comments end at the first newline, and looking for paths
+            // the tokenizer scans ahead from the start of each token. Make sure
+            // the previous token can't have seen this one.
+            let prev_is_separate = token.prev_token().map_or(true, |prev| match prev.kind() {
+                TOKEN_COMMENT if prev.text().starts_with('#') => text.starts_with('\n'),
+                TOKEN_WHITESPACE | TOKEN_COMMENT => true,
+                _ => prev.text().chars().last().is_some_and(|c| !is_path_char(c) && c != ':'),
+            });
+            if !prev_is_separate {
+                return None;
+            }
+            // ... and give it the same view ahead when lexing the new token
+            let mut input = text.clone();
+            let mut next = token.next_token();
+            while let Some(token) = next {
+                input.push_str(token.text());
+                let end = input[text.len()..].find(|c| !is_path_char(c)).map(|i| text.len() + i);
+                if end.is_some_and(|end| input[end..].chars().count() >= 2) {
+                    break;
+                }
+                next = token.next_token();
+            }
+            Tokenizer::new(&input).next()?
+        }
+        TOKEN_STRING_CONTENT => {
+            let multiline = token.parent().first_token().is_some_and(|start| start.text() == "''");
+            let mut input = text.clone();
+            if let Some(next) = token.next_token() {
+                input.push_str(next.text());
+            }
+            Tokenizer::new_string_body(&input, multiline).next()?
+        }
+        _ => return None,
+    };
+
+    if new_kind != kind || new_text != text {
+        return None;
+    }
+    Some(GreenToken::new(NixLanguage::kind_to_raw(kind), new_text))
+}
+
+/// Try to reparse the innermost balanced block containing the edit
+fn reparse_block(ast: &AST, root: &SyntaxNode, edit: &TextEdit) -> Option<AST> {
+    let node = match root.covering_element(edit.delete) {
+        NodeOrToken::Node(node) => node,
+        NodeOrToken::Token(token) => token.parent(),
+    };
+    node.ancestors().find_map(|node| {
+        let (green, errors) = reparse_node(&node, edit, ast.options)?;
+        let old = node.text_range();
+        let new = TextRange::offset_len(old.start(), green.text_len());
+
+        let before = ast
+            .errors
+            .iter()
+            .filter(|err| error_range(err).is_some_and(|r| r.end() <= old.start()));
+        let after = ast
+            .errors
+            .iter()
+            .filter(|err| error_range(err).map_or(true, |r| r.start() >= old.end()));
+        let errors = before
+            .cloned()
+            .chain(errors.iter().map(|err| shift_error(err, |offset| offset + old.start())))
+            .chain(after.map(|err| shift_error(err, shift_after(old, new))))
+            .collect();
+        Some(AST { node: node.replace_with(green), errors, options: ast.options })
+    })
+}
+fn reparse_node(
+    node: &SyntaxNode,
+    edit: &TextEdit,
+    options: ParseOptions,
+) -> Option<(GreenNode, Vec<ParseError>)> {
+    let (open, close) = match node.kind() {
+        NODE_ATTR_SET => (TOKEN_CURLY_B_OPEN, TOKEN_CURLY_B_CLOSE),
+        NODE_LIST => (TOKEN_SQUARE_B_OPEN, TOKEN_SQUARE_B_CLOSE),
+        NODE_PAREN => (TOKEN_PAREN_OPEN, TOKEN_PAREN_CLOSE),
+        _ => return None,
+    };
+    let open_token = node
+        .children_with_tokens()
+        .filter_map(|child| child.into_token())
+        .find(|token| token.kind() == open)?;
+    let close_token = node
+        .last_child_or_token()
+        .and_then(|child| child.into_token())
+        .filter(|token| token.kind() == close)?;
+    if edit.delete.start() < open_token.text_range().end()
+        || edit.delete.end() > close_token.text_range().start()
+        || node.ancestors().any(|node| node.kind() == NODE_ERROR)
+    {
+        return None;
+    }
+
+    let text = edit.apply(&node.text().to_string(), node.text_range().start());
+    let tokens: Vec<_> = Tokenizer::new(&text).borrowed().collect();
+    if !is_balanced(&tokens, close) {
+        return None;
+    }
+    // Telling a set from a pattern looks at the first two tokens after `{`,
+    // the second of which must not be outside the block
+    if open == TOKEN_CURLY_B_OPEN {
+        let mut inner = tokens.iter().skip_while(|(kind, _)| *kind != open).skip(1);
+        if inner.all(|(kind, _)| kind.is_trivia() || *kind == close) {
+            return None;
+        }
+    }
+
+    let (green, errors) = parser::parse_value(tokens, options)?;
+    if green.kind() != node.green().kind() {
+        return None;
+    }
+    // The block was parsed starting at depth zero rather than where it is in
+    // the tree. Every level of the tree takes at most two levels of parsing,
+    // so if the tree stays well below the limit parsing everything would not
+    // hit it either.
+    if let Some(max) = options.max_depth {
+        let depth = node.ancestors().count() + green_depth(&green);
+        if 2 * (depth + 2) >= max
+            || errors.iter().any(|err| matches!(err, ParseError::LimitExceeded(..)))
+        {
+            return None;
+        }
+    }
+    Some((green, errors))
+}
+/// Return how many levels of nodes deep a tree is, without recursing
+fn green_depth(green: &GreenNode) -> usize {
+    let mut depth = 0;
+    let mut level = vec![green];
+    while !level.is_empty() {
+        depth += 1;
+        level = level
+            .into_iter()
+            .flat_map(|node| node.children().filter_map(|child| child.into_node()))
+            .collect();
+    }
+    depth
+}
+/// Check that the tokens are free of errors and that the first opening
+/// delimiter is closed by the last token, which is `close`
+fn is_balanced(tokens: &[(SyntaxKind, &str)], close: SyntaxKind) -> bool {
+    let mut depth = 0usize;
+    for (i, (kind, _)) in tokens.iter().enumerate() {
+        match kind {
+            TOKEN_ERROR => return false,
+            TOKEN_CURLY_B_OPEN | TOKEN_SQUARE_B_OPEN | TOKEN_PAREN_OPEN | TOKEN_DYNAMIC_START
+            | TOKEN_INTERPOL_START => depth += 1,
+            TOKEN_CURLY_B_CLOSE | TOKEN_SQUARE_B_CLOSE | TOKEN_PAREN_CLOSE | TOKEN_DYNAMIC_END
+            | TOKEN_INTERPOL_END => match depth.checked_sub(1) {
+                Some(0) if i + 1 != tokens.len() => return false,
+                Some(new) => depth = new,
+                None => return false,
+            },
+            _ => (),
+        }
+    }
+    depth == 0 && tokens.last().is_some_and(|(kind, _)| *kind == close)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::TypedNode;
+
+    use std::{ffi::OsStr, fs, path::Path};
+
+    fn dump(ast: &AST) -> String {
+        let mut out = String::new();
+        for error in ast.errors() {
+            out.push_str(&format!("error: {}\n", error));
+        }
+        out.push_str(&format!("{}", ast.root().dump()));
+        out
+    }
+    fn check(code: &str, edit: TextEdit) {
+        let old = crate::parse(code);
+        let new = old.reparse(edit.clone());
+        let full = crate::parse(&edit.apply(code, 0.into()));
+        if new.node != full.node || new.errors() != full.errors() {
+            assert_eq!(dump(&new), dump(&full), "{:?} applied to {:?}", edit, code);
+        }
+    }
+
+    #[test]
+    fn token() {
+        let code = "{ foo = bar; /* comment */ baz = \"string\"; }";
+        let ast = crate::parse(code);
+        let edits = [
+            TextEdit::insert(4.into(), "d"),
+            TextEdit::insert(1.into(), "\n\n"),
+            TextEdit::replace(TextRange::from_to(16.into(), 23.into()), "remark"),
+            TextEdit::insert(37.into(), "ly"),
+        ];
+        for edit in edits.iter() {
+            assert!(reparse_token(&ast, &ast.node(), edit).is_some(), "{:?}", edit);
+            check(code, edit.clone());
+        }
+
+        let edits = [
+            // Keywords
+            TextEdit::replace(TextRange::from_to(2.into(), 5.into()), "in"),
+            // Paths
+            TextEdit::insert(5.into(), "/a"),
+            // Interpolation
+            TextEdit::insert(37.into(), "${"),
+            // Joined tokens
+            TextEdit::delete(TextRange::from_to(1.into(), 2.into())),
+        ];
+        for edit in edits.iter() {
+            assert!(reparse_token(&ast, &ast.node(), edit).is_none(), "{:?}", edit);
+            check(code, edit.clone());
+        }
+    }
+    #[test]
+    fn block() {
+        let code = "let a = [ 1 2 ]; in { inherit a; b = (a ++ [ 3 ]); }";
+        let ast = crate::parse(code);
+        let edits = [
+            TextEdit::insert(13.into(), " 5"),
+            TextEdit::replace(TextRange::from_to(22.into(), 32.into()), "c = { d = 1; };"),
+            TextEdit::insert(46.into(), " 4 (5)"),
+            TextEdit::insert(46.into(), " { x = 1; }"),
+        ];
+        for edit in edits.iter() {
+            assert!(reparse_block(&ast, &ast.node(), edit).is_some(), "{:?}", edit);
+            check(code, edit.clone());
+        }
+
+        let edits = [
+            // Unbalanced
+            TextEdit::insert(46.into(), " ]"),
+            TextEdit::insert(46.into(), " \""),
+            // Turns into a pattern
+            TextEdit::replace(TextRange::from_to(22.into(), 32.into()), "c, d"),
+            // Touches the delimiters
+            TextEdit::delete(TextRange::from_to(51.into(), 52.into())),
+        ];
+        for edit in edits.iter() {
+            assert!(reparse_block(&ast, &ast.node(), edit).is_none(), "{:?}", edit);
+            check(code, edit.clone());
+        }
+    }
+    #[test]
+    fn errors() {
+        let code = "{ a = (1 +); b = [ ( ]; c = { d = ; }; } x";
+        for &(offset, text) in &[(8, " "), (9, "2 "), (18, "2 "), (30, "e = 1; f")] {
+            check(code, TextEdit::insert(offset.into(), text));
+        }
+    }
+    #[test]
+    fn limits() {
+        fn check_with(options: ParseOptions, code: &str, edit: TextEdit) -> AST {
+            let new = crate::parse_with_options(code, options).reparse(edit.clone());
+            let full = crate::parse_with_options(&edit.apply(code, 0.into()), options);
+            assert_eq!(new.errors(), full.errors(), "{:?} applied to {:?}", edit, code);
+            assert!(new.node == full.node, "{:?} applied to {:?}", edit, code);
+            new
+        }
+        // Deep enough that an edit in the middle can hit the depth limit
+        let options = ParseOptions { max_depth: Some(40), ..ParseOptions::default() };
+        let code = format!("{}1{}", "( ".repeat(15), " )".repeat(15));
+        let offset = TextUnit::from_usize(2 * 15 + 1);
+        for &insert in &[" + 2", " + ((2))"] {
+            let ast = check_with(options, &code, TextEdit::insert(offset, insert));
+            assert_eq!(ast.errors(), Vec::new());
+        }
+        let ast = check_with(options, &code, TextEdit::insert(offset, " + ((((((2))))))"));
+        assert_eq!(ast.errors().len(), 1);
+
+        let options = ParseOptions { max_tokens: Some(12), ..ParseOptions::default() };
+        check_with(options, "{ a = [ 1 2 ]; b = 3; }", TextEdit::insert(9.into(), "0 "));
+    }
+    /// Check edits at every `step`th character of each file in the corpus
+    fn corpus(step: usize) {
+        fn visit(dir: &Path, step: usize) {
+            for entry in fs::read_dir(dir).unwrap() {
+                let path = entry.unwrap().path();
+                if path.is_dir() {
+                    visit(&path, step);
+                } else if path.extension() == Some(OsStr::new("nix")) {
+                    let code = fs::read_to_string(&path).unwrap();
+                    for (offset, c) in code.char_indices().step_by(step) {
+                        let offset = TextUnit::from_usize(offset);
+                        let range = TextRange::offset_len(offset, TextUnit::of_char(c));
+                        for insert in &["\n", "x", "}", "\"", "${"] {
+                            check(&code, TextEdit::insert(offset, *insert));
+                        }
+                        check(&code, TextEdit::delete(range));
+                        check(&code, TextEdit::replace(range, "y"));
+                    }
+                }
+            }
+        }
+        visit(&Path::new(env!("CARGO_MANIFEST_DIR")).join("test_data"), step);
+    }
+    #[test]
+    fn corpus_sampled() {
+        corpus(13);
+    }
+    #[test]
+    #[ignore = "slow, run with --ignored after changing the parser or reparsing"]
+    fn corpus_exhaustive() {
+        corpus(1);
+    }
+}
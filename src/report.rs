@@ -0,0 +1,330 @@
+//! Render diagnostics as snippets of the source, in the style of rustc
+
+use std::fmt::{self, Write};
+
+use crate::{
+    diagnostic::{Diagnostic, Label, Severity},
+    line_index::{ColumnUnit, LineColRange, LineIndex},
+    parser::ParseError,
+    TextUnit,
+};
+
+const RED: &str = "\x1b[1;31m";
+const YELLOW: &str = "\x1b[1;33m";
+const BLUE: &str = "\x1b[1;34m";
+const BOLD: &str = "\x1b[1m";
+const RESET: &str = "\x1b[0m";
+
+/// Renders diagnostics against the source code they were found in:
+///
+/// ```text
+/// error[E003]: unexpected `}`
+///  --> default.nix:1:9
+///   |
+/// 1 | { a = 1 }
+///   |         ^ wanted `;`
+///   = help: insert `;`
+/// ```
+pub struct Report<'a> {
+    name: &'a str,
+    source: &'a str,
+    index: LineIndex,
+    color: bool,
+}
+
+impl<'a> Report<'a> {
+    /// Create a report for the source code in a file called `name`
+    pub fn new(name: &'a str, source: &'a str) -> Self {
+        Self { name, source, index: LineIndex::new(source), color: false }
+    }
+    /// Set whether to color the output using ANSI escape codes
+    pub fn color(mut self, color: bool) -> Self {
+        self.color = color;
+        self
+    }
+    /// Render one error
+    pub fn render(&self, error: &ParseError) -> String {
+        self.render_diagnostic(&self.diagnostic(error))
+    }
+    /// Render several errors, separated by empty lines
+    pub fn render_all(&self, errors: &[ParseError]) -> String {
+        let diagnostics: Vec<_> = errors.iter().map(|error| self.diagnostic(error)).collect();
+        self.render_diagnostics(&diagnostics)
+    }
+    /// Render one diagnostic
+    pub fn render_diagnostic(&self, diagnostic: &Diagnostic) -> String {
+        let mut out = String::new();
+        self.write(&mut out, diagnostic).unwrap();
+        out
+    }
+    /// Render several diagnostics, separated by empty lines
+    pub fn render_diagnostics(&self, diagnostics: &[Diagnostic]) -> String {
+        let mut out = String::new();
+        for (i, diagnostic) in diagnostics.iter().enumerate() {
+            if i > 0 {
+                out.push('\n');
+            }
+            self.write(&mut out, diagnostic).unwrap();
+        }
+        out
+    }
+    /// Write one diagnostic to `out`
+    pub fn write<W: Write>(&self, out: &mut W, diagnostic: &Diagnostic) -> fmt::Result {
+        let style = match diagnostic.severity {
+            Severity::Error => RED,
+            Severity::Warning => YELLOW,
+        };
+        let primary = self.span(&diagnostic.primary);
+        let spans: Vec<_> = diagnostic.secondary.iter().map(|label| self.span(label)).collect();
+        let last_line = spans.iter().chain(Some(&primary)).map(|span| span.end.line).max().unwrap();
+        let width = (last_line + 1).to_string().len();
+        let gutter = self.paint(BLUE, &format!("{:width$} |", "", width = width));
+
+        writeln!(
+            out,
+            "{}{}",
+            self.paint(style, &format!("{}[{}]", diagnostic.severity, diagnostic.code)),
+            self.paint(BOLD, &format!(": {}", diagnostic.message))
+        )?;
+        writeln!(
+            out,
+            "{:width$}{} {}:{}:{}",
+            "",
+            self.paint(BLUE, "-->"),
+            self.name,
+            primary.start.line + 1,
+            primary.start.col + 1,
+            width = width
+        )?;
+        writeln!(out, "{}", gutter)?;
+
+        let mut labels: Vec<_> = diagnostic
+            .secondary
+            .iter()
+            .zip(spans)
+            .map(|(label, span)| (span, &label.message, BLUE, '-'))
+            .chain(Some((primary, &diagnostic.primary.message, style, '^')))
+            .collect();
+        labels.sort_by_key(|(span, ..)| span.start);
+
+        let mut prev_line = None;
+        for (span, message, style, mark) in labels {
+            let first = match prev_line {
+                // Don't print the same line twice
+                Some(prev) if span.start.line <= prev => prev + 1,
+                Some(prev) if span.start.line > prev + 1 => {
+                    writeln!(out, "{}", self.paint(BLUE, "..."))?;
+                    span.start.line
+                }
+                _ => span.start.line,
+            };
+            for line in span.start.line..=span.end.line {
+                if line >= first {
+                    self.write_line(out, line, width)?;
+                }
+                let text = self.line(line);
+                let start = if line == span.start.line { span.start.col as usize } else { 0 };
+                let end = if line == span.end.line {
+                    span.end.col as usize
+                } else {
+                    text.chars().count()
+                };
+                let marks = mark.to_string();
+                if line == span.end.line {
+                    // Empty ranges, like the end of the file, still get marked
+                    let marks = marks.repeat(end.saturating_sub(start).max(1));
+                    let marks = self.paint(style, &format!("{} {}", marks, message));
+                    writeln!(out, "{} {:start$}{}", gutter, "", marks, start = start)?;
+                } else if end > start {
+                    let marks = self.paint(style, &marks.repeat(end - start));
+                    writeln!(out, "{} {:start$}{}", gutter, "", marks, start = start)?;
+                }
+            }
+            prev_line = Some(prev_line.map_or(span.end.line, |prev: u32| prev.max(span.end.line)));
+        }
+
+        for note in &diagnostic.notes {
+            writeln!(out, "{:width$} {} note: {}", "", self.paint(BLUE, "="), note, width = width)?;
+        }
+        if let Some(fix) = &diagnostic.fix {
+            writeln!(
+                out,
+                "{:width$} {} help: {}",
+                "",
+                self.paint(BLUE, "="),
+                fix.message,
+                width = width
+            )?;
+        }
+        Ok(())
+    }
+
+    fn diagnostic(&self, error: &ParseError) -> Diagnostic {
+        Diagnostic::from_error(error, TextUnit::of_str(self.source))
+    }
+    fn line(&self, line: u32) -> String {
+        self.source[self.index.line_range(line).unwrap()].replace('\t', " ")
+    }
+    fn write_line<W: Write>(&self, out: &mut W, line: u32, width: usize) -> fmt::Result {
+        let text = self.line(line);
+        let number = self.paint(BLUE, &format!("{:width$} |", line + 1, width = width));
+        if text.is_empty() {
+            writeln!(out, "{}", number)
+        } else {
+            writeln!(out, "{} {}", number, text)
+        }
+    }
+    /// Return the lines and columns to mark for a label
+    fn span(&self, label: &Label) -> LineColRange {
+        let range = label.range;
+        let mut span = self.index.range(range, ColumnUnit::Char);
+        // Point at the last line with code on it rather than at a trailing
+        // newline, both for ranges ending with one and for the end of file
+        let trailing = range.is_empty() && range.end() == TextUnit::of_str(self.source);
+        if span.end.col == 0 && span.end.line > 0 && (span.end.line > span.start.line || trailing) {
+            let line = self.index.line_range(span.end.line - 1).unwrap();
+            span.end = self.index.line_col(line.end(), ColumnUnit::Char);
+            if trailing {
+                span.start = span.end;
+            }
+        }
+        span
+    }
+    fn paint(&self, style: &str, text: &str) -> String {
+        if self.color {
+            format!("{}{}{}", style, text, RESET)
+        } else {
+            text.to_string()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{SyntaxKind, TextRange};
+
+    fn range(start: u32, end: u32) -> TextRange {
+        TextRange::from_to(start.into(), end.into())
+    }
+
+    #[test]
+    fn single_line() {
+        let code = "{ a = 1; b = ; }";
+        let errors = crate::parse(code).errors();
+        assert_eq!(
+            Report::new("default.nix", code).render_all(&errors[..1]),
+            "\
+error[E003]: unexpected `;`
+ --> default.nix:1:14
+  |
+1 | { a = 1; b = ; }
+  |              ^ wanted any of `(`, `rec`, `{`, `[`, `${`, string, identifier
+"
+        );
+    }
+    #[test]
+    fn multi_line() {
+        let code = "[\r\n\tfoo\r\n\r\n  bar\r\n]\r\n";
+        let report = Report::new("a.nix", code);
+        assert_eq!(
+            report.render(&ParseError::Unexpected(range(4, 16))),
+            "\
+error[E001]: unexpected input
+ --> a.nix:2:2
+  |
+2 |  foo
+  |  ^^^
+3 |
+4 |   bar
+  | ^^^^^ this could not be parsed
+"
+        );
+        // Ending with a newline doesn't show the next line
+        assert_eq!(
+            report.render(&ParseError::UnexpectedExtra(range(3, 9))),
+            "\
+error[E002]: unexpected input after the expression
+ --> a.nix:2:1
+  |
+2 |  foo
+  | ^^^^ wanted end of file
+  = note: a Nix file is a single expression
+"
+        );
+    }
+    #[test]
+    fn end_of_file() {
+        let code = "{\n  a = 1\n";
+        let report = Report::new("a.nix", code);
+        assert_eq!(
+            report
+                .render(&ParseError::UnexpectedEOFWanted(Box::new([SyntaxKind::TOKEN_SEMICOLON]))),
+            "\
+error[E006]: unexpected end of file
+ --> a.nix:2:8
+  |
+2 |   a = 1
+  |        ^ wanted `;`
+"
+        );
+        assert_eq!(
+            Report::new("empty.nix", "").render(&ParseError::UnexpectedEOF),
+            "\
+error[E005]: unexpected end of file
+ --> empty.nix:1:1
+  |
+1 |
+  | ^ more input wanted
+"
+        );
+    }
+    #[test]
+    fn labels() {
+        let code = "let\n  a = [\n    1\n\n    2\n";
+        let ast = crate::parse(code);
+        let diagnostics = ast.diagnostics();
+        assert_eq!(
+            Report::new("a.nix", code).render_diagnostic(diagnostics.last().unwrap()),
+            "\
+error[E005]: unexpected end of file
+ --> a.nix:5:6
+  |
+2 |   a = [
+  |       - unclosed `[`
+...
+5 |     2
+  |      ^ more input wanted
+"
+        );
+        let code = "{\n  a = 1\n}";
+        let ast = crate::parse(code);
+        assert_eq!(
+            Report::new("a.nix", code).render_diagnostic(&ast.diagnostics()[0]),
+            "\
+error[E003]: unexpected `}`
+ --> a.nix:3:1
+  |
+3 | }
+  | ^ wanted `;`
+  = help: insert `;`
+"
+        );
+    }
+    #[test]
+    fn color() {
+        let report = Report::new("a.nix", "x |> f").color(true);
+        let feature = crate::parser::SyntaxFeature::PipeOperators;
+        assert_eq!(
+            report.render(&ParseError::DisabledFeature(feature, range(2, 4))),
+            "\
+\x1b[1;31merror[E007]\x1b[0m\x1b[1m: pipe operator is not enabled\x1b[0m
+ \x1b[1;34m-->\x1b[0m a.nix:1:3
+\x1b[1;34m  |\x1b[0m
+\x1b[1;34m1 |\x1b[0m x |> f
+\x1b[1;34m  |\x1b[0m   \x1b[1;31m^^ not enabled\x1b[0m
+  \x1b[1;34m=\x1b[0m note: this syntax can be turned on in the `ParseOptions`
+"
+        );
+    }
+}
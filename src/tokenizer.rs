@@ -1,8 +1,75 @@
 //! The tokenizer: turns a string into tokens, such as numbers, strings, and keywords
 
+use std::fmt;
+
 use rowan::SmolStr;
 
-use crate::SyntaxKind::{self, *};
+use crate::{
+    SyntaxKind::{self, *},
+    TextRange, TextUnit,
+};
+
+/// Why the tokenizer emitted a `TOKEN_ERROR`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum LexError {
+    /// A string that is never closed, with the range of its opening quote
+    UnterminatedString { multiline: bool, start: TextRange },
+    /// A `/*` comment that is never closed, with the range of the `/*`
+    UnterminatedComment { start: TextRange },
+    /// A path such as `./a/`, which must not end with a `/`
+    PathEndsWithSlash,
+    /// A `~` that isn't followed by a `/`
+    HomeWithoutSlash,
+    /// A float with an exponent missing its digits, such as `1.5e`
+    MissingExponent,
+    /// A `<store path` with no closing `>`, with the range of the `<`
+    UnclosedStorePath { start: TextRange },
+    /// A character that can't start any token
+    UnexpectedChar,
+}
+
+impl LexError {
+    /// Return the range of the opening delimiter that was never closed, if
+    /// this error is about one
+    pub fn start(&self) -> Option<TextRange> {
+        match *self {
+            LexError::UnterminatedString { start, .. }
+            | LexError::UnterminatedComment { start }
+            | LexError::UnclosedStorePath { start } => Some(start),
+            _ => None,
+        }
+    }
+    /// Return the same error with its opening delimiter moved
+    pub(crate) fn map_start(self, f: impl FnOnce(TextRange) -> TextRange) -> Self {
+        match self {
+            LexError::UnterminatedString { multiline, start } => {
+                LexError::UnterminatedString { multiline, start: f(start) }
+            }
+            LexError::UnterminatedComment { start } => {
+                LexError::UnterminatedComment { start: f(start) }
+            }
+            LexError::UnclosedStorePath { start } => {
+                LexError::UnclosedStorePath { start: f(start) }
+            }
+            err => err,
+        }
+    }
+}
+
+impl fmt::Display for LexError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            LexError::UnterminatedString { multiline: false, .. } => "unterminated string",
+            LexError::UnterminatedString { multiline: true, .. } => "unterminated multiline string",
+            LexError::UnterminatedComment { .. } => "unterminated comment",
+            LexError::PathEndsWithSlash => "path ends with a `/`",
+            LexError::HomeWithoutSlash => "`~` not followed by `/`",
+            LexError::MissingExponent => "missing digits in float exponent",
+            LexError::UnclosedStorePath { .. } => "unclosed store path",
+            LexError::UnexpectedChar => "unexpected character",
+        })
+    }
+}
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 enum IdentType {
@@ -12,37 +79,133 @@ enum IdentType {
     Uri,
 }
 
-fn is_valid_path_char(c: char) -> bool {
-    match c {
-        'a'..='z' | 'A'..='Z' | '0'..='9' | '/' | '_' | '.' | '+' | '-' => true,
-        _ => false,
+// Classes of bytes, looked up in `CLASSES`
+const IDENT: u8 = 1 << 0; // can be part of an identifier after its start
+const PATH: u8 = 1 << 1; // can be part of a path
+const URI: u8 = 1 << 2; // can be part of a URI
+const PATH_RUN: u8 = 1 << 3; // can come before the `/` that makes a token a path
+const SPACE: u8 = 1 << 4; // ASCII whitespace
+const DIGIT: u8 = 1 << 5;
+
+static CLASSES: [u8; 256] = classes();
+
+const fn classes() -> [u8; 256] {
+    let mut classes = [0; 256];
+    let mut i = 0;
+    while i < 128 {
+        let c = i as u8;
+        let alphanumeric = c.is_ascii_alphanumeric();
+        let mut class = 0;
+        if alphanumeric || matches!(c, b'_' | b'-' | b'\'') {
+            class |= IDENT;
+        }
+        if alphanumeric || matches!(c, b'_' | b'.' | b'+' | b'-') {
+            class |= PATH_RUN | PATH | URI;
+        }
+        if c == b'/' {
+            class |= PATH | URI;
+        }
+        if matches!(
+            c,
+            b'%' | b'?' | b':' | b'@' | b'&' | b'=' | b'$' | b',' | b'!' | b'~' | b'*' | b'\''
+        ) {
+            class |= URI;
+        }
+        if matches!(c, b'\t' | b'\n' | 0x0B | 0x0C | b'\r' | b' ') {
+            class |= SPACE;
+        }
+        if c.is_ascii_digit() {
+            class |= DIGIT;
+        }
+        classes[i] = class;
+        i += 1;
     }
+    classes
+}
+/// Returns true if `byte` is in any of the classes
+fn is(byte: Option<u8>, class: u8) -> bool {
+    byte.is_some_and(|byte| CLASSES[byte as usize] & class != 0)
 }
 
-fn is_valid_uri_char(c: char) -> bool {
-    match c {
-        '%' | '?' | ':' | '@' | '&' | '=' | '$' | ',' | '!' | '~' | '*' | '\'' => true,
-        _ => is_valid_path_char(c),
-    }
+/// Returns the kind of `ident` if it's a keyword
+fn keyword(ident: &str) -> Option<SyntaxKind> {
+    Some(match ident {
+        "assert" => TOKEN_ASSERT,
+        "else" => TOKEN_ELSE,
+        "if" => TOKEN_IF,
+        "in" => TOKEN_IN,
+        "inherit" => TOKEN_INHERIT,
+        "let" => TOKEN_LET,
+        "rec" => TOKEN_REC,
+        "then" => TOKEN_THEN,
+        "with" => TOKEN_WITH,
+        _ => return None,
+    })
 }
 
-#[derive(Clone, Copy)]
+fn range(start: usize, end: usize) -> TextRange {
+    TextRange::from_to(TextUnit::from_usize(start), TextUnit::from_usize(end))
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 struct Interpol {
     brackets: u32,
     string: bool,
     multiline: bool,
+    path: bool,
+    // Where the string around this interpolation started
+    string_start: Option<usize>,
 }
-#[derive(Clone, Copy)]
+// `start` is where a string started, to report it if it's never closed, or
+// None if that isn't known, such as when resuming from a `TokenizerState`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 enum Todo {
-    StringBody { multiline: bool },
-    StringEnd,
+    StringBody { multiline: bool, start: Option<usize> },
+    StringEnd { multiline: bool, start: Option<usize> },
     InterpolStart,
+    Path,
+    // The rest of a `/*` comment that went past the end of the last slice
+    Comment,
 }
-#[derive(Clone, Copy, Default)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
 struct Context {
     interpol: Option<Interpol>,
     todo: Option<Todo>,
 }
+impl Context {
+    /// Forget where strings started, which depends on more than the state
+    fn without_positions(mut self) -> Self {
+        if let Some(interpol) = &mut self.interpol {
+            interpol.string_start = None;
+        }
+        match &mut self.todo {
+            Some(Todo::StringBody { start, .. }) | Some(Todo::StringEnd { start, .. }) => {
+                *start = None
+            }
+            _ => (),
+        }
+        self
+    }
+}
+
+/// Everything a `Tokenizer` needs to know about the text before it, such as
+/// whether it's inside a string or an interpolation. Take it with
+/// `Tokenizer::state` at the end of one slice of input and pass it to
+/// `Tokenizer::with_state` to carry on lexing another.
+///
+/// States don't hold any positions, so they can be compared to find out if
+/// an edit changes how the text after it is lexed.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct TokenizerState {
+    ctx: Vec<Context>,
+    after_value: bool,
+}
+impl Default for TokenizerState {
+    /// The state at the start of a file
+    fn default() -> Self {
+        Self { ctx: vec![Context::default()], after_value: false }
+    }
+}
 
 #[derive(Clone, Copy)]
 struct State<'a> {
@@ -60,21 +223,77 @@ impl Eq for State<'_> {}
 pub struct Tokenizer<'a> {
     ctx: Vec<Context>,
     state: State<'a>,
+    errors: Vec<(LexError, TextRange)>,
+    // Whether the text before the input ends with a value, see `follows_value`
+    after_value: bool,
+    more_input: bool,
+    // Where the last run of characters before a possible path `/` started
+    // and ended, for normal and store paths
+    path_runs: [Option<(usize, usize)>; 2],
 }
 impl<'a> Tokenizer<'a> {
     /// Create a new instance
     pub fn new(input: &'a str) -> Self {
-        Self { ctx: vec![Context::default()], state: State { input, offset: 0 } }
+        Self::with_state(input, TokenizerState::default())
+    }
+    /// Create an instance that carries on from where the tokenizer `state`
+    /// was taken from stopped. Lexing a file in slices like this gives the
+    /// same tokens as lexing it all at once, except that tokens can be split
+    /// where the slices meet. Ranges are relative to the start of `input`.
+    pub fn with_state(input: &'a str, state: TokenizerState) -> Self {
+        Self {
+            ctx: state.ctx,
+            state: State { input, offset: 0 },
+            errors: Vec::new(),
+            after_value: state.after_value,
+            more_input: false,
+            path_runs: [None; 2],
+        }
+    }
+    /// Create an instance that starts out inside the body of a string, used
+    /// to relex string content on its own
+    pub(crate) fn new_string_body(input: &'a str, multiline: bool) -> Self {
+        let todo = Todo::StringBody { multiline, start: None };
+        let ctx = vec![Context { interpol: None, todo: Some(todo) }];
+        Self::with_state(input, TokenizerState { ctx, after_value: false }).more_input(true)
+    }
+    /// Set whether more input follows this slice, such as when lexing one
+    /// line at a time. If so, a string or comment that goes past the end of
+    /// the slice isn't an error, and the state carries on into the next one.
+    /// Slices should end at the end of a line.
+    pub fn more_input(mut self, more_input: bool) -> Self {
+        self.more_input = more_input;
+        self
+    }
+    /// Return the state to resume lexing from with `with_state`. This should
+    /// be taken between tokens, such as after the iterator has ended.
+    pub fn state(&self) -> TokenizerState {
+        TokenizerState {
+            ctx: self.ctx.iter().map(|ctx| ctx.without_positions()).collect(),
+            after_value: self.follows_value(self.state),
+        }
+    }
+    /// Return why each `TOKEN_ERROR` so far was emitted, along with its range
+    pub fn errors(&self) -> &[(LexError, TextRange)] {
+        &self.errors
     }
 
-    fn remaining(&self) -> &str {
+    fn remaining(&self) -> &'a str {
         &self.state.input[self.state.offset..]
     }
-    fn peek(&self) -> Option<char> {
-        self.remaining().chars().next()
+    fn peek(&self) -> Option<u8> {
+        self.state.input.as_bytes().get(self.state.offset).copied()
+    }
+    fn next(&mut self) -> Option<u8> {
+        let byte = self.peek();
+        if byte.is_some() {
+            self.state.offset += 1;
+        }
+        byte
     }
-    fn next(&mut self) -> Option<char> {
-        let c = self.peek();
+    /// Consume a whole character, which may be more than one byte
+    fn next_char(&mut self) -> Option<char> {
+        let c = self.remaining().chars().next();
         if let Some(c) = c {
             self.state.offset += c.len_utf8();
         }
@@ -87,39 +306,194 @@ impl<'a> Tokenizer<'a> {
         }
         starts_with
     }
-    fn string_since(&self, past: State) -> SmolStr {
-        SmolStr::new(&past.input[past.offset..self.state.offset])
+    fn string_since(&self, past: State) -> &'a str {
+        &self.state.input[past.offset..self.state.offset]
+    }
+    fn range_since(&self, past: State) -> TextRange {
+        range(past.offset, self.state.offset)
+    }
+    /// Record an error and return the error token for everything since `past`
+    fn error(&mut self, past: State, err: LexError) -> (SyntaxKind, &'a str) {
+        self.errors.push((err, self.range_since(past)));
+        (TOKEN_ERROR, self.string_since(past))
+    }
+    fn number(&mut self, past: State, kind: SyntaxKind) -> (SyntaxKind, &'a str) {
+        match self.next_exponent(kind) {
+            TOKEN_ERROR => self.error(past, LexError::MissingExponent),
+            kind => (kind, self.string_since(past)),
+        }
     }
 
-    fn consume<F>(&mut self, mut f: F) -> usize
-    where
-        F: FnMut(char) -> bool,
-    {
-        let mut len = 0;
-        while self.peek().map(|c| f(c)).unwrap_or(false) {
-            self.next().unwrap();
-            len += 1;
+    /// Consume bytes in any of the classes and return how many there were
+    fn consume(&mut self, class: u8) -> usize {
+        let start = self.state.offset;
+        while is(self.peek(), class) {
+            self.state.offset += 1;
+        }
+        self.state.offset - start
+    }
+    /// Consume everything up to the next `byte`, or the end of input
+    fn consume_until(&mut self, byte: u8) {
+        let rest = &self.remaining().as_bytes();
+        self.state.offset += rest.iter().position(|&b| b == byte).unwrap_or(rest.len());
+    }
+    /// Consume whitespace, including non-ASCII whitespace
+    fn consume_whitespace(&mut self) -> usize {
+        let start = self.state.offset;
+        while let Some(byte) = self.peek() {
+            if byte.is_ascii() {
+                if !is(Some(byte), SPACE) {
+                    break;
+                }
+                self.state.offset += 1;
+            } else {
+                match self.remaining().chars().next() {
+                    Some(c) if c.is_whitespace() => self.state.offset += c.len_utf8(),
+                    _ => break,
+                }
+            }
+        }
+        self.state.offset - start
+    }
+    /// Consume the exponent of a number, if there is one, and return the kind
+    /// of the whole number
+    fn next_exponent(&mut self, kind: SyntaxKind) -> SyntaxKind {
+        let bytes = self.remaining().as_bytes();
+        if !matches!(bytes.first(), Some(b'e') | Some(b'E')) {
+            return kind;
+        }
+        let sign = matches!(bytes.get(1), Some(b'+') | Some(b'-'));
+        let digits = is(bytes.get(1 + sign as usize).copied(), DIGIT);
+        if !digits && kind == TOKEN_INTEGER {
+            // Something like `1 else`, written without the space
+            return kind;
+        }
+        self.state.offset += 1 + sign as usize;
+        if self.consume(DIGIT) == 0 {
+            return TOKEN_ERROR;
+        }
+        TOKEN_FLOAT
+    }
+    /// Returns true if the text before `state` ends with something a `.` can
+    /// select from, such as an identifier or a closing bracket, but not a
+    /// keyword
+    fn follows_value(&self, state: State) -> bool {
+        let before = &state.input.as_bytes()[..state.offset];
+        match before.last() {
+            Some(&byte) if byte.is_ascii_alphanumeric() || matches!(byte, b'_' | b'\'') => {
+                // The identifier before, if any, starts at the first letter
+                // or `_` of the run of identifier characters
+                let run = before.iter().rposition(|&b| !is(Some(b), IDENT)).map_or(0, |i| i + 1);
+                let start = before[run..]
+                    .iter()
+                    .position(|&b| b.is_ascii_alphabetic() || b == b'_')
+                    .map_or(state.offset, |i| run + i);
+                keyword(&state.input[start..state.offset]).is_none()
+            }
+            Some(byte) => matches!(byte, b'"' | b')' | b']' | b'}'),
+            None => self.after_value,
+        }
+    }
+    /// Consume the rest of a `/*` comment. `open` is where it started, if
+    /// that is known.
+    fn block_comment(&mut self, start: State, open: Option<usize>) -> (SyntaxKind, &'a str) {
+        loop {
+            self.consume_until(b'*');
+            self.next(); // consume the '*', if any
+            match self.peek() {
+                None if self.more_input => {
+                    self.ctx.last_mut().unwrap().todo = Some(Todo::Comment);
+                    return (TOKEN_COMMENT, self.string_since(start));
+                }
+                None => {
+                    // A comment from before this slice is reported at its start
+                    let open = open.map_or(range(0, 0), |open| range(open, open + 2));
+                    return self.error(start, LexError::UnterminatedComment { start: open });
+                }
+                Some(b'/') => {
+                    self.next().unwrap();
+                    return (TOKEN_COMMENT, self.string_since(start));
+                }
+                _ => (),
+            }
+        }
+    }
+    /// Look past the characters before a possible `/` to tell if the token
+    /// starting here is a path, a store path or a URI. Runs like `a.b.c` are
+    /// lexed one token at a time, so the end of the run is kept to only scan
+    /// it once.
+    fn ident_type(&mut self, store_path: bool) -> Option<IdentType> {
+        let offset = self.state.offset;
+        let bytes = self.state.input.as_bytes();
+        let end = match self.path_runs[store_path as usize] {
+            Some((start, end)) if start <= offset && offset <= end => end,
+            _ => {
+                let len = bytes[offset..]
+                    .iter()
+                    .position(|&b| {
+                        !is(Some(b), PATH_RUN) && !(store_path && (b == b'<' || b == b'/'))
+                    })
+                    .unwrap_or(bytes.len() - offset);
+                self.path_runs[store_path as usize] = Some((offset, offset + len));
+                offset + len
+            }
+        };
+        match bytes.get(end) {
+            // a//b parses as Update(a, b)
+            Some(b'/') => match self.state.input[end + 1..].chars().next() {
+                Some('/') | Some('*') | None => None,
+                Some(c) if c.is_whitespace() => None,
+                Some(_) => Some(IdentType::Path),
+            },
+            Some(b'>') => Some(IdentType::Store),
+            Some(b':') if is(bytes.get(end + 1).copied(), URI) => Some(IdentType::Uri),
+            _ => None,
         }
-        len
     }
-    fn next_string(&mut self, multiline: bool) -> SyntaxKind {
+    fn path_fragment(&mut self, start: State) -> (SyntaxKind, &'a str) {
+        let path = self.string_since(start);
+        if self.remaining().starts_with("${") {
+            // The path continues with an interpolation
+            self.ctx.last_mut().unwrap().todo = Some(Todo::Path);
+        } else if path.ends_with('/') {
+            return self.error(start, LexError::PathEndsWithSlash);
+        }
+        (TOKEN_PATH, path)
+    }
+    /// Report a string that is never closed. If it started before this slice
+    /// of input, the start of the slice is given as where it started.
+    fn unterminated_string(
+        &mut self,
+        past: State,
+        string_start: Option<usize>,
+        multiline: bool,
+    ) -> (SyntaxKind, &'a str) {
+        let quote = if multiline { 2 } else { 1 };
+        let start = string_start.map_or(range(0, 0), |start| range(start, start + quote));
+        self.error(past, LexError::UnterminatedString { multiline, start })
+    }
+    fn next_string(&mut self, multiline: bool, string_start: Option<usize>) -> SyntaxKind {
         loop {
             let start = self.state;
             match self.next() {
+                // The string carries on in the next slice
+                None if self.more_input => return TOKEN_STRING_CONTENT,
                 None => return TOKEN_ERROR,
-                Some('"') if !multiline => {
+                Some(b'"') if !multiline => {
                     self.state = start;
                     return TOKEN_STRING_CONTENT;
                 }
-                Some('\\') if !multiline => match self.next() {
-                    None => return TOKEN_ERROR,
-                    Some(_) => (),
-                },
+                Some(b'\\') if !multiline => {
+                    if self.next().is_none() {
+                        return TOKEN_ERROR;
+                    }
+                }
 
-                Some('\'') if multiline => match self.next() {
+                Some(b'\'') if multiline => match self.next() {
+                    None if self.more_input => return TOKEN_STRING_CONTENT,
                     None => return TOKEN_ERROR,
-                    Some('\'') => match self.peek() {
-                        Some('\'') | Some('\\') | Some('$') => {
+                    Some(b'\'') => match self.peek() {
+                        Some(b'\'') | Some(b'\\') | Some(b'$') => {
                             self.next().unwrap();
                         }
                         _ => {
@@ -130,16 +504,21 @@ impl<'a> Tokenizer<'a> {
                     Some(_) => (),
                 },
 
-                Some('$') => match self.peek() {
-                    Some('$') => {
+                Some(b'$') => match self.peek() {
+                    Some(b'$') => {
                         self.next().unwrap();
                     }
-                    Some('{') => {
+                    Some(b'{') => {
                         self.state = start;
                         self.ctx.push(Context {
-                            interpol: Some(Interpol { brackets: 0, string: true, multiline }),
+                            interpol: Some(Interpol {
+                                brackets: 0,
+                                string: true,
+                                multiline,
+                                path: false,
+                                string_start,
+                            }),
                             todo: Some(Todo::InterpolStart),
-                            ..Default::default()
                         });
                         return TOKEN_STRING_CONTENT;
                     }
@@ -154,45 +533,127 @@ impl<'a> Iterator for Tokenizer<'a> {
     type Item = (SyntaxKind, SmolStr);
 
     fn next(&mut self) -> Option<Self::Item> {
+        self.next_token().map(|(kind, text)| (kind, SmolStr::new(text)))
+    }
+}
+
+/// An iterator over the tokens of a `Tokenizer`, with their text borrowed from
+/// the input instead of copied. See `Tokenizer::borrowed`.
+pub struct Borrowed<'t, 'a> {
+    tokenizer: &'t mut Tokenizer<'a>,
+}
+impl<'a> Iterator for Borrowed<'_, 'a> {
+    type Item = (SyntaxKind, &'a str);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.tokenizer.next_token()
+    }
+}
+
+/// An iterator over the tokens of a `Tokenizer` and where they are in the
+/// input. See `Tokenizer::ranged`.
+pub struct Ranged<'t, 'a> {
+    tokenizer: &'t mut Tokenizer<'a>,
+}
+impl Iterator for Ranged<'_, '_> {
+    type Item = (SyntaxKind, TextRange);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let start = self.tokenizer.state.offset;
+        let (kind, text) = self.tokenizer.next_token()?;
+        Some((kind, range(start, start + text.len())))
+    }
+}
+
+impl<'a> Tokenizer<'a> {
+    /// Return an iterator over the remaining tokens that borrows their text
+    /// from the input, rather than copying it into a `SmolStr`
+    pub fn borrowed(&mut self) -> Borrowed<'_, 'a> {
+        Borrowed { tokenizer: self }
+    }
+    /// Return an iterator over the remaining tokens that yields their ranges
+    /// in the input instead of their text, for things like syntax
+    /// highlighting
+    pub fn ranged(&mut self) -> Ranged<'_, 'a> {
+        Ranged { tokenizer: self }
+    }
+
+    fn next_token(&mut self) -> Option<(SyntaxKind, &'a str)> {
         let start = self.state;
 
         // Handle already started multi-token
         loop {
-            let todo = &mut self.ctx.last_mut().unwrap().todo;
-            match todo.take() {
-                Some(Todo::InterpolStart) => {
-                    if self.starts_with_bump("${") {
-                        return Some((TOKEN_INTERPOL_START, self.string_since(start)));
-                    }
+            let todo = self.ctx.last_mut().unwrap().todo.take();
+            match todo {
+                Some(Todo::InterpolStart) if self.starts_with_bump("${") => {
+                    return Some((TOKEN_INTERPOL_START, self.string_since(start)));
                 }
-                Some(Todo::StringBody { multiline }) => {
-                    *todo = Some(Todo::StringEnd);
-                    let token = self.next_string(multiline);
+                Some(Todo::StringBody { multiline, start: string_start }) => {
+                    if self.more_input && self.peek().is_none() {
+                        // The string carries on in the next slice
+                        self.ctx.last_mut().unwrap().todo = todo;
+                        return None;
+                    }
+                    self.ctx.last_mut().unwrap().todo =
+                        Some(Todo::StringEnd { multiline, start: string_start });
+                    let token = self.next_string(multiline, string_start);
+                    if self.more_input && self.peek().is_none() && token == TOKEN_STRING_CONTENT {
+                        self.ctx.last_mut().unwrap().todo = todo;
+                    }
                     if self.state == start {
                         continue;
                     }
+                    if token == TOKEN_ERROR {
+                        // The string ends here, and has been reported
+                        self.ctx.last_mut().unwrap().todo = None;
+                        return Some(self.unterminated_string(start, string_start, multiline));
+                    }
                     return Some((token, self.string_since(start)));
                 }
-                Some(Todo::StringEnd) => {
+                Some(Todo::Path) => {
+                    if self.starts_with_bump("${") {
+                        self.ctx.push(Context {
+                            interpol: Some(Interpol {
+                                brackets: 0,
+                                string: false,
+                                multiline: false,
+                                path: true,
+                                string_start: None,
+                            }),
+                            todo: None,
+                        });
+                        return Some((TOKEN_INTERPOL_START, self.string_since(start)));
+                    }
+                    if self.consume(PATH) > 0 {
+                        return Some(self.path_fragment(start));
+                    }
+                }
+                Some(Todo::Comment) => {
+                    if self.more_input && self.peek().is_none() {
+                        self.ctx.last_mut().unwrap().todo = todo;
+                        return None;
+                    }
+                    return Some(self.block_comment(start, None));
+                }
+                Some(Todo::StringEnd { multiline, start: string_start }) => {
                     let status = match self.peek() {
-                        Some('"') => {
+                        Some(b'"') => {
                             self.next().unwrap();
                             true
                         }
-                        Some('\'') => match {
+                        Some(b'\'') => {
                             self.next().unwrap();
-                            self.peek()
-                        } {
-                            Some('\'') => {
+                            if self.peek() == Some(b'\'') {
                                 self.next().unwrap();
                                 true
+                            } else {
+                                false
                             }
-                            _ => false,
-                        },
+                        }
                         _ => false,
                     };
                     if !status {
-                        return Some((TOKEN_ERROR, self.string_since(start)));
+                        return Some(self.unterminated_string(start, string_start, multiline));
                     }
 
                     return Some((TOKEN_STRING_END, self.string_since(start)));
@@ -202,77 +663,51 @@ impl<'a> Iterator for Tokenizer<'a> {
             break;
         }
 
-        if self.consume(char::is_whitespace) > 0 {
+        if self.consume_whitespace() > 0 {
             return Some((TOKEN_WHITESPACE, self.string_since(start)));
         }
 
-        if self.peek() == Some('#') {
-            self.consume(|c| c != '\n');
+        if self.peek() == Some(b'#') {
+            self.consume_until(b'\n');
             return Some((TOKEN_COMMENT, self.string_since(start)));
         }
         if self.starts_with_bump("/*") {
-            loop {
-                self.consume(|c| c != '*');
-                self.next(); // consume the '*', if any
-                match self.peek() {
-                    None => return Some((TOKEN_ERROR, self.string_since(start))),
-                    Some('/') => {
-                        self.next().unwrap();
-                        return Some((TOKEN_COMMENT, self.string_since(start)));
-                    }
-                    _ => (),
-                }
-            }
+            return Some(self.block_comment(start, Some(start.offset)));
         }
 
         if self.starts_with_bump("...") {
             return Some((TOKEN_ELLIPSIS, self.string_since(start)));
         }
 
-        // Check if it's a path
-        let store_path = self.peek() == Some('<');
-        let kind = {
-            let mut lookahead = self.remaining().chars().skip_while(|c| match c {
-                'a'..='z' | 'A'..='Z' | '0'..='9' | '_' | '.' | '+' | '-' => true,
-                '<' | '/' => store_path,
-                _ => false,
-            });
-            match (lookahead.next(), lookahead.next()) {
-                // a//b parses as Update(a, b)
-                (Some('/'), Some('/')) => None,
-                (Some('/'), Some('*')) => None,
-                (Some('/'), Some(c)) if !c.is_whitespace() => Some(IdentType::Path),
-                (Some('>'), _) => Some(IdentType::Store),
-                (Some(':'), Some(c)) if is_valid_uri_char(c) => Some(IdentType::Uri),
-                _ => None,
-            }
+        // Check if it's a path. Only these can start one.
+        let first = self.peek()?;
+        let kind = if is(Some(first), PATH) || first == b'<' {
+            self.ident_type(first == b'<')
+        } else {
+            None
         };
 
         let c = self.next()?;
 
-        if c == '~' || kind == Some(IdentType::Path) {
-            if c == '~' && self.next() != Some('/') {
-                return Some((TOKEN_ERROR, self.string_since(start)));
+        if c == b'~' || kind == Some(IdentType::Path) {
+            if c == b'~' && self.next_char() != Some('/') {
+                return Some(self.error(start, LexError::HomeWithoutSlash));
             }
-            self.consume(is_valid_path_char);
-            let ident = self.string_since(start);
-            if ident.ends_with('/') {
-                return Some((TOKEN_ERROR, ident));
-            }
-            return Some((TOKEN_PATH, ident));
+            self.consume(PATH);
+            return Some(self.path_fragment(start));
         }
 
         match c {
-            '=' if self.peek() == Some('=') => {
+            b'=' if self.peek() == Some(b'=') => {
                 self.next().unwrap();
                 Some((TOKEN_EQUAL, self.string_since(start)))
             }
-            '!' if self.peek() == Some('=') => {
+            b'!' if self.peek() == Some(b'=') => {
                 self.next().unwrap();
                 Some((TOKEN_NOT_EQUAL, self.string_since(start)))
             }
-            '!' => Some((TOKEN_INVERT, self.string_since(start))),
-            '{' => {
+            b'!' => Some((TOKEN_INVERT, self.string_since(start))),
+            b'{' => {
                 if let Some(Interpol { ref mut brackets, .. }) =
                     self.ctx.last_mut().unwrap().interpol
                 {
@@ -280,8 +715,8 @@ impl<'a> Iterator for Tokenizer<'a> {
                 }
                 Some((TOKEN_CURLY_B_OPEN, self.string_since(start)))
             }
-            '}' => {
-                if let Some(Interpol { ref mut brackets, string, multiline }) =
+            b'}' => {
+                if let Some(Interpol { ref mut brackets, string, multiline, path, string_start }) =
                     self.ctx.last_mut().unwrap().interpol
                 {
                     match brackets.checked_sub(1) {
@@ -291,7 +726,10 @@ impl<'a> Iterator for Tokenizer<'a> {
 
                             if string {
                                 self.ctx.last_mut().unwrap().todo =
-                                    Some(Todo::StringBody { multiline });
+                                    Some(Todo::StringBody { multiline, start: string_start });
+                                return Some((TOKEN_INTERPOL_END, self.string_since(start)));
+                            } else if path {
+                                self.ctx.last_mut().unwrap().todo = Some(Todo::Path);
                                 return Some((TOKEN_INTERPOL_END, self.string_since(start)));
                             } else {
                                 return Some((TOKEN_DYNAMIC_END, self.string_since(start)));
@@ -301,67 +739,89 @@ impl<'a> Iterator for Tokenizer<'a> {
                 }
                 Some((TOKEN_CURLY_B_CLOSE, self.string_since(start)))
             }
-            '[' => Some((TOKEN_SQUARE_B_OPEN, self.string_since(start))),
-            ']' => Some((TOKEN_SQUARE_B_CLOSE, self.string_since(start))),
-            '@' => Some((TOKEN_AT, self.string_since(start))),
-            ':' => Some((TOKEN_COLON, self.string_since(start))),
-            ',' => Some((TOKEN_COMMA, self.string_since(start))),
-            '.' => Some((TOKEN_DOT, self.string_since(start))),
-            '=' => Some((TOKEN_ASSIGN, self.string_since(start))),
-            '?' => Some((TOKEN_QUESTION, self.string_since(start))),
-            ';' => Some((TOKEN_SEMICOLON, self.string_since(start))),
-            '(' => Some((TOKEN_PAREN_OPEN, self.string_since(start))),
-            ')' => Some((TOKEN_PAREN_CLOSE, self.string_since(start))),
-            '+' if self.peek() == Some('+') => {
+            b'[' => Some((TOKEN_SQUARE_B_OPEN, self.string_since(start))),
+            b']' => Some((TOKEN_SQUARE_B_CLOSE, self.string_since(start))),
+            b'@' => Some((TOKEN_AT, self.string_since(start))),
+            b':' => Some((TOKEN_COLON, self.string_since(start))),
+            b',' => Some((TOKEN_COMMA, self.string_since(start))),
+            // `.5` is a float, but `a.5` selects from `a`
+            b'.' if is(self.peek(), DIGIT) && !self.follows_value(start) => {
+                self.consume(DIGIT);
+                Some(self.number(start, TOKEN_FLOAT))
+            }
+            b'.' => Some((TOKEN_DOT, self.string_since(start))),
+            b'=' => Some((TOKEN_ASSIGN, self.string_since(start))),
+            b'?' => Some((TOKEN_QUESTION, self.string_since(start))),
+            b';' => Some((TOKEN_SEMICOLON, self.string_since(start))),
+            b'(' => Some((TOKEN_PAREN_OPEN, self.string_since(start))),
+            b')' => Some((TOKEN_PAREN_CLOSE, self.string_since(start))),
+            b'+' if self.peek() == Some(b'+') => {
                 self.next().unwrap();
                 Some((TOKEN_CONCAT, self.string_since(start)))
             }
-            '-' if self.peek() == Some('>') => {
+            b'-' if self.peek() == Some(b'>') => {
                 self.next().unwrap();
                 Some((TOKEN_IMPLICATION, self.string_since(start)))
             }
-            '/' if self.peek() == Some('/') => {
+            b'/' if self.peek() == Some(b'/') => {
                 self.next().unwrap();
                 Some((TOKEN_UPDATE, self.string_since(start)))
             }
-            '+' => Some((TOKEN_ADD, self.string_since(start))),
-            '-' => Some((TOKEN_SUB, self.string_since(start))),
-            '*' => Some((TOKEN_MUL, self.string_since(start))),
-            '/' => Some((TOKEN_DIV, self.string_since(start))),
-            '<' if kind == Some(IdentType::Store) => {
-                self.consume(is_valid_path_char);
-                if self.next() != Some('>') {
-                    return Some((TOKEN_ERROR, self.string_since(start)));
+            b'+' => Some((TOKEN_ADD, self.string_since(start))),
+            b'-' => Some((TOKEN_SUB, self.string_since(start))),
+            b'*' => Some((TOKEN_MUL, self.string_since(start))),
+            b'/' => Some((TOKEN_DIV, self.string_since(start))),
+            b'<' if kind == Some(IdentType::Store) => {
+                self.consume(PATH);
+                if self.next_char() != Some('>') {
+                    let err = LexError::UnclosedStorePath {
+                        start: range(start.offset, start.offset + 1),
+                    };
+                    return Some(self.error(start, err));
                 }
                 Some((TOKEN_PATH, self.string_since(start)))
             }
-            '&' if self.peek() == Some('&') => {
+            b'&' if self.peek() == Some(b'&') => {
                 self.next().unwrap();
                 Some((TOKEN_AND, self.string_since(start)))
             }
-            '|' if self.peek() == Some('|') => {
+            b'|' if self.peek() == Some(b'>') => {
+                self.next().unwrap();
+                Some((TOKEN_PIPE_RIGHT, self.string_since(start)))
+            }
+            b'<' if self.peek() == Some(b'|') => {
+                self.next().unwrap();
+                Some((TOKEN_PIPE_LEFT, self.string_since(start)))
+            }
+            b'|' if self.peek() == Some(b'|') => {
                 self.next().unwrap();
                 Some((TOKEN_OR, self.string_since(start)))
             }
-            '<' if self.peek() == Some('=') => {
+            b'<' if self.peek() == Some(b'=') => {
                 self.next().unwrap();
                 Some((TOKEN_LESS_OR_EQ, self.string_since(start)))
             }
-            '<' => Some((TOKEN_LESS, self.string_since(start))),
-            '>' if self.peek() == Some('=') => {
+            b'<' => Some((TOKEN_LESS, self.string_since(start))),
+            b'>' if self.peek() == Some(b'=') => {
                 self.next().unwrap();
                 Some((TOKEN_MORE_OR_EQ, self.string_since(start)))
             }
-            '>' => Some((TOKEN_MORE, self.string_since(start))),
-            '$' if self.peek() == Some('{') => {
+            b'>' => Some((TOKEN_MORE, self.string_since(start))),
+            b'$' if self.peek() == Some(b'{') => {
                 self.next().unwrap();
                 self.ctx.push(Context {
-                    interpol: Some(Interpol { brackets: 0, string: false, multiline: false }),
+                    interpol: Some(Interpol {
+                        brackets: 0,
+                        string: false,
+                        multiline: false,
+                        path: false,
+                        string_start: None,
+                    }),
                     ..Default::default()
                 });
                 Some((TOKEN_DYNAMIC_START, self.string_since(start)))
             }
-            'a'..='z' | 'A'..='Z' | '_' => {
+            b'a'..=b'z' | b'A'..=b'Z' | b'_' => {
                 let kind = match kind {
                     // It's detected as store if it ends with >, but if it
                     // didn't start with <, that's wrong
@@ -369,71 +829,58 @@ impl<'a> Iterator for Tokenizer<'a> {
                     Some(kind) => kind,
                 };
                 assert_ne!(kind, IdentType::Path, "paths are checked earlier");
-                self.consume(|c| match c {
-                    'a'..='z' | 'A'..='Z' | '0'..='9' | '_' | '-' | '\'' => true,
-                    c => kind == IdentType::Uri && is_valid_uri_char(c),
-                });
+                self.consume(if kind == IdentType::Uri { IDENT | URI } else { IDENT });
                 let ident = self.string_since(start);
                 let syntax_kind = match kind {
-                    IdentType::Ident => match &*ident {
-                        "assert" => TOKEN_ASSERT,
-                        "else" => TOKEN_ELSE,
-                        "if" => TOKEN_IF,
-                        "in" => TOKEN_IN,
-                        "inherit" => TOKEN_INHERIT,
-                        "let" => TOKEN_LET,
-                        "rec" => TOKEN_REC,
-                        "then" => TOKEN_THEN,
-                        "with" => TOKEN_WITH,
-                        _ => TOKEN_IDENT,
-                    },
+                    IdentType::Ident => keyword(ident).unwrap_or(TOKEN_IDENT),
                     IdentType::Path | IdentType::Store => TOKEN_PATH,
                     IdentType::Uri => TOKEN_URI,
                 };
                 Some((syntax_kind, ident))
             }
-            '"' => {
-                self.ctx.last_mut().unwrap().todo = Some(Todo::StringBody { multiline: false });
+            b'"' => {
+                self.ctx.last_mut().unwrap().todo =
+                    Some(Todo::StringBody { multiline: false, start: Some(start.offset) });
                 Some((TOKEN_STRING_START, self.string_since(start)))
             }
-            '\'' if self.peek() == Some('\'') => {
+            b'\'' if self.peek() == Some(b'\'') => {
                 self.next().unwrap();
-                self.ctx.last_mut().unwrap().todo = Some(Todo::StringBody { multiline: true });
+                self.ctx.last_mut().unwrap().todo =
+                    Some(Todo::StringBody { multiline: true, start: Some(start.offset) });
                 Some((TOKEN_STRING_START, self.string_since(start)))
             }
-            '0'..='9' => {
-                self.consume(|c| c >= '0' && c <= '9');
-                if self.peek() == Some('.') {
+            b'0'..=b'9' => {
+                self.consume(DIGIT);
+                let kind = if self.peek() == Some(b'.') {
                     self.next().unwrap();
-                    if self.consume(|c| c >= '0' && c <= '9') == 0 {
-                        return Some((TOKEN_ERROR, self.string_since(start)));
-                    }
-                    if self.peek() == Some('e') || self.peek() == Some('E') {
-                        self.next().unwrap();
-                        if self.peek() == Some('-') {
-                            self.next().unwrap();
-                        }
-                        if self.consume(|c| c >= '0' && c <= '9') == 0 {
-                            return Some((TOKEN_ERROR, self.string_since(start)));
-                        }
-                    }
-                    Some((TOKEN_FLOAT, self.string_since(start)))
+                    self.consume(DIGIT);
+                    TOKEN_FLOAT
                 } else {
-                    Some((TOKEN_INTEGER, self.string_since(start)))
-                }
+                    TOKEN_INTEGER
+                };
+                Some(self.number(start, kind))
+            }
+            _ => {
+                // Don't stop halfway through a character
+                self.state = start;
+                self.next_char();
+                Some(self.error(start, LexError::UnexpectedChar))
             }
-            _ => Some((TOKEN_ERROR, self.string_since(start))),
         }
     }
 }
 
+#[cfg(test)]
+mod reference;
+
 #[cfg(test)]
 mod tests {
     use super::{
+        LexError,
         SyntaxKind::{self, *},
         Tokenizer,
     };
-    use rowan::SmolStr;
+    use rowan::{SmolStr, TextRange, TextUnit};
 
     fn tokenize(input: &str) -> Vec<(SyntaxKind, SmolStr)> {
         Tokenizer::new(input).collect()
@@ -504,6 +951,103 @@ mod tests {
         );
     }
     #[test]
+    fn float_forms() {
+        for &float in &["1.5", ".5", "1.", "0.5", "00.5", "1e10", "1E+3", "1.5e-3", ".5e2", "1.e5"]
+        {
+            assert_eq!(tokenize(float), tokens![(TOKEN_FLOAT, float)], "{}", float);
+        }
+        assert_eq!(tokenize("1.5e"), tokens![(TOKEN_ERROR, "1.5e")]);
+        assert_eq!(tokenize("1.5e+"), tokens![(TOKEN_ERROR, "1.5e+")]);
+        assert_eq!(
+            tokenize("[1 .5]"),
+            tokens![
+                (TOKEN_SQUARE_B_OPEN, "["),
+                (TOKEN_INTEGER, "1"),
+                (TOKEN_WHITESPACE, " "),
+                (TOKEN_FLOAT, ".5"),
+                (TOKEN_SQUARE_B_CLOSE, "]")
+            ]
+        );
+        assert_eq!(tokenize("1else"), tokens![(TOKEN_INTEGER, "1"), (TOKEN_ELSE, "else")]);
+        for &(keyword, kind) in &[("then", TOKEN_THEN), ("else", TOKEN_ELSE), ("in", TOKEN_IN)] {
+            let input = format!("{}.5", keyword);
+            assert_eq!(
+                tokenize(&input),
+                tokens![(kind, keyword), (TOKEN_FLOAT, ".5")],
+                "{}",
+                input
+            );
+        }
+    }
+    #[test]
+    fn float_select() {
+        assert_eq!(
+            tokenize("a.5"),
+            tokens![(TOKEN_IDENT, "a"), (TOKEN_DOT, "."), (TOKEN_INTEGER, "5")]
+        );
+        assert_eq!(
+            tokenize("a.b.5"),
+            tokens![
+                (TOKEN_IDENT, "a"),
+                (TOKEN_DOT, "."),
+                (TOKEN_IDENT, "b"),
+                (TOKEN_DOT, "."),
+                (TOKEN_INTEGER, "5")
+            ]
+        );
+        assert_eq!(
+            tokenize("(a).5"),
+            tokens![
+                (TOKEN_PAREN_OPEN, "("),
+                (TOKEN_IDENT, "a"),
+                (TOKEN_PAREN_CLOSE, ")"),
+                (TOKEN_DOT, "."),
+                (TOKEN_INTEGER, "5")
+            ]
+        );
+        // Keywords aren't values, but identifiers that start with one are
+        assert_eq!(
+            tokenize("if c then.5 else.5"),
+            tokens![
+                (TOKEN_IF, "if"),
+                (TOKEN_WHITESPACE, " "),
+                (TOKEN_IDENT, "c"),
+                (TOKEN_WHITESPACE, " "),
+                (TOKEN_THEN, "then"),
+                (TOKEN_FLOAT, ".5"),
+                (TOKEN_WHITESPACE, " "),
+                (TOKEN_ELSE, "else"),
+                (TOKEN_FLOAT, ".5")
+            ]
+        );
+        assert_eq!(
+            tokenize("in.5 inx.5"),
+            tokens![
+                (TOKEN_IN, "in"),
+                (TOKEN_FLOAT, ".5"),
+                (TOKEN_WHITESPACE, " "),
+                (TOKEN_IDENT, "inx"),
+                (TOKEN_DOT, "."),
+                (TOKEN_INTEGER, "5")
+            ]
+        );
+    }
+    #[test]
+    fn pipes() {
+        assert_eq!(
+            tokenize("a|>b<|c||d"),
+            tokens![
+                (TOKEN_IDENT, "a"),
+                (TOKEN_PIPE_RIGHT, "|>"),
+                (TOKEN_IDENT, "b"),
+                (TOKEN_PIPE_LEFT, "<|"),
+                (TOKEN_IDENT, "c"),
+                (TOKEN_OR, "||"),
+                (TOKEN_IDENT, "d")
+            ]
+        );
+    }
+    #[test]
     fn basic_string_set() {
         assert_eq!(
             tokenize(r#"{ string = "Hello \"World\""; }"#),
@@ -821,6 +1365,63 @@ mod tests {
         assert_eq!(tokenize("<hello/world>"), path("<hello/world>"));
     }
     #[test]
+    fn path_interpolation() {
+        assert_eq!(
+            tokenize("./pkgs/${name}/default.nix"),
+            tokens![
+                (TOKEN_PATH, "./pkgs/"),
+                (TOKEN_INTERPOL_START, "${"),
+                (TOKEN_IDENT, "name"),
+                (TOKEN_INTERPOL_END, "}"),
+                (TOKEN_PATH, "/default.nix")
+            ]
+        );
+        assert_eq!(
+            tokenize("~/${a}${b}.nix x"),
+            tokens![
+                (TOKEN_PATH, "~/"),
+                (TOKEN_INTERPOL_START, "${"),
+                (TOKEN_IDENT, "a"),
+                (TOKEN_INTERPOL_END, "}"),
+                (TOKEN_INTERPOL_START, "${"),
+                (TOKEN_IDENT, "b"),
+                (TOKEN_INTERPOL_END, "}"),
+                (TOKEN_PATH, ".nix"),
+                (TOKEN_WHITESPACE, " "),
+                (TOKEN_IDENT, "x")
+            ]
+        );
+        assert_eq!(
+            tokenize("./${a}/"),
+            tokens![
+                (TOKEN_PATH, "./"),
+                (TOKEN_INTERPOL_START, "${"),
+                (TOKEN_IDENT, "a"),
+                (TOKEN_INTERPOL_END, "}"),
+                (TOKEN_ERROR, "/")
+            ]
+        );
+        assert_eq!(
+            tokenize("./a${b}"),
+            tokens![
+                (TOKEN_PATH, "./a"),
+                (TOKEN_INTERPOL_START, "${"),
+                (TOKEN_IDENT, "b"),
+                (TOKEN_INTERPOL_END, "}")
+            ]
+        );
+        assert_eq!(
+            tokenize("./a/b${c}/d"),
+            tokens![
+                (TOKEN_PATH, "./a/b"),
+                (TOKEN_INTERPOL_START, "${"),
+                (TOKEN_IDENT, "c"),
+                (TOKEN_INTERPOL_END, "}"),
+                (TOKEN_PATH, "/d")
+            ]
+        );
+    }
+    #[test]
     fn uri() {
         assert_eq!(
             tokenize("https://google.com/?q=Hello+World"),
@@ -1097,4 +1698,242 @@ mod tests {
             ]
         );
     }
+    #[test]
+    fn errors() {
+        fn errors(input: &str) -> Vec<(LexError, TextRange)> {
+            let mut tokenizer = Tokenizer::new(input);
+            let tokens = tokenizer.by_ref().filter(|(kind, _)| *kind == TOKEN_ERROR).count();
+            assert_eq!(tokens, tokenizer.errors().len(), "{}", input);
+            tokenizer.errors().to_vec()
+        }
+        fn range(start: u32, end: u32) -> TextRange {
+            TextRange::from_to(start.into(), end.into())
+        }
+        assert_eq!(errors("[ 1 \"a\" ]"), Vec::new());
+        assert_eq!(
+            errors("x: \"abc"),
+            vec![(
+                LexError::UnterminatedString { multiline: false, start: range(3, 4) },
+                range(4, 7)
+            )]
+        );
+        // Reported once, after the last interpolation
+        assert_eq!(
+            errors("{ a = ''\n  ${b} c"),
+            vec![(
+                LexError::UnterminatedString { multiline: true, start: range(6, 8) },
+                range(15, 17)
+            )]
+        );
+        assert_eq!(
+            errors("\""),
+            vec![(
+                LexError::UnterminatedString { multiline: false, start: range(0, 1) },
+                range(1, 1)
+            )]
+        );
+        assert_eq!(
+            errors("1 /* a"),
+            vec![(LexError::UnterminatedComment { start: range(2, 4) }, range(2, 6))]
+        );
+        assert_eq!(errors("./a/"), vec![(LexError::PathEndsWithSlash, range(0, 4))]);
+        assert_eq!(errors("~a"), vec![(LexError::HomeWithoutSlash, range(0, 2))]);
+        assert_eq!(errors("1.5e+"), vec![(LexError::MissingExponent, range(0, 5))]);
+        assert_eq!(
+            errors("<a<b>"),
+            vec![(LexError::UnclosedStorePath { start: range(0, 1) }, range(0, 3))]
+        );
+        assert_eq!(errors("a ^ b"), vec![(LexError::UnexpectedChar, range(2, 3))]);
+    }
+    #[test]
+    fn non_ascii() {
+        assert_eq!(
+            tokenize("\"é\"\u{a0}a"),
+            tokens![
+                (TOKEN_STRING_START, "\""),
+                (TOKEN_STRING_CONTENT, "é"),
+                (TOKEN_STRING_END, "\""),
+                (TOKEN_WHITESPACE, "\u{a0}"),
+                (TOKEN_IDENT, "a")
+            ]
+        );
+        assert_eq!(tokenize("~é"), tokens![(TOKEN_ERROR, "~é")]);
+        assert_eq!(tokenize("aé"), tokens![(TOKEN_IDENT, "a"), (TOKEN_ERROR, "é")]);
+    }
+    #[test]
+    fn long_runs() {
+        // Each token used to look ahead to the end of the run
+        let input = "a.".repeat(100_000);
+        let tokens = tokenize(&input);
+        assert_eq!(tokens.len(), 200_000);
+        assert_eq!(tokenize(&(input.clone() + "a/b")), tokens![(TOKEN_PATH, input + "a/b")]);
+        assert_eq!(tokenize(&"-".repeat(100_000)).len(), 100_000);
+    }
+    #[test]
+    fn borrowed_and_ranged() {
+        let input = "let a = \"x ${b}\"; in ./a/${c} # d\n";
+        let owned = tokenize(input);
+        let borrowed: Vec<_> = Tokenizer::new(input).borrowed().collect();
+        let ranged: Vec<_> = Tokenizer::new(input).ranged().collect();
+        assert_eq!(owned.len(), borrowed.len());
+        assert_eq!(owned.len(), ranged.len());
+
+        let mut end = 0.into();
+        for ((owned, borrowed), ranged) in owned.iter().zip(&borrowed).zip(&ranged) {
+            assert_eq!((owned.0, owned.1.as_str()), *borrowed);
+            assert_eq!(owned.0, ranged.0);
+            assert_eq!(ranged.1.start(), end);
+            assert_eq!(&input[ranged.1], borrowed.1);
+            end = ranged.1.end();
+        }
+        assert_eq!(end, TextUnit::of_str(input));
+
+        // Switching iterators halfway keeps going where the last one stopped
+        let mut tokenizer = Tokenizer::new(input);
+        assert_eq!(tokenizer.borrowed().nth(2), Some((TOKEN_IDENT, "a")));
+        assert_eq!(tokenizer.ranged().next(), Some((TOKEN_WHITESPACE, super::range(5, 6))));
+        assert_eq!(Iterator::next(&mut tokenizer), Some((TOKEN_ASSIGN, "=".into())));
+    }
+    #[test]
+    fn resume() {
+        use super::TokenizerState;
+        use std::{fs, iter, path::Path};
+
+        fn check(input: &str) {
+            let tokens = tokenize(input);
+
+            // Resuming between any two tokens gives the same tokens
+            let mut offset = 0;
+            for i in 0..=tokens.len() {
+                let mut tokenizer = Tokenizer::new(input);
+                tokenizer.by_ref().take(i).for_each(drop);
+                let rest: Vec<_> =
+                    Tokenizer::with_state(&input[offset..], tokenizer.state()).collect();
+                assert_eq!(rest, &tokens[i..], "{} {}", input, offset);
+                offset += tokens.get(i).map_or(0, |(_, text)| text.len());
+            }
+
+            // Lexing a line at a time only splits tokens where lines meet
+            let kinds: Vec<_> = tokens
+                .iter()
+                .flat_map(|(kind, text)| iter::repeat(*kind).take(text.len()))
+                .collect();
+            let mut state = TokenizerState::default();
+            let mut by_line = Vec::new();
+            let mut lines = input.split_inclusive('\n').peekable();
+            while let Some(line) = lines.next() {
+                let mut tokenizer =
+                    Tokenizer::with_state(line, state).more_input(lines.peek().is_some());
+                for (kind, text) in tokenizer.borrowed() {
+                    by_line.extend(iter::repeat(kind).take(text.len()));
+                }
+                state = tokenizer.state();
+            }
+            assert_eq!(by_line, kinds, "{}", input);
+        }
+
+        check("{ a = ''\n  x ${\n  \"y\n  ${z}\"\n} ''; b = /* c\n * d\n*/ a.5; }");
+        check("x: x.5 ./a/${b}/c\n\n  # d\n.5\n\"e\\\nf\"");
+        for dir in &["test_data/general", "test_data/parser"] {
+            for entry in fs::read_dir(Path::new(env!("CARGO_MANIFEST_DIR")).join(dir)).unwrap() {
+                let path = entry.unwrap().path();
+                if path.extension().is_some_and(|ext| ext == "nix") {
+                    check(&fs::read_to_string(path).unwrap());
+                }
+            }
+        }
+
+        // The state inside a multiline string doesn't depend on what came before
+        let state = |input| {
+            let mut tokenizer = Tokenizer::new(input).more_input(true);
+            tokenizer.by_ref().for_each(drop);
+            tokenizer.state()
+        };
+        assert_eq!(state("a = ''\n"), state("{ b = 1; a = ''\n  x\n"));
+        assert_ne!(state("a = ''\n"), state("a = \"\n"));
+        assert_ne!(state("a = ''${\n"), state("a = ''\n"));
+        assert_eq!(state("a = 1;\n"), TokenizerState::default());
+
+        // Strings and comments from before the slice are reported at its start
+        let errors = |input, before| {
+            let mut tokenizer = Tokenizer::with_state(input, state(before));
+            let tokens: Vec<_> = tokenizer.by_ref().collect();
+            (tokens, tokenizer.errors().to_vec())
+        };
+        let start = super::range(0, 0);
+        assert_eq!(
+            errors("y", "a = \"x\n"),
+            (
+                tokens![(TOKEN_ERROR, "y")],
+                vec![(
+                    LexError::UnterminatedString { multiline: false, start },
+                    super::range(0, 1)
+                )]
+            )
+        );
+        assert_eq!(
+            errors("y", "a = /* x\n"),
+            (
+                tokens![(TOKEN_ERROR, "y")],
+                vec![(LexError::UnterminatedComment { start }, super::range(0, 1))]
+            )
+        );
+        assert_eq!(
+            errors("y}", "a = ''${\n"),
+            (
+                tokens![(TOKEN_IDENT, "y"), (TOKEN_INTERPOL_END, "}"), (TOKEN_ERROR, "")],
+                vec![(LexError::UnterminatedString { multiline: true, start }, super::range(2, 2))]
+            )
+        );
+    }
+    /// Random input made of pieces that mean something to the tokenizer
+    fn random_inputs(count: usize) -> impl Iterator<Item = String> {
+        const PIECES: &[&str] = &[
+            "a", "b1", "_", "'", "''", "\"", "\\", "$", "${", "{", "}", "/", "./", "~", "<", ">",
+            ".", "..", "...", "1", "2.", ".5", "e", "E", "+", "-", "*", "#", "/*", "*/", "\n", " ",
+            ":", "@", "?", ";", "=", "!", "&", "|", "[", "]", "(", ")", ",", "%", "https", "é",
+            "\t",
+        ];
+        // xorshift, so failures can be reproduced
+        let mut seed = 0x2545_f491_4f6c_dd1du64;
+        let mut random = move || {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            seed as usize
+        };
+        (0..count).map(move |_| {
+            let len = random() % 24;
+            (0..len).map(|_| PIECES[random() % PIECES.len()]).collect()
+        })
+    }
+    fn check_reference(input: &str) {
+        let mut tokenizer = Tokenizer::new(input);
+        let tokens: Vec<_> = tokenizer.borrowed().collect();
+        let mut reference = super::reference::Tokenizer::new(input);
+        let expected: Vec<_> = std::iter::from_fn(|| reference.next_token()).collect();
+        assert_eq!(tokens, expected, "{:?}", input);
+        assert_eq!(tokenizer.errors(), reference.errors(), "{:?}", input);
+    }
+    fn corpus(dir: &std::path::Path, f: &mut impl FnMut(&str)) {
+        for entry in std::fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            if path.is_dir() {
+                corpus(&path, f);
+            } else if path.extension().is_some_and(|ext| ext == "nix") {
+                f(&std::fs::read_to_string(path).unwrap());
+            }
+        }
+    }
+    #[test]
+    fn same_as_reference() {
+        let test_data = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("test_data");
+        corpus(&test_data, &mut check_reference);
+        random_inputs(5_000).for_each(|input| check_reference(&input));
+    }
+    #[test]
+    #[ignore = "slow, run with --ignored after changing the tokenizer"]
+    fn same_as_reference_exhaustive() {
+        random_inputs(300_000).for_each(|input| check_reference(&input));
+    }
 }
@@ -0,0 +1,532 @@
+//! The `char` based tokenizer that the byte based one replaced, kept only to
+//! check that both give the same tokens and errors. It lexes whole inputs,
+//! without the support for slices and resuming.
+
+use crate::{
+    SyntaxKind::{self, *},
+    TextRange, TextUnit,
+};
+
+use super::LexError;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum IdentType {
+    Ident,
+    Path,
+    Store,
+    Uri,
+}
+
+fn is_valid_path_char(c: char) -> bool {
+    matches!(c, 'a'..='z' | 'A'..='Z' | '0'..='9' | '/' | '_' | '.' | '+' | '-')
+}
+
+fn range(start: usize, end: usize) -> TextRange {
+    TextRange::from_to(TextUnit::from_usize(start), TextUnit::from_usize(end))
+}
+
+fn is_valid_uri_char(c: char) -> bool {
+    match c {
+        '%' | '?' | ':' | '@' | '&' | '=' | '$' | ',' | '!' | '~' | '*' | '\'' => true,
+        _ => is_valid_path_char(c),
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Interpol {
+    brackets: u32,
+    string: bool,
+    multiline: bool,
+    path: bool,
+    string_start: usize,
+}
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Todo {
+    StringBody { multiline: bool, start: usize },
+    StringEnd { multiline: bool, start: usize },
+    InterpolStart,
+    Path,
+}
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+struct Context {
+    interpol: Option<Interpol>,
+    todo: Option<Todo>,
+}
+
+pub struct Tokenizer<'a> {
+    ctx: Vec<Context>,
+    input: &'a str,
+    offset: usize,
+    errors: Vec<(LexError, TextRange)>,
+}
+impl<'a> Tokenizer<'a> {
+    pub fn new(input: &'a str) -> Self {
+        Self { ctx: vec![Context::default()], input, offset: 0, errors: Vec::new() }
+    }
+    pub fn errors(&self) -> &[(LexError, TextRange)] {
+        &self.errors
+    }
+
+    fn remaining(&self) -> &str {
+        &self.input[self.offset..]
+    }
+    fn peek(&self) -> Option<char> {
+        self.remaining().chars().next()
+    }
+    fn next(&mut self) -> Option<char> {
+        let c = self.peek();
+        if let Some(c) = c {
+            self.offset += c.len_utf8();
+        }
+        c
+    }
+    fn starts_with_bump(&mut self, s: &str) -> bool {
+        let starts_with = self.remaining().starts_with(s);
+        if starts_with {
+            self.offset += s.len();
+        }
+        starts_with
+    }
+    fn string_since(&self, past: usize) -> &'a str {
+        &self.input[past..self.offset]
+    }
+    fn error(&mut self, past: usize, err: LexError) -> (SyntaxKind, &'a str) {
+        self.errors.push((err, range(past, self.offset)));
+        (TOKEN_ERROR, self.string_since(past))
+    }
+    fn number(&mut self, past: usize, kind: SyntaxKind) -> (SyntaxKind, &'a str) {
+        match self.next_exponent(kind) {
+            TOKEN_ERROR => self.error(past, LexError::MissingExponent),
+            kind => (kind, self.string_since(past)),
+        }
+    }
+
+    fn consume<F>(&mut self, mut f: F) -> usize
+    where
+        F: FnMut(char) -> bool,
+    {
+        let mut len = 0;
+        while self.peek().map(&mut f).unwrap_or(false) {
+            self.next().unwrap();
+            len += 1;
+        }
+        len
+    }
+    fn next_exponent(&mut self, kind: SyntaxKind) -> SyntaxKind {
+        let mut lookahead = self.remaining().chars();
+        if !matches!(lookahead.next(), Some('e') | Some('E')) {
+            return kind;
+        }
+        let sign = matches!(lookahead.clone().next(), Some('+') | Some('-'));
+        if sign {
+            lookahead.next();
+        }
+        let digits = lookahead.next().is_some_and(|c| c.is_ascii_digit());
+        if !digits && kind == TOKEN_INTEGER {
+            return kind;
+        }
+        self.next().unwrap();
+        if sign {
+            self.next().unwrap();
+        }
+        if self.consume(|c| c.is_ascii_digit()) == 0 {
+            return TOKEN_ERROR;
+        }
+        TOKEN_FLOAT
+    }
+    fn follows_value(&self, offset: usize) -> bool {
+        let before = &self.input[..offset];
+        match before.chars().next_back() {
+            Some(c) if c.is_ascii_alphanumeric() || matches!(c, '_' | '\'') => {
+                let run = before.trim_end_matches(|c: char| {
+                    c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '\'')
+                });
+                let word = before[run.len()..]
+                    .trim_start_matches(|c: char| !c.is_ascii_alphabetic() && c != '_');
+                !matches!(
+                    word,
+                    "assert" | "else" | "if" | "in" | "inherit" | "let" | "rec" | "then" | "with"
+                )
+            }
+            Some(c) => matches!(c, '"' | ')' | ']' | '}'),
+            None => false,
+        }
+    }
+    fn block_comment(&mut self, start: usize) -> (SyntaxKind, &'a str) {
+        loop {
+            self.consume(|c| c != '*');
+            self.next();
+            match self.peek() {
+                None => {
+                    let err = LexError::UnterminatedComment { start: range(start, start + 2) };
+                    return self.error(start, err);
+                }
+                Some('/') => {
+                    self.next().unwrap();
+                    return (TOKEN_COMMENT, self.string_since(start));
+                }
+                _ => (),
+            }
+        }
+    }
+    fn path_fragment(&mut self, start: usize) -> (SyntaxKind, &'a str) {
+        let path = self.string_since(start);
+        if self.remaining().starts_with("${") {
+            self.ctx.last_mut().unwrap().todo = Some(Todo::Path);
+        } else if path.ends_with('/') {
+            return self.error(start, LexError::PathEndsWithSlash);
+        }
+        (TOKEN_PATH, path)
+    }
+    fn unterminated_string(
+        &mut self,
+        past: usize,
+        string_start: usize,
+        multiline: bool,
+    ) -> (SyntaxKind, &'a str) {
+        let quote = if multiline { 2 } else { 1 };
+        let start = range(string_start, string_start + quote);
+        self.error(past, LexError::UnterminatedString { multiline, start })
+    }
+    fn next_string(&mut self, multiline: bool, string_start: usize) -> SyntaxKind {
+        loop {
+            let start = self.offset;
+            match self.next() {
+                None => return TOKEN_ERROR,
+                Some('"') if !multiline => {
+                    self.offset = start;
+                    return TOKEN_STRING_CONTENT;
+                }
+                Some('\\') if !multiline => {
+                    if self.next().is_none() {
+                        return TOKEN_ERROR;
+                    }
+                }
+
+                Some('\'') if multiline => match self.next() {
+                    None => return TOKEN_ERROR,
+                    Some('\'') => match self.peek() {
+                        Some('\'') | Some('\\') | Some('$') => {
+                            self.next().unwrap();
+                        }
+                        _ => {
+                            self.offset = start;
+                            return TOKEN_STRING_CONTENT;
+                        }
+                    },
+                    Some(_) => (),
+                },
+
+                Some('$') => match self.peek() {
+                    Some('$') => {
+                        self.next().unwrap();
+                    }
+                    Some('{') => {
+                        self.offset = start;
+                        self.ctx.push(Context {
+                            interpol: Some(Interpol {
+                                brackets: 0,
+                                string: true,
+                                multiline,
+                                path: false,
+                                string_start,
+                            }),
+                            todo: Some(Todo::InterpolStart),
+                        });
+                        return TOKEN_STRING_CONTENT;
+                    }
+                    _ => (),
+                },
+                Some(_) => (),
+            }
+        }
+    }
+
+    pub fn next_token(&mut self) -> Option<(SyntaxKind, &'a str)> {
+        let start = self.offset;
+
+        loop {
+            let todo = self.ctx.last_mut().unwrap().todo.take();
+            match todo {
+                Some(Todo::InterpolStart) if self.starts_with_bump("${") => {
+                    return Some((TOKEN_INTERPOL_START, self.string_since(start)));
+                }
+                Some(Todo::StringBody { multiline, start: string_start }) => {
+                    self.ctx.last_mut().unwrap().todo =
+                        Some(Todo::StringEnd { multiline, start: string_start });
+                    let token = self.next_string(multiline, string_start);
+                    if self.offset == start {
+                        continue;
+                    }
+                    if token == TOKEN_ERROR {
+                        self.ctx.last_mut().unwrap().todo = None;
+                        return Some(self.unterminated_string(start, string_start, multiline));
+                    }
+                    return Some((token, self.string_since(start)));
+                }
+                Some(Todo::Path) => {
+                    if self.starts_with_bump("${") {
+                        self.ctx.push(Context {
+                            interpol: Some(Interpol {
+                                brackets: 0,
+                                string: false,
+                                multiline: false,
+                                path: true,
+                                string_start: 0,
+                            }),
+                            todo: None,
+                        });
+                        return Some((TOKEN_INTERPOL_START, self.string_since(start)));
+                    }
+                    if self.consume(is_valid_path_char) > 0 {
+                        return Some(self.path_fragment(start));
+                    }
+                }
+                Some(Todo::StringEnd { multiline, start: string_start }) => {
+                    let status = match self.peek() {
+                        Some('"') => {
+                            self.next().unwrap();
+                            true
+                        }
+                        Some('\'') => {
+                            self.next().unwrap();
+                            if self.peek() == Some('\'') {
+                                self.next().unwrap();
+                                true
+                            } else {
+                                false
+                            }
+                        }
+                        _ => false,
+                    };
+                    if !status {
+                        return Some(self.unterminated_string(start, string_start, multiline));
+                    }
+
+                    return Some((TOKEN_STRING_END, self.string_since(start)));
+                }
+                _ => (),
+            }
+            break;
+        }
+
+        if self.consume(char::is_whitespace) > 0 {
+            return Some((TOKEN_WHITESPACE, self.string_since(start)));
+        }
+
+        if self.peek() == Some('#') {
+            self.consume(|c| c != '\n');
+            return Some((TOKEN_COMMENT, self.string_since(start)));
+        }
+        if self.starts_with_bump("/*") {
+            return Some(self.block_comment(start));
+        }
+
+        if self.starts_with_bump("...") {
+            return Some((TOKEN_ELLIPSIS, self.string_since(start)));
+        }
+
+        let store_path = self.peek() == Some('<');
+        let kind = {
+            let mut lookahead = self.remaining().chars().skip_while(|c| match c {
+                'a'..='z' | 'A'..='Z' | '0'..='9' | '_' | '.' | '+' | '-' => true,
+                '<' | '/' => store_path,
+                _ => false,
+            });
+            match (lookahead.next(), lookahead.next()) {
+                (Some('/'), Some('/')) => None,
+                (Some('/'), Some('*')) => None,
+                (Some('/'), Some(c)) if !c.is_whitespace() => Some(IdentType::Path),
+                (Some('>'), _) => Some(IdentType::Store),
+                (Some(':'), Some(c)) if is_valid_uri_char(c) => Some(IdentType::Uri),
+                _ => None,
+            }
+        };
+
+        let c = self.next()?;
+
+        if c == '~' || kind == Some(IdentType::Path) {
+            if c == '~' && self.next() != Some('/') {
+                return Some(self.error(start, LexError::HomeWithoutSlash));
+            }
+            self.consume(is_valid_path_char);
+            return Some(self.path_fragment(start));
+        }
+
+        match c {
+            '=' if self.peek() == Some('=') => {
+                self.next().unwrap();
+                Some((TOKEN_EQUAL, self.string_since(start)))
+            }
+            '!' if self.peek() == Some('=') => {
+                self.next().unwrap();
+                Some((TOKEN_NOT_EQUAL, self.string_since(start)))
+            }
+            '!' => Some((TOKEN_INVERT, self.string_since(start))),
+            '{' => {
+                if let Some(Interpol { ref mut brackets, .. }) =
+                    self.ctx.last_mut().unwrap().interpol
+                {
+                    *brackets += 1;
+                }
+                Some((TOKEN_CURLY_B_OPEN, self.string_since(start)))
+            }
+            '}' => {
+                if let Some(Interpol { ref mut brackets, string, multiline, path, string_start }) =
+                    self.ctx.last_mut().unwrap().interpol
+                {
+                    match brackets.checked_sub(1) {
+                        Some(new) => *brackets = new,
+                        None => {
+                            self.ctx.pop().unwrap();
+
+                            if string {
+                                self.ctx.last_mut().unwrap().todo =
+                                    Some(Todo::StringBody { multiline, start: string_start });
+                                return Some((TOKEN_INTERPOL_END, self.string_since(start)));
+                            } else if path {
+                                self.ctx.last_mut().unwrap().todo = Some(Todo::Path);
+                                return Some((TOKEN_INTERPOL_END, self.string_since(start)));
+                            } else {
+                                return Some((TOKEN_DYNAMIC_END, self.string_since(start)));
+                            }
+                        }
+                    }
+                }
+                Some((TOKEN_CURLY_B_CLOSE, self.string_since(start)))
+            }
+            '[' => Some((TOKEN_SQUARE_B_OPEN, self.string_since(start))),
+            ']' => Some((TOKEN_SQUARE_B_CLOSE, self.string_since(start))),
+            '@' => Some((TOKEN_AT, self.string_since(start))),
+            ':' => Some((TOKEN_COLON, self.string_since(start))),
+            ',' => Some((TOKEN_COMMA, self.string_since(start))),
+            '.' if self.peek().is_some_and(|c| c.is_ascii_digit())
+                && !self.follows_value(start) =>
+            {
+                self.consume(|c| c.is_ascii_digit());
+                Some(self.number(start, TOKEN_FLOAT))
+            }
+            '.' => Some((TOKEN_DOT, self.string_since(start))),
+            '=' => Some((TOKEN_ASSIGN, self.string_since(start))),
+            '?' => Some((TOKEN_QUESTION, self.string_since(start))),
+            ';' => Some((TOKEN_SEMICOLON, self.string_since(start))),
+            '(' => Some((TOKEN_PAREN_OPEN, self.string_since(start))),
+            ')' => Some((TOKEN_PAREN_CLOSE, self.string_since(start))),
+            '+' if self.peek() == Some('+') => {
+                self.next().unwrap();
+                Some((TOKEN_CONCAT, self.string_since(start)))
+            }
+            '-' if self.peek() == Some('>') => {
+                self.next().unwrap();
+                Some((TOKEN_IMPLICATION, self.string_since(start)))
+            }
+            '/' if self.peek() == Some('/') => {
+                self.next().unwrap();
+                Some((TOKEN_UPDATE, self.string_since(start)))
+            }
+            '+' => Some((TOKEN_ADD, self.string_since(start))),
+            '-' => Some((TOKEN_SUB, self.string_since(start))),
+            '*' => Some((TOKEN_MUL, self.string_since(start))),
+            '/' => Some((TOKEN_DIV, self.string_since(start))),
+            '<' if kind == Some(IdentType::Store) => {
+                self.consume(is_valid_path_char);
+                if self.next() != Some('>') {
+                    let err = LexError::UnclosedStorePath { start: range(start, start + 1) };
+                    return Some(self.error(start, err));
+                }
+                Some((TOKEN_PATH, self.string_since(start)))
+            }
+            '&' if self.peek() == Some('&') => {
+                self.next().unwrap();
+                Some((TOKEN_AND, self.string_since(start)))
+            }
+            '|' if self.peek() == Some('>') => {
+                self.next().unwrap();
+                Some((TOKEN_PIPE_RIGHT, self.string_since(start)))
+            }
+            '<' if self.peek() == Some('|') => {
+                self.next().unwrap();
+                Some((TOKEN_PIPE_LEFT, self.string_since(start)))
+            }
+            '|' if self.peek() == Some('|') => {
+                self.next().unwrap();
+                Some((TOKEN_OR, self.string_since(start)))
+            }
+            '<' if self.peek() == Some('=') => {
+                self.next().unwrap();
+                Some((TOKEN_LESS_OR_EQ, self.string_since(start)))
+            }
+            '<' => Some((TOKEN_LESS, self.string_since(start))),
+            '>' if self.peek() == Some('=') => {
+                self.next().unwrap();
+                Some((TOKEN_MORE_OR_EQ, self.string_since(start)))
+            }
+            '>' => Some((TOKEN_MORE, self.string_since(start))),
+            '$' if self.peek() == Some('{') => {
+                self.next().unwrap();
+                self.ctx.push(Context {
+                    interpol: Some(Interpol {
+                        brackets: 0,
+                        string: false,
+                        multiline: false,
+                        path: false,
+                        string_start: 0,
+                    }),
+                    ..Default::default()
+                });
+                Some((TOKEN_DYNAMIC_START, self.string_since(start)))
+            }
+            'a'..='z' | 'A'..='Z' | '_' => {
+                let kind = match kind {
+                    Some(IdentType::Store) | None => IdentType::Ident,
+                    Some(kind) => kind,
+                };
+                self.consume(|c| match c {
+                    'a'..='z' | 'A'..='Z' | '0'..='9' | '_' | '-' | '\'' => true,
+                    c => kind == IdentType::Uri && is_valid_uri_char(c),
+                });
+                let ident = self.string_since(start);
+                let syntax_kind = match kind {
+                    IdentType::Ident => match ident {
+                        "assert" => TOKEN_ASSERT,
+                        "else" => TOKEN_ELSE,
+                        "if" => TOKEN_IF,
+                        "in" => TOKEN_IN,
+                        "inherit" => TOKEN_INHERIT,
+                        "let" => TOKEN_LET,
+                        "rec" => TOKEN_REC,
+                        "then" => TOKEN_THEN,
+                        "with" => TOKEN_WITH,
+                        _ => TOKEN_IDENT,
+                    },
+                    IdentType::Path | IdentType::Store => TOKEN_PATH,
+                    IdentType::Uri => TOKEN_URI,
+                };
+                Some((syntax_kind, ident))
+            }
+            '"' => {
+                self.ctx.last_mut().unwrap().todo =
+                    Some(Todo::StringBody { multiline: false, start });
+                Some((TOKEN_STRING_START, self.string_since(start)))
+            }
+            '\'' if self.peek() == Some('\'') => {
+                self.next().unwrap();
+                self.ctx.last_mut().unwrap().todo =
+                    Some(Todo::StringBody { multiline: true, start });
+                Some((TOKEN_STRING_START, self.string_since(start)))
+            }
+            '0'..='9' => {
+                self.consume(|c| c.is_ascii_digit());
+                let kind = if self.peek() == Some('.') {
+                    self.next().unwrap();
+                    self.consume(|c| c.is_ascii_digit());
+                    TOKEN_FLOAT
+                } else {
+                    TOKEN_INTEGER
+                };
+                Some(self.number(start, kind))
+            }
+            _ => Some(self.error(start, LexError::UnexpectedChar)),
+        }
+    }
+}
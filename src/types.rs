@@ -1,7 +1,9 @@
 //! Provides a type system for the AST, in some sense
-use std::fmt;
+use std::{convert::TryFrom, fmt};
 
 use crate::{
+    comments::{self, Comments},
+    edit,
     value::{self, StrPart, Value as ParsedValue, ValueError},
     NodeOrToken, SyntaxElement,
     SyntaxKind::{self, *},
@@ -31,6 +33,54 @@ macro_rules! typed {
         )*
     }
 }
+macro_rules! typed_enum {
+    ($($(#[$meta:meta])* $name:ident { $($kind:ident => $variant:ident),* }),*) => {
+        $(
+            $(#[$meta])*
+            #[derive(Clone)]
+            pub enum $name {
+                $($variant($variant)),*
+            }
+
+            impl $name {
+                /// Return the inner node if it is a `T`
+                pub fn downcast<T: TryFrom<Self>>(self) -> Option<T> {
+                    T::try_from(self).ok()
+                }
+            }
+            impl TypedNode for $name {
+                fn cast(from: SyntaxNode) -> Option<Self> {
+                    match from.kind() {
+                        $($kind => Some($name::$variant($variant(from))),)*
+                        _ => None,
+                    }
+                }
+                fn node(&self) -> &SyntaxNode {
+                    match self {
+                        $($name::$variant(inner) => inner.node(),)*
+                    }
+                }
+            }
+            $(
+                impl From<$variant> for $name {
+                    fn from(from: $variant) -> Self {
+                        $name::$variant(from)
+                    }
+                }
+                impl TryFrom<$name> for $variant {
+                    type Error = $name;
+
+                    fn try_from(from: $name) -> Result<Self, $name> {
+                        match from {
+                            $name::$variant(inner) => Ok(inner),
+                            other => Err(other),
+                        }
+                    }
+                }
+            )*
+        )*
+    }
+}
 macro_rules! nth {
     ($self:expr; $index:expr) => {
         $self.node().children()
@@ -61,6 +111,9 @@ pub enum BinOpKind {
     MoreOrEq,
     NotEqual,
     Or,
+
+    PipeRight,
+    PipeLeft,
 }
 impl BinOpKind {
     /// Get the operation kind from a token in the AST
@@ -85,9 +138,38 @@ impl BinOpKind {
             TOKEN_NOT_EQUAL => Some(BinOpKind::NotEqual),
             TOKEN_OR => Some(BinOpKind::Or),
 
+            TOKEN_PIPE_RIGHT => Some(BinOpKind::PipeRight),
+            TOKEN_PIPE_LEFT => Some(BinOpKind::PipeLeft),
+
             _ => None,
         }
     }
+    /// Get the token for this operation kind
+    pub fn to_token(self) -> SyntaxKind {
+        match self {
+            BinOpKind::Concat => TOKEN_CONCAT,
+            BinOpKind::IsSet => TOKEN_QUESTION,
+            BinOpKind::Update => TOKEN_UPDATE,
+
+            BinOpKind::Add => TOKEN_ADD,
+            BinOpKind::Sub => TOKEN_SUB,
+            BinOpKind::Mul => TOKEN_MUL,
+            BinOpKind::Div => TOKEN_DIV,
+
+            BinOpKind::And => TOKEN_AND,
+            BinOpKind::Equal => TOKEN_EQUAL,
+            BinOpKind::Implication => TOKEN_IMPLICATION,
+            BinOpKind::Less => TOKEN_LESS,
+            BinOpKind::LessOrEq => TOKEN_LESS_OR_EQ,
+            BinOpKind::More => TOKEN_MORE,
+            BinOpKind::MoreOrEq => TOKEN_MORE_OR_EQ,
+            BinOpKind::NotEqual => TOKEN_NOT_EQUAL,
+            BinOpKind::Or => TOKEN_OR,
+
+            BinOpKind::PipeRight => TOKEN_PIPE_RIGHT,
+            BinOpKind::PipeLeft => TOKEN_PIPE_LEFT,
+        }
+    }
 }
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
@@ -175,18 +257,39 @@ pub trait TypedNode: Clone {
     fn first_token(&self) -> Option<SyntaxToken> {
         tokens(self.node()).next()
     }
+    /// Return the comments belonging to this node, see `comments`
+    fn comments(&self) -> Comments {
+        comments::comments(self.node())
+    }
     /// Return a dump of the AST. One of the goals is to be a stable
     /// format that can be used in tests.
     fn dump(&self) -> TextDump {
         TextDump(self.node().clone())
     }
+    /// Returns a clone of the tree root but with this node replaced. The
+    /// replacement is wrapped in parenthesis if needed to keep the meaning
+    /// { a = x; }.value replaced with x: x is { a = x: x; }
+    /// [ a ].items[0] replaced with x: x is [ (x: x) ]
+    /// Returns None if the replacement can't go in this place, such as a set
+    /// in place of a key or of the identifier of a lambda argument.
+    fn replace_with(&self, replacement: &SyntaxNode) -> Option<Root> {
+        edit::replace(self.node(), replacement)
+    }
+    /// Returns a clone of the tree root but without this node, or None if
+    /// it can't be removed from its parent. Only entries, inherits, list
+    /// items, pattern entries and inherited identifiers can be removed.
+    fn remove(&self) -> Option<Root> {
+        edit::remove(self.node())
+    }
 }
 
 pub trait TokenWrapper: TypedNode {
-    fn as_str(&self) -> &str {
+    /// Return the text of the wrapped token, or None if the node doesn't
+    /// start with one
+    fn as_str(&self) -> Option<&str> {
         match &self.node().green().children().next() {
-            Some(rowan::NodeOrToken::Token(token)) => token.text().as_str(),
-            _ => unreachable!(),
+            Some(rowan::NodeOrToken::Token(token)) => Some(token.text().as_str()),
+            _ => None,
         }
     }
 }
@@ -201,15 +304,71 @@ pub trait EntryHolder: TypedNode {
     fn inherits(&self) -> Box<dyn Iterator<Item = Inherit>> {
         Box::new(self.node().children().filter_map(Inherit::cast))
     }
+    /// Return an iterator over all key=value entries and inherits, in order
+    fn bindings(&self) -> Box<dyn Iterator<Item = Binding>> {
+        Box::new(self.node().children().filter_map(Binding::cast))
+    }
+    /// Returns a clone of the tree root but with an entry inserted so it
+    /// becomes the nth entry or inherit
+    fn insert_entry(&self, index: usize, entry: &KeyValue) -> Root {
+        edit::insert_entry(self.node(), index, entry.node())
+    }
+    /// Returns a clone of the tree root but with an entry added at the end
+    fn push_entry(&self, entry: &KeyValue) -> Root {
+        self.insert_entry(usize::MAX, entry)
+    }
 }
 /// Provides the function `.inner()` for wrapping types like parenthensis
 pub trait Wrapper: TypedNode {
     /// Return the inner value
-    fn inner(&self) -> Option<SyntaxNode> {
-        nth!(self; 0)
+    fn inner(&self) -> Option<Expr> {
+        nth!(self; (Expr) 0)
     }
 }
 
+typed_enum! [
+    /// Any node that can be used as a value. Typed accessors return this
+    /// where an expression goes.
+    Expr {
+        NODE_APPLY => Apply,
+        NODE_ASSERT => Assert,
+        NODE_ATTR_SET => AttrSet,
+        NODE_BIN_OP => BinOp,
+        NODE_ERROR => Error,
+        NODE_IDENT => Ident,
+        NODE_IF_ELSE => IfElse,
+        NODE_LAMBDA => Lambda,
+        NODE_LEGACY_LET => LegacyLet,
+        NODE_LET_IN => LetIn,
+        NODE_LIST => List,
+        NODE_OR_DEFAULT => OrDefault,
+        NODE_PAREN => Paren,
+        NODE_PATH_WITH_INTERPOL => Path,
+        NODE_SELECT => Select,
+        NODE_STRING => Str,
+        NODE_UNARY_OP => UnaryOp,
+        NODE_LITERAL => Value,
+        NODE_WITH => With
+    },
+    /// One part of an attribute path, like `a`, `"b"` or `${c}` in `a."b".${c}`
+    KeyPart {
+        NODE_IDENT => Ident,
+        NODE_STRING => Str,
+        NODE_DYNAMIC => Dynamic,
+        NODE_ERROR => Error
+    },
+    /// The argument of a lambda, either `x:` or `{ x }:`
+    Param {
+        NODE_IDENT => Ident,
+        NODE_PATTERN => Pattern
+    },
+    /// An entry in a set or `let`, either `a = b;` or `inherit a;`
+    Binding {
+        NODE_KEY_VALUE => KeyValue,
+        NODE_INHERIT => Inherit
+    }
+];
+
 pub struct ParsedTypeError(pub SyntaxKind);
 
 pub enum ParsedType {
@@ -237,6 +396,7 @@ pub enum ParsedType {
     AttrSet(AttrSet),
     KeyValue(KeyValue),
     Str(Str),
+    Path(Path),
     UnaryOp(UnaryOp),
     Value(Value),
     With(With),
@@ -258,6 +418,7 @@ impl core::convert::TryFrom<SyntaxNode> for ParsedType {
             NODE_INHERIT => Ok(ParsedType::Inherit(Inherit::cast(node).unwrap())),
             NODE_INHERIT_FROM => Ok(ParsedType::InheritFrom(InheritFrom::cast(node).unwrap())),
             NODE_STRING => Ok(ParsedType::Str(Str::cast(node).unwrap())),
+            NODE_PATH_WITH_INTERPOL => Ok(ParsedType::Path(Path::cast(node).unwrap())),
             NODE_LAMBDA => Ok(ParsedType::Lambda(Lambda::cast(node).unwrap())),
             NODE_LEGACY_LET => Ok(ParsedType::LegacyLet(LegacyLet::cast(node).unwrap())),
             NODE_LET_IN => Ok(ParsedType::LetIn(LetIn::cast(node).unwrap())),
@@ -285,60 +446,61 @@ typed! [
     NODE_LITERAL => Value: TokenWrapper: {
         /// Parse the value
         pub fn to_value(&self) -> Result<ParsedValue, ValueError> {
-            ParsedValue::from_token(self.first_token().expect("invalid ast").kind(), self.as_str())
+            let token = self.first_token().ok_or(ValueError::Unknown)?;
+            ParsedValue::from_token(token.kind(), token.text())
         }
     },
 
     NODE_APPLY => Apply: {
         /// Return the lambda being applied
-        pub fn lambda(&self) -> Option<SyntaxNode> {
-            nth!(self; 0)
+        pub fn lambda(&self) -> Option<Expr> {
+            nth!(self; (Expr) 0)
         }
         /// Return the value which the lambda is being applied with
-        pub fn value(&self) -> Option<SyntaxNode> {
-            nth!(self; 1)
+        pub fn value(&self) -> Option<Expr> {
+            nth!(self; (Expr) 1)
         }
     },
     NODE_ASSERT => Assert: {
         /// Return the assert condition
-        pub fn condition(&self) -> Option<SyntaxNode> {
-            nth!(self; 0)
+        pub fn condition(&self) -> Option<Expr> {
+            nth!(self; (Expr) 0)
         }
         /// Return the success body
-        pub fn body(&self) -> Option<SyntaxNode> {
-            nth!(self; 1)
+        pub fn body(&self) -> Option<Expr> {
+            nth!(self; (Expr) 1)
         }
     },
     NODE_KEY => Key: {
         /// Return the path as an iterator of identifiers
-        pub fn path<'a>(&'a self) -> impl Iterator<Item = SyntaxNode> + 'a {
-            self.node().children()
+        pub fn path(&self) -> impl Iterator<Item = KeyPart> {
+            self.node().children().filter_map(KeyPart::cast)
         }
     },
     NODE_DYNAMIC => Dynamic: Wrapper,
     NODE_ERROR => Error,
     NODE_IF_ELSE => IfElse: {
         /// Return the condition
-        pub fn condition(&self) -> Option<SyntaxNode> {
-            nth!(self; 0)
+        pub fn condition(&self) -> Option<Expr> {
+            nth!(self; (Expr) 0)
         }
         /// Return the success body
-        pub fn body(&self) -> Option<SyntaxNode> {
-            nth!(self; 1)
+        pub fn body(&self) -> Option<Expr> {
+            nth!(self; (Expr) 1)
         }
         /// Return the else body
-        pub fn else_body(&self) -> Option<SyntaxNode> {
-            nth!(self; 2)
+        pub fn else_body(&self) -> Option<Expr> {
+            nth!(self; (Expr) 2)
         }
     },
     NODE_SELECT => Select: {
         /// Return the set being indexed
-        pub fn set(&self) -> Option<SyntaxNode> {
-            nth!(self; 0)
+        pub fn set(&self) -> Option<Expr> {
+            nth!(self; (Expr) 0)
         }
         /// Return the index
-        pub fn index(&self) -> Option<SyntaxNode> {
-            nth!(self; 1)
+        pub fn index(&self) -> Option<KeyPart> {
+            nth!(self; (KeyPart) 1)
         }
     },
     NODE_INHERIT => Inherit: {
@@ -359,41 +521,62 @@ typed! [
             value::string_parts(self)
         }
     },
+    NODE_PATH_WITH_INTERPOL => Path: {
+        /// Return the path fragments and interpolations. Plain paths without
+        /// interpolation are not parsed into this node, but into a `Value`.
+        pub fn parts(&self) -> Vec<StrPart> {
+            value::path_parts(self)
+        }
+    },
     NODE_LAMBDA => Lambda: {
         /// Return the argument of the lambda
-        pub fn arg(&self) -> Option<SyntaxNode> {
-            nth!(self; 0)
+        pub fn arg(&self) -> Option<Param> {
+            nth!(self; (Param) 0)
         }
         /// Return the body of the lambda
-        pub fn body(&self) -> Option<SyntaxNode> {
-            nth!(self; 1)
+        pub fn body(&self) -> Option<Expr> {
+            nth!(self; (Expr) 1)
         }
     },
     NODE_LEGACY_LET => LegacyLet: EntryHolder,
     NODE_LET_IN => LetIn: EntryHolder: {
         /// Return the body
-        pub fn body(&self) -> Option<SyntaxNode> {
-            self.node().last_child()
+        pub fn body(&self) -> Option<Expr> {
+            self.node().last_child().and_then(Expr::cast)
         }
     },
     NODE_LIST => List: {
         /// Return an iterator over items in the list
-        pub fn items(&self) -> impl Iterator<Item = SyntaxNode> {
-            self.node().children()
+        pub fn items(&self) -> impl Iterator<Item = Expr> {
+            self.node().children().filter_map(Expr::cast)
+        }
+        /// Returns a clone of the tree root but with an item inserted so it
+        /// becomes the nth item, or None if the item isn't an expression
+        pub fn insert_item(&self, index: usize, item: &SyntaxNode) -> Option<Root> {
+            edit::insert_item(self.node(), index, item)
+        }
+        /// Returns a clone of the tree root but with an item added at the end
+        pub fn push_item(&self, item: &SyntaxNode) -> Option<Root> {
+            self.insert_item(usize::MAX, item)
         }
     },
     NODE_BIN_OP => BinOp: {
         /// Return the left hand side of the binary operation
-        pub fn lhs(&self) -> Option<SyntaxNode> {
-            nth!(self; 0)
+        pub fn lhs(&self) -> Option<Expr> {
+            nth!(self; (Expr) 0)
         }
         /// Return the operator
-        pub fn operator(&self) -> BinOpKind {
-            self.first_token().and_then(|t| BinOpKind::from_token(t.kind())).expect("invalid ast")
+        pub fn operator(&self) -> Option<BinOpKind> {
+            self.first_token().and_then(|t| BinOpKind::from_token(t.kind()))
+        }
+        /// Return the right hand side of the binary operation. For `?`
+        /// this is an attribute path instead, see `key`.
+        pub fn rhs(&self) -> Option<Expr> {
+            nth!(self; (Expr) 1)
         }
-        /// Return the right hand side of the binary operation
-        pub fn rhs(&self) -> Option<SyntaxNode> {
-            nth!(self; 1)
+        /// Return the attribute path checked by a `?` operation
+        pub fn key(&self) -> Option<Key> {
+            nth!(self; (Key) 1)
         }
     },
     NODE_OR_DEFAULT => OrDefault: {
@@ -402,8 +585,8 @@ typed! [
             nth!(self; (Select) 0)
         }
         /// Return the default value
-        pub fn default(&self) -> Option<SyntaxNode> {
-            nth!(self; 1)
+        pub fn default(&self) -> Option<Expr> {
+            nth!(self; (Expr) 1)
         }
     },
     NODE_PAREN => Paren: Wrapper,
@@ -419,8 +602,8 @@ typed! [
             nth!(self; (Ident) 0)
         }
         /// Return the default value, if any
-        pub fn default(&self) -> Option<SyntaxNode> {
-            self.node().children().nth(1)
+        pub fn default(&self) -> Option<Expr> {
+            nth!(self; (Expr) 1)
         }
     },
     NODE_PATTERN => Pattern: {
@@ -440,10 +623,14 @@ typed! [
         /// Returns a clone of the tree root but without entries where the
         /// callback function returns false
         /// { a, b, c } without b is { a, c }
-        pub fn filter_entries<F>(&self, _callback: F) -> Root
+        pub fn filter_entries<F>(&self, mut callback: F) -> Root
             where F: FnMut(&PatEntry) -> bool
         {
-            unimplemented!("TODO: filter_entries or better editing API")
+            edit::remove_children(
+                self.node(),
+                |child| child.as_node().cloned().and_then(PatEntry::cast).is_some_and(|e| !callback(&e)),
+                Some(TOKEN_COMMA),
+            )
         }
     },
     NODE_ROOT => Root: Wrapper,
@@ -455,10 +642,14 @@ typed! [
         /// Returns a clone of the tree root but without entries where the
         /// callback function returns false
         /// { a = 2; b = 3; } without 0 is { b = 3; }
-        pub fn filter_entries<F>(&self, _callback: F) -> Root
+        pub fn filter_entries<F>(&self, mut callback: F) -> Root
             where F: FnMut(&KeyValue) -> bool
         {
-            unimplemented!("TODO: filter_entries or better editing API")
+            edit::remove_children(
+                self.node(),
+                |child| child.as_node().cloned().and_then(KeyValue::cast).is_some_and(|e| !callback(&e)),
+                None,
+            )
         }
     },
     NODE_KEY_VALUE => KeyValue: {
@@ -467,28 +658,215 @@ typed! [
             nth!(self; (Key) 0)
         }
         /// Return this entry's value
-        pub fn value(&self) -> Option<SyntaxNode> {
-            nth!(self; 1)
+        pub fn value(&self) -> Option<Expr> {
+            nth!(self; (Expr) 1)
         }
     },
     NODE_UNARY_OP => UnaryOp: {
         /// Return the operator
-        pub fn operator(&self) -> UnaryOpKind {
-            self.first_token().and_then(|t| UnaryOpKind::from_token(t.kind())).expect("invalid ast")
+        pub fn operator(&self) -> Option<UnaryOpKind> {
+            self.first_token().and_then(|t| UnaryOpKind::from_token(t.kind()))
         }
         /// Return the value in the operation
-        pub fn value(&self) -> Option<SyntaxNode> {
-            nth!(self; 0)
+        pub fn value(&self) -> Option<Expr> {
+            nth!(self; (Expr) 0)
         }
     },
     NODE_WITH => With: {
         /// Return the namespace
-        pub fn namespace(&self) -> Option<SyntaxNode> {
-            nth!(self; 0)
+        pub fn namespace(&self) -> Option<Expr> {
+            nth!(self; (Expr) 0)
         }
         /// Return the body
-        pub fn body(&self) -> Option<SyntaxNode> {
-            nth!(self; 1)
+        pub fn body(&self) -> Option<Expr> {
+            nth!(self; (Expr) 1)
         }
     }
 ];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{convert::TryFrom, ffi::OsStr, fs, path::Path as FsPath};
+
+    /// Call every accessor on every node
+    fn walk(root: &SyntaxNode) {
+        for node in root.descendants() {
+            let _ = (Expr::cast(node.clone()), KeyPart::cast(node.clone()));
+            let _ = (Param::cast(node.clone()), Binding::cast(node.clone()));
+            let parsed = match ParsedType::try_from(node) {
+                Ok(parsed) => parsed,
+                Err(_) => continue,
+            };
+            match parsed {
+                ParsedType::Apply(apply) => {
+                    let _ = (apply.lambda(), apply.value());
+                }
+                ParsedType::Assert(assert) => {
+                    let _ = (assert.condition(), assert.body());
+                }
+                ParsedType::Key(key) => {
+                    key.path().for_each(drop);
+                }
+                ParsedType::Dynamic(dynamic) => {
+                    dynamic.inner();
+                }
+                ParsedType::Error(error) => {
+                    error.errors();
+                }
+                ParsedType::Ident(ident) => {
+                    ident.as_str();
+                }
+                ParsedType::IfElse(if_else) => {
+                    let _ = (if_else.condition(), if_else.body(), if_else.else_body());
+                }
+                ParsedType::Select(select) => {
+                    let _ = (select.set(), select.index());
+                }
+                ParsedType::Inherit(inherit) => {
+                    inherit.from().and_then(|from| from.inner());
+                    inherit.idents().for_each(|ident| {
+                        ident.as_str();
+                    });
+                }
+                ParsedType::InheritFrom(from) => {
+                    from.inner();
+                }
+                ParsedType::Lambda(lambda) => {
+                    let _ = (lambda.arg(), lambda.body());
+                }
+                ParsedType::LegacyLet(let_) => {
+                    let_.bindings().for_each(drop);
+                }
+                ParsedType::LetIn(let_in) => {
+                    let_in.entries().for_each(drop);
+                    let_in.inherits().for_each(drop);
+                    let_in.body();
+                }
+                ParsedType::List(list) => {
+                    list.items().for_each(drop);
+                }
+                ParsedType::BinOp(op) => {
+                    let _ = (op.lhs(), op.operator(), op.rhs(), op.key());
+                }
+                ParsedType::OrDefault(or) => {
+                    let _ = (or.index(), or.default());
+                }
+                ParsedType::Paren(paren) => {
+                    paren.inner();
+                }
+                ParsedType::PatBind(bind) => {
+                    bind.name();
+                }
+                ParsedType::PatEntry(entry) => {
+                    let _ = (entry.name(), entry.default());
+                }
+                ParsedType::Pattern(pattern) => {
+                    let _ = (pattern.at(), pattern.ellipsis());
+                    pattern.entries().for_each(drop);
+                }
+                ParsedType::Root(root) => {
+                    root.inner();
+                }
+                ParsedType::AttrSet(set) => {
+                    set.recursive();
+                    set.bindings().for_each(drop);
+                }
+                ParsedType::KeyValue(entry) => {
+                    let _ = (entry.key(), entry.value());
+                }
+                ParsedType::Str(string) => {
+                    string.parts();
+                }
+                ParsedType::Path(path) => {
+                    path.parts();
+                }
+                ParsedType::UnaryOp(op) => {
+                    let _ = (op.operator(), op.value());
+                }
+                ParsedType::Value(value) => {
+                    let _ = (value.as_str(), value.to_value());
+                }
+                ParsedType::With(with) => {
+                    let _ = (with.namespace(), with.body());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn random_trees() {
+        use crate::{NixLanguage, SmolStr};
+        use rowan::{GreenNodeBuilder, Language};
+
+        // Trees the parser wouldn't make, with any kind of node or token
+        // anywhere
+        let mut seed: u64 = 0x9e37_79b9_7f4a_7c15;
+        let mut random = move |max: u64| {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            seed % max
+        };
+        for _ in 0..20_000 {
+            let mut builder = GreenNodeBuilder::new();
+            builder.start_node(NixLanguage::kind_to_raw(NODE_ROOT));
+            let mut depth = 0;
+            for _ in 0..random(12) {
+                let raw = rowan::SyntaxKind(random(__LAST as u64) as u16);
+                let kind = NixLanguage::kind_from_raw(raw);
+                match random(3) {
+                    0 if depth > 0 => {
+                        builder.finish_node();
+                        depth -= 1;
+                    }
+                    _ if kind.name().starts_with("TOKEN_") => {
+                        let text = ["x", "1", "''", "<", ""][random(5) as usize];
+                        builder.token(raw, SmolStr::new(text))
+                    }
+                    _ => {
+                        builder.start_node(raw);
+                        depth += 1;
+                    }
+                }
+            }
+            for _ in 0..depth {
+                builder.finish_node();
+            }
+            builder.finish_node();
+            walk(&SyntaxNode::new_root(builder.finish()));
+        }
+    }
+    /// Check that the accessors don't panic on broken versions of every
+    /// `step`th character of each file in the corpus
+    fn broken_trees(step: usize) {
+        fn visit(dir: &FsPath, step: usize) {
+            for entry in fs::read_dir(dir).unwrap() {
+                let path = entry.unwrap().path();
+                if path.is_dir() {
+                    visit(&path, step);
+                } else if path.extension() == Some(OsStr::new("nix")) {
+                    let code = fs::read_to_string(&path).unwrap();
+                    for (offset, c) in code.char_indices().step_by(step) {
+                        let end = offset + c.len_utf8();
+                        walk(&crate::parse(&code[..offset]).node());
+                        for insert in &["", "x", "}", "\"", "${", ":"] {
+                            let edited = [&code[..offset], insert, &code[end..]].concat();
+                            walk(&crate::parse(&edited).node());
+                        }
+                    }
+                }
+            }
+        }
+        visit(&FsPath::new(env!("CARGO_MANIFEST_DIR")).join("test_data"), step);
+    }
+    #[test]
+    fn broken_trees_sampled() {
+        broken_trees(7);
+    }
+    #[test]
+    #[ignore = "slow, run with --ignored after changing the typed accessors"]
+    fn broken_trees_exhaustive() {
+        broken_trees(1);
+    }
+}
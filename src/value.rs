@@ -92,7 +92,7 @@ pub(crate) fn indention<'a>(s: &'a str) -> impl Iterator<Item = char> + 'a {
 
 /// Remove common indention in string
 pub fn remove_common_indent(input: &str) -> String {
-    let mut common = std::usize::MAX;
+    let mut common = usize::MAX;
     for line in input.lines() {
         let indent = indention(line).count();
         if line.chars().count() == indent {
@@ -202,8 +202,8 @@ impl Value {
                         return Err(ValueError::StorePath);
                     }
                     Value::Path(Anchor::Store, String::from(&s[1..len - 1]))
-                } else if s.starts_with("~/") {
-                    Value::Path(Anchor::Home, String::from(&s[2..]))
+                } else if let Some(home) = s.strip_prefix("~/") {
+                    Value::Path(Anchor::Home, String::from(home))
                 } else if s.starts_with('/') {
                     Value::Path(Anchor::Absolute, String::from(s))
                 } else {
@@ -225,8 +225,8 @@ pub enum StrPart {
 pub(crate) fn string_parts(string: &types::Str) -> Vec<StrPart> {
     let mut parts = Vec::new();
     let mut literals = 0;
-    let mut common = std::usize::MAX;
-    let multiline = string.first_token().map_or(false, |t| t.text().as_str() == "''");
+    let mut common = usize::MAX;
+    let multiline = string.first_token().is_some_and(|t| t.text().as_str() == "''");
     let mut last_was_ast = false;
 
     for child in string.node().children_with_tokens() {
@@ -237,7 +237,7 @@ pub(crate) fn string_parts(string: &types::Str) -> Vec<StrPart> {
                 let line_count = text.lines().count();
                 let next_is_ast = child
                     .next_sibling_or_token()
-                    .map_or(false, |child| child.kind() == NODE_STRING_INTERPOL);
+                    .is_some_and(|child| child.kind() == NODE_STRING_INTERPOL);
                 for (i, line) in text.lines().enumerate().skip(if last_was_ast { 1 } else { 0 }) {
                     let indent: usize = indention(line).count();
                     if (i != line_count - 1 || !next_is_ast) && indent == line.chars().count() {
@@ -250,14 +250,12 @@ pub(crate) fn string_parts(string: &types::Str) -> Vec<StrPart> {
                 parts.push(StrPart::Literal(text.to_string()));
                 literals += 1;
             }
-            NodeOrToken::Token(token) => {
-                assert!(token.kind() == TOKEN_STRING_START || token.kind() == TOKEN_STRING_END)
-            }
-            NodeOrToken::Node(node) => {
-                assert_eq!(node.kind(), NODE_STRING_INTERPOL);
+            NodeOrToken::Node(node) if node.kind() == NODE_STRING_INTERPOL => {
                 parts.push(StrPart::Ast(node.clone()));
                 last_was_ast = true;
             }
+            // The quotes, or anything else in a broken tree
+            _ => (),
         }
     }
 
@@ -279,6 +277,21 @@ pub(crate) fn string_parts(string: &types::Str) -> Vec<StrPart> {
     parts
 }
 
+pub(crate) fn path_parts(path: &types::Path) -> Vec<StrPart> {
+    path.node()
+        .children_with_tokens()
+        .filter_map(|child| match child {
+            NodeOrToken::Token(token) if token.kind() == TOKEN_PATH => {
+                Some(StrPart::Literal(token.text().to_string()))
+            }
+            NodeOrToken::Node(node) if node.kind() == NODE_STRING_INTERPOL => {
+                Some(StrPart::Ast(node))
+            }
+            _ => None,
+        })
+        .collect()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -356,5 +369,10 @@ mod tests {
 
         assert_eq!(Value::from_token(TOKEN_INTEGER, "123"), Ok(Value::Integer(123)));
         assert_eq!(Value::from_token(TOKEN_FLOAT, "1.234"), Ok(Value::Float(1.234)));
+        assert_eq!(Value::from_token(TOKEN_FLOAT, ".5"), Ok(Value::Float(0.5)));
+        assert_eq!(Value::from_token(TOKEN_FLOAT, "1."), Ok(Value::Float(1.0)));
+        assert_eq!(Value::from_token(TOKEN_FLOAT, "00.5"), Ok(Value::Float(0.5)));
+        assert_eq!(Value::from_token(TOKEN_FLOAT, "1e10"), Ok(Value::Float(1e10)));
+        assert_eq!(Value::from_token(TOKEN_FLOAT, "1E+3"), Ok(Value::Float(1000.0)));
     }
 }
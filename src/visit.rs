@@ -0,0 +1,206 @@
+//! Walk over or rewrite a tree with one method per kind of typed node
+
+use std::convert::TryFrom;
+
+use rowan::{GreenNode, Language};
+
+use crate::{edit::green, types::*, NixLanguage, NodeOrToken, SyntaxNode};
+
+/// Returns true if `node` is nested deeper than `max_depth` in its tree
+fn too_deep(node: &SyntaxNode, max_depth: Option<usize>) -> bool {
+    max_depth.is_some_and(|max| node.ancestors().nth(max + 1).is_some())
+}
+
+/// Visit every child node of `node`. This is what the `visit_*` methods of a
+/// `Visitor` do unless overridden, so call it to keep walking from one.
+pub fn walk<V: Visitor + ?Sized>(visitor: &mut V, node: &SyntaxNode) {
+    for child in node.children() {
+        visitor.visit(&child);
+    }
+}
+/// Fold every child node of `node` and return a copy of it with the results.
+/// This is what the `fold_*` methods of a `Fold` do unless overridden.
+pub fn fold_children<F: Fold + ?Sized>(folder: &mut F, node: &SyntaxNode) -> GreenNode {
+    let children: Vec<_> = node
+        .children_with_tokens()
+        .map(|child| match child {
+            NodeOrToken::Node(child) => NodeOrToken::Node(folder.fold(&child)),
+            token => green(&token),
+        })
+        .collect();
+    GreenNode::new(NixLanguage::kind_to_raw(node.kind()), children)
+}
+
+macro_rules! traits {
+    ($($variant:ident => $visit:ident, $fold:ident;)*) => {
+        /// Walks a tree, calling the `visit_*` method for each typed node it
+        /// finds. By default each method walks into the children of the node,
+        /// so override the ones you need and call `walk` from them to keep
+        /// going. Nodes without a type, such as string interpolations, are
+        /// walked through.
+        pub trait Visitor {
+            /// How deep in the tree to go, where the root is at depth 0.
+            /// Deeper nodes are skipped. There is no limit by default.
+            fn max_depth(&self) -> Option<usize> {
+                None
+            }
+            /// Call the `visit_*` method for the type of `node`
+            fn visit(&mut self, node: &SyntaxNode) {
+                if too_deep(node, self.max_depth()) {
+                    return;
+                }
+                match ParsedType::try_from(node.clone()) {
+                    $(Ok(ParsedType::$variant(node)) => self.$visit(&node),)*
+                    Err(_) => walk(self, node),
+                }
+            }
+            $(
+                fn $visit(&mut self, node: &$variant) {
+                    walk(self, node.node())
+                }
+            )*
+        }
+
+        /// Rewrites a tree, calling the `fold_*` method for each typed node to
+        /// get the green node to put in its place. By default each method
+        /// keeps the node, with its children folded. Replacements are used as
+        /// they are, so build them with `make` to get any parenthesis needed
+        /// to keep their meaning.
+        pub trait Fold {
+            /// How deep in the tree to go, where the root is at depth 0.
+            /// Deeper nodes are kept as they are. There is no limit by default.
+            fn max_depth(&self) -> Option<usize> {
+                None
+            }
+            /// Call the `fold_*` method for the type of `node`
+            fn fold(&mut self, node: &SyntaxNode) -> GreenNode {
+                if too_deep(node, self.max_depth()) {
+                    return node.green().clone();
+                }
+                match ParsedType::try_from(node.clone()) {
+                    $(Ok(ParsedType::$variant(node)) => self.$fold(&node),)*
+                    Err(_) => fold_children(self, node),
+                }
+            }
+            /// Fold `node` and return a copy of the whole tree with the result
+            /// in its place
+            fn fold_tree(&mut self, node: &SyntaxNode) -> SyntaxNode {
+                let replacement = self.fold(node);
+                SyntaxNode::new_root(node.replace_with(replacement))
+            }
+            $(
+                fn $fold(&mut self, node: &$variant) -> GreenNode {
+                    fold_children(self, node.node())
+                }
+            )*
+        }
+    };
+}
+
+traits! {
+    Apply => visit_apply, fold_apply;
+    Assert => visit_assert, fold_assert;
+    AttrSet => visit_attr_set, fold_attr_set;
+    BinOp => visit_bin_op, fold_bin_op;
+    Dynamic => visit_dynamic, fold_dynamic;
+    Error => visit_error, fold_error;
+    Ident => visit_ident, fold_ident;
+    IfElse => visit_if_else, fold_if_else;
+    Inherit => visit_inherit, fold_inherit;
+    InheritFrom => visit_inherit_from, fold_inherit_from;
+    Key => visit_key, fold_key;
+    KeyValue => visit_key_value, fold_key_value;
+    Lambda => visit_lambda, fold_lambda;
+    LegacyLet => visit_legacy_let, fold_legacy_let;
+    LetIn => visit_let_in, fold_let_in;
+    List => visit_list, fold_list;
+    OrDefault => visit_or_default, fold_or_default;
+    Paren => visit_paren, fold_paren;
+    PatBind => visit_pat_bind, fold_pat_bind;
+    PatEntry => visit_pat_entry, fold_pat_entry;
+    Path => visit_path, fold_path;
+    Pattern => visit_pattern, fold_pattern;
+    Root => visit_root, fold_root;
+    Select => visit_select, fold_select;
+    Str => visit_str, fold_str;
+    UnaryOp => visit_unary_op, fold_unary_op;
+    Value => visit_value, fold_value;
+    With => visit_with, fold_with;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::make;
+
+    #[derive(Default)]
+    struct Idents {
+        names: Vec<String>,
+        max_depth: Option<usize>,
+    }
+    impl Visitor for Idents {
+        fn max_depth(&self) -> Option<usize> {
+            self.max_depth
+        }
+        fn visit_ident(&mut self, node: &Ident) {
+            self.names.extend(node.as_str().map(String::from));
+        }
+        // Skip over the bodies of lambdas
+        fn visit_lambda(&mut self, node: &Lambda) {
+            if let Some(arg) = node.arg() {
+                self.visit(arg.node());
+            }
+        }
+    }
+
+    #[test]
+    fn visitor() {
+        let ast = crate::parse("{ a = b; c = \"${d}\"; e = f: g; }.h or [ (i) ]");
+        let mut idents = Idents::default();
+        idents.visit(&ast.node());
+        assert_eq!(idents.names, ["a", "b", "c", "d", "e", "f", "h", "i"]);
+
+        // Nothing inside the set, the list or the parenthesis
+        let mut idents = Idents { max_depth: Some(3), ..Idents::default() };
+        idents.visit(&ast.node());
+        assert_eq!(idents.names, ["h"]);
+    }
+
+    struct Rename;
+    impl Fold for Rename {
+        fn fold_ident(&mut self, node: &Ident) -> GreenNode {
+            match node.as_str() {
+                Some("x") => make::ident("y").node().green().clone(),
+                _ => node.node().green().clone(),
+            }
+        }
+        // Wrap every list item in a function call
+        fn fold_list(&mut self, node: &List) -> GreenNode {
+            let items: Vec<_> = node
+                .items()
+                .map(|item| {
+                    let item = Expr::cast(SyntaxNode::new_root(self.fold(item.node()))).unwrap();
+                    make::apply(&make::ident("f").into(), &item)
+                })
+                .collect();
+            make::list(items).node().green().clone()
+        }
+    }
+
+    #[test]
+    fn fold() {
+        let ast = crate::parse("let x = 1; in { inherit x; z = [ x 2 ]; } # end");
+        let root = Rename.fold_tree(&ast.node());
+        assert_eq!(root.to_string(), "let y = 1; in { inherit y; z = [ (f y) (f 2) ]; } # end");
+
+        // Folding into a part of the tree gives a whole new tree
+        let set = ast.root().inner().and_then(Expr::downcast::<LetIn>).and_then(|l| l.body());
+        let root = Rename.fold_tree(set.unwrap().node());
+        assert_eq!(root.to_string(), "let x = 1; in { inherit y; z = [ (f y) (f 2) ]; } # end");
+
+        // Nothing changes without overrides
+        struct Keep;
+        impl Fold for Keep {}
+        assert_eq!(Keep.fold(&ast.node()), *ast.node().green());
+    }
+}
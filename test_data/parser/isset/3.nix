@@ -0,0 +1 @@
+x ? a.b.c || x ? "a b".${c} && x ? a ? b
@@ -0,0 +1,6 @@
+[
+  ./pkgs/${name}/default.nix
+  ~/${dir}
+  /nix/${store}${hash}-a
+  ./${"quoted"}.${ext} ./plain/path
+]
@@ -0,0 +1,6 @@
+[
+  ./fix-${v}.patch
+  ./a${b}
+  ~/x${y}
+  ./a/b${c}/d
+]